@@ -0,0 +1,418 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::time::SystemTime;
+
+use clap::{crate_version, App, AppSettings, Arg, ArgMatches};
+use reqwest::blocking::Client;
+use reqwest::StatusCode;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use dit::comm::{self, Stage};
+use dit::errors::Refusal;
+use dit::utils::{Operation, TlsConfig};
+
+use crate::store::Event;
+
+const DEFAULT_SERVER: &str = "http://localhost:8000";
+
+/// Project as listed by `/admin/projects`
+#[derive(Serialize, Deserialize)]
+pub struct ProjectSummary {
+    pub name: String,
+    pub pending: usize,
+    pub archived: usize,
+}
+
+/// Session of a project as listed by `/admin/sessions`, with the number of messages stored for
+/// each round
+#[derive(Serialize, Deserialize)]
+pub struct SessionReport {
+    pub session: String,
+    pub operation: Operation,
+    pub stage: Stage,
+    pub initiator: Option<String>,
+    pub parties: Vec<(u16, Option<String>)>,
+    pub messages: BTreeMap<String, usize>,
+    pub ended: Option<SystemTime>,
+    pub successor: Option<String>,
+}
+
+/// Round a message is stored under, which its key names after the sender and, for
+/// point-to-point messages, the recipient
+pub fn round_of(key: &str) -> &str {
+    let mut round = key;
+    for _ in 0..2 {
+        match round.split_once('-') {
+            Some((party, rest)) if party.parse::<u16>().is_ok() => round = rest,
+            _ => break,
+        }
+    }
+
+    round
+}
+
+fn build_admin() -> App<'static, 'static> {
+    let project = Arg::with_name("project")
+        .required(true)
+        .help("Name of the project");
+
+    App::new("dit-server admin")
+        .version(crate_version!())
+        .about("Inspect and manage the operations of a running dit-server")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(
+            Arg::with_name("server")
+                .long("server")
+                .takes_value(true)
+                .default_value(DEFAULT_SERVER)
+                .help("Address of the server"),
+        )
+        .arg(
+            Arg::with_name("token")
+                .long("token")
+                .takes_value(true)
+                .env("DIT_ADMIN_TOKEN")
+                .required(true)
+                .help("The admin_token set in the Rocket.toml of the server"),
+        )
+        .arg(
+            Arg::with_name("tls")
+                .long("tls")
+                .takes_value(true)
+                .help("TOML file with the keys of a [server.tls] table, to reach servers over HTTPS"),
+        )
+        .subcommand(App::new("projects").about("List the projects the server knows about"))
+        .subcommand(
+            App::new("sessions")
+                .about("List the sessions of a project, oldest first, with their parties and the messages of each round")
+                .arg(project.clone()),
+        )
+        .subcommand(
+            App::new("abort")
+                .about("Abort a stuck operation, and archive its session right away")
+                .arg(project.clone())
+                .arg(
+                    Arg::with_name("session")
+                        .required(true)
+                        .help("Session of the operation, as listed by `sessions`"),
+                ),
+        )
+        .subcommand(
+            App::new("purge")
+                .about("Drop the archived sessions of a project without waiting for them to expire")
+                .arg(project.clone())
+                .arg(
+                    Arg::with_name("older-than")
+                        .long("older-than")
+                        .takes_value(true)
+                        .help("Only drop the sessions archived at least this many seconds ago"),
+                ),
+        )
+        .subcommand(
+            App::new("export")
+                .about("Print the events making up the state of a project, one JSON object per line")
+                .arg(project),
+        )
+}
+
+/// Client for the admin API of the server at `address`
+struct AdminClient {
+    client: Client,
+    address: String,
+    token: String,
+}
+
+impl AdminClient {
+    fn request<T: Serialize, R: DeserializeOwned>(
+        &self,
+        path: &str,
+        body: T,
+    ) -> Result<R, Box<dyn Error>> {
+        let response = self
+            .client
+            .post(format!("{}/admin/{}", self.address, path))
+            .bearer_auth(&self.token)
+            .json(&body)
+            .send()?;
+
+        let status = response.status();
+        if status == StatusCode::FORBIDDEN {
+            return Err("The server did not accept the admin token".into());
+        }
+        if status.is_success() {
+            return Ok(response.json()?);
+        }
+
+        match response.json::<Refusal>() {
+            Ok(refusal) => Err(format!("The server refused the request: {}", refusal).into()),
+            Err(_) => Err(format!("The server answered with {}", status).into()),
+        }
+    }
+}
+
+/// Client for the server, set up like the one of participants by the TLS config at `path`,
+/// whose paths are resolved against the directory it is in
+fn http_client(path: Option<&Path>) -> Result<Client, Box<dyn Error>> {
+    let path = match path {
+        Some(path) => path,
+        None => return Ok(comm::http_client(None, Path::new("."))?),
+    };
+
+    let config: TlsConfig = toml::from_str(&fs::read_to_string(path)?)?;
+    let base = path.parent().unwrap_or_else(|| Path::new("."));
+    Ok(comm::http_client(Some(&config), base)?)
+}
+
+/// Run `dit-server admin` with the arguments following `admin`, returning the exit code
+pub fn run(args: Vec<String>) -> i32 {
+    let name = "dit-server admin".to_owned();
+    let matches = build_admin().get_matches_from(std::iter::once(name).chain(args));
+
+    match execute(&matches) {
+        Ok(()) => 0,
+        Err(err) => {
+            eprintln!("{}", err);
+            1
+        }
+    }
+}
+
+fn execute(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let admin = AdminClient {
+        client: http_client(matches.value_of("tls").map(Path::new))?,
+        address: matches
+            .value_of("server")
+            .unwrap()
+            .trim_end_matches('/')
+            .to_owned(),
+        token: matches.value_of("token").unwrap().to_owned(),
+    };
+
+    match matches.subcommand() {
+        ("projects", _) => {
+            let projects: Vec<ProjectSummary> = admin.request("projects", 0)?;
+            for project in projects {
+                println!(
+                    "{}\t{} pending, {} archived",
+                    project.name, project.pending, project.archived
+                );
+            }
+        }
+        ("sessions", Some(args)) => {
+            let sessions: Vec<SessionReport> =
+                admin.request("sessions", args.value_of("project").unwrap())?;
+            for session in sessions {
+                print_session(&session);
+            }
+        }
+        ("abort", Some(args)) => {
+            let body = (
+                args.value_of("project").unwrap(),
+                args.value_of("session").unwrap(),
+            );
+            admin.request::<_, ()>("abort", body)?;
+            println!("Aborted the operation");
+        }
+        ("purge", Some(args)) => {
+            let older_than: u64 = args.value_of("older-than").unwrap_or("0").parse()?;
+            let body = (args.value_of("project").unwrap(), older_than);
+            let purged: usize = admin.request("purge", body)?;
+            println!("Dropped {} archived sessions", purged);
+        }
+        ("export", Some(args)) => {
+            let events: Vec<Event> = admin.request("export", args.value_of("project").unwrap())?;
+            for event in events {
+                println!("{}", serde_json::to_string(&event)?);
+            }
+        }
+        _ => unreachable!("A subcommand is required"),
+    }
+
+    Ok(())
+}
+
+fn print_session(session: &SessionReport) {
+    println!("{} ({:?})", session.session, session.stage);
+    for line in session.operation.to_string().lines() {
+        println!("    {}", line);
+    }
+
+    if let Some(initiator) = &session.initiator {
+        println!("    started by {}", initiator);
+    }
+    if let Some(successor) = &session.successor {
+        println!("    followed by {}", successor);
+    }
+    if let Some(age) = session.ended.and_then(|ended| ended.elapsed().ok()) {
+        println!("    archived {} seconds ago", age.as_secs());
+    }
+
+    let parties = session
+        .parties
+        .iter()
+        .map(|(number, identity)| match identity {
+            Some(identity) => format!("{} ({})", number, identity),
+            None => number.to_string(),
+        })
+        .collect::<Vec<_>>();
+    if parties.is_empty() {
+        println!("    nobody signed up");
+    } else {
+        println!("    parties: {}", parties.join(", "));
+    }
+
+    for (round, count) in session.messages.iter() {
+        println!("    {}: {} messages", round, count);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::temp_dir;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Answer a single request with `status` and `body`, handing back the request it got
+    fn serve_once(status: &'static str, body: String) -> (String, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request = String::new();
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if let Some(value) = line.to_lowercase().strip_prefix("content-length:") {
+                    length = value.trim().parse().unwrap();
+                }
+                request.push_str(&line);
+                if line == "\r\n" {
+                    break;
+                }
+            }
+            let mut content = vec![0; length];
+            reader.read_exact(&mut content).unwrap();
+            request.push_str(&String::from_utf8(content).unwrap());
+
+            let response = format!(
+                "HTTP/1.1 {}\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+            (&stream).write_all(response.as_bytes()).unwrap();
+            request
+        });
+
+        (address, server)
+    }
+
+    fn admin_client(address: String) -> AdminClient {
+        AdminClient {
+            client: http_client(None).unwrap(),
+            address,
+            token: "secret".to_owned(),
+        }
+    }
+
+    #[test]
+    fn rounds_drop_sender_and_recipient() {
+        assert_eq!(round_of("1-keygen-round1"), "keygen-round1");
+        assert_eq!(round_of("2-3-keygen-round3"), "keygen-round3");
+        assert_eq!(round_of("4-5-6-round"), "6-round");
+        assert_eq!(round_of("sign-round0"), "sign-round0");
+    }
+
+    #[test]
+    fn parses_the_subcommands() {
+        let matches = build_admin()
+            .get_matches_from_safe(vec![
+                "admin",
+                "--token",
+                "secret",
+                "purge",
+                "sample",
+                "--older-than",
+                "60",
+            ])
+            .unwrap();
+        assert_eq!(matches.value_of("server"), Some(DEFAULT_SERVER));
+        let (name, args) = matches.subcommand();
+        assert_eq!(name, "purge");
+        assert_eq!(args.unwrap().value_of("older-than"), Some("60"));
+
+        // Aborting needs the session
+        let missing = vec!["admin", "--token", "secret", "abort", "sample"];
+        assert!(build_admin().get_matches_from_safe(missing).is_err());
+    }
+
+    #[test]
+    fn reads_the_tls_config() {
+        let dir = temp_dir("admin-tls");
+        let config = dir.join("tls.toml");
+
+        fs::write(&config, "").unwrap();
+        assert!(http_client(Some(&config)).is_ok());
+
+        // Paths are resolved against the directory of the config
+        fs::write(&config, "ca_bundle = \"bundle.pem\"\n").unwrap();
+        assert!(http_client(Some(&config)).is_err());
+        fs::write(dir.join("bundle.pem"), "").unwrap();
+        assert!(http_client(Some(&config)).is_ok());
+
+        fs::write(&config, "client_key = \"key.pem\"\n").unwrap();
+        assert!(http_client(Some(&config)).is_err());
+
+        fs::write(&config, "ca_bundle = [\"not\", \"a\", \"path\"]\n").unwrap();
+        assert!(http_client(Some(&config)).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn sends_the_token() {
+        let (address, server) = serve_once("200 OK", "3".to_owned());
+        let purged: usize = admin_client(address)
+            .request("purge", ("sample", 0))
+            .unwrap();
+        assert_eq!(purged, 3);
+
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /admin/purge "), "{}", request);
+        assert!(request
+            .to_lowercase()
+            .contains("authorization: bearer secret"));
+        assert!(request.ends_with("[\"sample\",0]"), "{}", request);
+    }
+
+    #[test]
+    fn reports_refusals() {
+        let body = serde_json::to_string(&Refusal::UnknownSession).unwrap();
+        let (address, server) = serve_once("404 Not Found", body);
+        let err = admin_client(address)
+            .request::<_, ()>("abort", ("sample", "session"))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "The server refused the request: {}",
+                Refusal::UnknownSession
+            )
+        );
+        server.join().unwrap();
+
+        let (address, server) = serve_once("403 Forbidden", String::new());
+        let err = admin_client(address)
+            .request::<_, ()>("projects", 0)
+            .unwrap_err();
+        assert_eq!(err.to_string(), "The server did not accept the admin token");
+        server.join().unwrap();
+    }
+}
@@ -1,20 +1,30 @@
-use clap::{crate_version, App, AppSettings, Arg, ArgMatches};
+use clap::{crate_version, value_t, App, AppSettings, Arg, ArgGroup, ArgMatches};
 use std::fs;
-use std::path::Path;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 // TODO Get rid of this -> maybe some sort of notification for the state change?
 use std::thread::sleep;
 
-use crate::comm::HTTPChannel;
+use crate::comm::directory::DirectoryChannel;
+use crate::comm::offline::{OfflineChannel, RoundFile, DEFAULT_CHUNK_SIZE};
+use crate::comm::relay;
+use crate::comm;
+use crate::comm::Channel;
 use crate::comm::PartyKeyPair;
 use crate::config as cfg;
 use crate::config;
 use crate::dkg;
-use crate::errors::Result;
+use crate::errors::{CriticalError, ProtocolError, Result, UserError};
 use crate::git;
+use crate::hooks;
+use crate::identity::Identity;
 use crate::pgp::*;
+use crate::provenance;
+use crate::provenance::{Envelope, Statement};
 use crate::signing;
+use crate::transparency::{self, EntryKind, ProofRequest, SignedTreeHead};
 use crate::utils;
 use crate::utils::{Config, Operation};
 
@@ -22,6 +32,13 @@ use curv::arithmetic::Converter;
 use curv::elliptic::curves::traits::*;
 use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2020::party_i::SignatureRecid;
 
+const DEFAULT_BUILDER: &str = "https://github.com/luzap/dit";
+const DEFAULT_PROVENANCE: &str = "provenance.intoto.jsonl";
+/// Latest head of the transparency log of the server seen by `dit verify-tag`, kept in the
+/// config directory
+const TRANSPARENCY_FILE: &str = "transparency.json";
+const SIGNATURE_ARMOR: &str = "-----BEGIN PGP SIGNATURE-----";
+
 pub fn build_app() -> App<'static, 'static> {
     let app = App::new("dit")
         .version(crate_version!())
@@ -76,6 +93,164 @@ pub fn build_app() -> App<'static, 'static> {
                         .short("p")
                         .number_of_values(1)
                         .help("Sets the public key that will be used for the signing."),
+                )
+                .arg(
+                    Arg::with_name("offline")
+                        .long("offline")
+                        .help("Carries the rounds over with `dit round export` and `dit round import` instead of the configured channel"),
+                ),
+        )
+        .subcommand(
+            App::new("start-attestation")
+                .help("Start distributed signing of an in-toto provenance statement for release artifacts")
+                .arg(
+                    Arg::with_name("artifacts")
+                        .required(true)
+                        .multiple(true)
+                        .help("Sets the artifacts that are the subject of the statement"),
+                )
+                .arg(
+                    Arg::with_name("commit")
+                        .short("c")
+                        .number_of_values(1)
+                        .help("Sets the commit the artifacts were built from (defaults to current HEAD)"),
+                )
+                .arg(
+                    Arg::with_name("builder")
+                        .short("b")
+                        .number_of_values(1)
+                        .help("Sets the URI identifying the builder that produced the artifacts"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .number_of_values(1)
+                        .help("Sets the file the signed DSSE envelope will be written to"),
+                )
+                .arg(
+                    Arg::with_name("pubkey")
+                        .short("p")
+                        .number_of_values(1)
+                        .help("Sets the public key that will be used for the signing."),
+                )
+                .arg(
+                    Arg::with_name("offline")
+                        .long("offline")
+                        .help("Carries the rounds over with `dit round export` and `dit round import` instead of the configured channel"),
+                ),
+        )
+        .subcommand(
+            App::new("install-hooks")
+                .help("Install git hooks that refuse release tags not signed by the project key")
+                .arg(
+                    Arg::with_name("pattern")
+                        .short("r")
+                        .number_of_values(1)
+                        .help("Sets the shell pattern release tags are matched against (defaults to `v*`)"),
+                )
+                .arg(
+                    Arg::with_name("bare")
+                        .long("bare")
+                        .number_of_values(1)
+                        .help("Installs a pre-receive hook into the bare (mirror) repository at the given path"),
+                )
+                .arg(
+                    Arg::with_name("force")
+                        .short("f")
+                        .help("Overwrites existing hooks that were not installed by dit"),
+                ),
+        )
+        .subcommand(
+            App::new("pending")
+                .help("Print the pending operation, if there is one, without participating"),
+        )
+        .subcommand(
+            App::new("identity")
+                .help("Print the public key this participant signs protocol messages with, generating it on first use"),
+        )
+        .subcommand(
+            App::new("publish-key")
+                .help("Publish the project key, or certifications and revocations of it, to the keyserver of dit-server")
+                .arg(
+                    Arg::with_name("files")
+                        .multiple(true)
+                        .help("Sets the binary or armored OpenPGP files to publish (defaults to the generated keyfile)"),
+                ),
+        )
+        .subcommand(
+            App::new("relay")
+                .help("Keep the messages of all participants in a directory, and serve them to the `relay` and `socket` channels")
+                .arg(
+                    Arg::with_name("path")
+                        .long("path")
+                        .number_of_values(1)
+                        .required(true)
+                        .help("Sets the directory the messages are kept in"),
+                )
+                .arg(
+                    Arg::with_name("stdio")
+                        .long("stdio")
+                        .help("Serves a single participant over stdin and stdout, as when started through ssh"),
+                )
+                .arg(
+                    Arg::with_name("socket")
+                        .long("socket")
+                        .number_of_values(1)
+                        .help("Serves every participant connecting to the Unix socket at the given path"),
+                )
+                .group(
+                    ArgGroup::with_name("transport")
+                        .args(&["stdio", "socket"])
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            App::new("round")
+                .help("Move the rounds of offline operations to and from air-gapped participants")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    App::new("export")
+                        .help("Write the messages of the offline operation to a file")
+                        .arg(
+                            Arg::with_name("output")
+                                .short("o")
+                                .number_of_values(1)
+                                .help("Sets the file the round is written to (defaults to `round.json`, or `round.txt` with --chunks)"),
+                        )
+                        .arg(
+                            Arg::with_name("chunks")
+                                .long("chunks")
+                                .help("Writes the round as lines small enough to be shown as QR codes"),
+                        )
+                        .arg(
+                            Arg::with_name("chunk size")
+                                .long("chunk-size")
+                                .number_of_values(1)
+                                .requires("chunks")
+                                .help("Sets the maximum number of characters per line"),
+                        ),
+                )
+                .subcommand(
+                    App::new("import")
+                        .help("Read the messages of other participants and advance the offline operation")
+                        .arg(
+                            Arg::with_name("files")
+                                .required(true)
+                                .multiple(true)
+                                .help("Sets the exported rounds (or files of chunks) to read, `-` reading from stdin"),
+                        )
+                        .arg(
+                            Arg::with_name("index")
+                                .long("index")
+                                .number_of_values(1)
+                                .help("Sets the party number to sign up under, instead of the lowest one not seen yet"),
+                        )
+                        .arg(
+                            Arg::with_name("output")
+                                .short("o")
+                                .number_of_values(1)
+                                .help("Sets the file the DSSE envelope is written to, when finishing an attestation"),
+                        ),
                 ),
         );
     app
@@ -87,12 +262,13 @@ pub fn build_app() -> App<'static, 'static> {
 /// # Warning
 /// Will fail on protocol, network and local file system errors
 ///
-fn keygen_stage<P: AsRef<Path>>(
-    channel: &HTTPChannel,
+fn keygen_stage<C: Channel + ?Sized, P: AsRef<Path>>(
+    channel: &C,
     keypair_file: P,
     config: &Config,
 ) -> Result<PartyKeyPair> {
-    let keypair = dkg::distributed_keygen(channel, config.participants, config.threshold).unwrap();
+    let keypair =
+        dkg::distributed_keygen(channel, config.participants.count(), config.threshold)?;
 
     fs::write(keypair_file, serde_json::to_string(&keypair)?)?;
 
@@ -105,16 +281,20 @@ fn keygen_stage<P: AsRef<Path>>(
 /// Note that the current way this is structured is quite inflexible: some number of the particants
 /// need to continue participating in the subsequent tag generation. In reality, this could either
 /// be used to do the "offline" computation for the tag portion of the protocol, and send
-/// legitimate PGP public keys to all parties (maybe the server should be a keyserver?)
+/// legitimate PGP public keys to all parties
+///
+/// Every participant that signed the key publishes it through the channel, when the channel is
+/// backed by a keyserver. Returns what was signed, if anything, for the leader to log once the
+/// operation is over
 ///
-fn keysign_stage<P: AsRef<Path>>(
-    channel: &HTTPChannel,
+fn keysign_stage<C: Channel + ?Sized, P: AsRef<Path>>(
+    channel: &C,
     op: &Operation,
     keypair: &PartyKeyPair,
     pgp_file: P,
     env: &crate::git::GitEnv,
     config: &Config,
-) -> Result<()> {
+) -> Result<Option<Vec<u8>>> {
     if let Operation::SignKey {
         participants: _,
         threshold: _,
@@ -144,25 +324,39 @@ fn keysign_stage<P: AsRef<Path>>(
             channel,
             &hashable,
             &keypair,
-            config.participants,
+            config.participants.count(),
             config.threshold,
         ) {
             Ok(sig) => sig,
-            Err(_) => {
+            Err(ProtocolError::Full) => {
                 println!("{}Did not participate in key signing! \n{}Make sure to sync repository before initiating tag signing", utils::DIT_LOG, utils::DIT_LOG);
-                return Ok(());
+                return Ok(None);
             }
+            Err(err) => return Err(err.into()),
         };
         let sig_data = encode_sig_data(signature);
         message.finalize_signature(hashed, keyid.clone(), sig_data);
 
         message.write_to_file(pgp_file)?;
         config::write_keyid(&env.git_dir, &keyid)?;
+
+        // The key is usable without the keyserver, so failing to publish it is not fatal
+        match channel.publish_key(&message.get_formatted_message()) {
+            Ok(published) if !published.is_empty() => {
+                println!("{}Published the key to the server", utils::DIT_LOG)
+            }
+            Ok(_) => {}
+            Err(err) => println!(
+                "{}Could not publish the key ({}), retry with `dit publish-key`",
+                utils::DIT_LOG, err
+            ),
+        }
+
+        Ok(Some(hashable))
     } else {
         println!("{}Started signing, with the operation:\n{}", utils::DIT_LOG, op);
+        Ok(None)
     }
-
-    Ok(())
 }
 
 /// Initiates the key generation operation and controls its subsequent control flow
@@ -170,13 +364,13 @@ fn keysign_stage<P: AsRef<Path>>(
 ///
 /// The leader is the only party that should be able to change the state of an
 /// existing operation
-pub fn leader_keygen(
-    channel: &HTTPChannel,
+pub fn leader_keygen<C: Channel + ?Sized>(
+    channel: &C,
     config: &Config,
     args: Option<&ArgMatches>,
     env: &crate::git::GitEnv,
 ) -> Result<()> {
-    let participants = config.participants;
+    let participants = config.participants.count();
     let threshold = config.threshold;
 
     let key_base_dir = Path::join(&env.git_dir, &cfg::CONFIG_DIR);
@@ -213,13 +407,14 @@ pub fn leader_keygen(
         email: user.email.clone(),
         epoch: utils::get_current_epoch()?.as_secs(),
     };
-    channel.start_operation(&op);
+    channel.start_operation(&op)?;
 
     println!("{}Generating key shares", utils::DIT_LOG);
     let keypair = keygen_stage(channel, keypair_file, config)?;
     println!("{}Generation successful.", utils::DIT_LOG);
     println!("{}Signing generated key", utils::DIT_LOG);
-    channel.end_operation(&op);
+    channel.end_operation(&op)?;
+    log_outcome(channel, EntryKind::KeyGen, &keypair.y_sum.pk_to_key_slice());
 
     let op = Operation::SignKey {
         participants,
@@ -229,19 +424,22 @@ pub fn leader_keygen(
         epoch: utils::get_current_epoch()?.as_secs(),
     };
 
-    channel.start_operation(&op);
+    channel.start_operation(&op)?;
 
-    keysign_stage(channel, &op, &keypair, pgp_keyfile, env, config)?;
+    let signed = keysign_stage(channel, &op, &keypair, pgp_keyfile, env, config)?;
     println!("{}Successfully signed key", utils::DIT_LOG);
 
-    channel.end_operation(&op);
-    channel.clear();
+    channel.end_operation(&op)?;
+    if let Some(signed) = signed {
+        log_outcome(channel, EntryKind::KeySignature, &signed);
+    }
+    channel.clear()?;
 
     Ok(())
 }
 
-pub fn participant_keygen(
-    channel: &HTTPChannel,
+pub fn participant_keygen<C: Channel + ?Sized>(
+    channel: &C,
     env: &crate::git::GitEnv,
     config: &Config,
 ) -> Result<()> {
@@ -260,15 +458,13 @@ pub fn participant_keygen(
     println!("{}Generation successful.", utils::DIT_LOG);
     println!("{}Signing generated key", utils::DIT_LOG);
 
+    let mut op = channel.get_current_operation()?;
     let new_op = loop {
-        let new_op = channel.get_current_operation();
-        if let Ok(op) = new_op {
-            if matches!(op, Operation::SignKey { .. }) {
-                break op;
-            } else {
-                // TODO How do we get rid of this?
-                sleep(Duration::from_millis(250));
-            }
+        match op {
+            Operation::SignKey { .. } => break op,
+            Operation::Blame { .. } => return Err(ProtocolError::Blame.into()),
+            _ if comm::deadline::is_cancelled() => return Err(ProtocolError::Cancelled.into()),
+            _ => op = channel.await_operation(&op)?,
         }
     };
 
@@ -278,8 +474,8 @@ pub fn participant_keygen(
     Ok(())
 }
 
-pub fn leader_tag(
-    channel: &HTTPChannel,
+pub fn leader_tag<C: Channel + ?Sized>(
+    channel: &C,
     config: &Config,
     args: Option<&ArgMatches>,
     env: &crate::git::GitEnv,
@@ -297,69 +493,85 @@ pub fn leader_tag(
             .join(&args.value_of("pubkey").unwrap_or("public_key.json"));
 
         let hash = git::get_commit_hash(commit)?;
-        let signing_time = utils::get_current_epoch()?;
         let user = get_user(config, env);
 
         let tag = utils::Tag {
             creator: user.username,
             email: user.email,
-            epoch: signing_time.as_secs(),
+            epoch: utils::get_current_epoch()?.as_secs(),
             timezone: git::get_current_timezone()?,
-            commit: hash.clone(),
+            commit: hash,
             name: tag_name.to_string(),
-            message: message.clone(),
+            message,
         };
 
-        let mut tag_string = git::create_tag_string(&tag);
-
         // TODO Get this from the config file or from the server: the server solution
         // would require for the server to have persistent memory, which is outside
         // of what we use right now, but the other variant might be vulnerable to
         // interference from a malicious developer trying to create the keys
         let op = Operation::SignTag {
-            participants: config.participants,
+            participants: config.participants.count(),
             threshold: config.threshold,
             tag,
         };
 
-        // TODO The way the current channel interface is structured seems to
-        // be somewhat problematic. Maybe it's best to create a channel as a
-        // data object and then pass it to functions that use type bounds on
-        // the interface?
-        channel.start_operation(&op);
-
-        let mut message = Message::new();
-        message.new_signature(signing_time);
-
-        let mut hashable = tag_string.as_bytes().to_vec();
-        hashable.append(&mut message.get_hashable());
-
-        let hash = message.get_sha256_hash(Some(tag_string.as_bytes().to_vec()));
-        let keyid = config::get_keyid(&env.git_dir)?;
-
-        let signature = tag_signing_stage(channel, &hashable, keyfile, config)?;
-        let sig_data = encode_sig_data(signature);
-        let hash = &hash[hash.len() - 2..];
-        message.finalize_signature(hash, keyid, sig_data);
-        let signature = message.get_formatted_message();
-        let armor = armor_binary_output(&signature);
-        tag_string.push_str(&armor);
+        channel.start_operation(&op)?;
 
-        git::create_git_tag(&tag_name, &tag_string, env)?;
+        let signed = finish_tag(channel, &op, keyfile, env, config)?;
 
-        channel.end_operation(&op);
+        channel.end_operation(&op)?;
+        log_outcome(channel, EntryKind::TagSignature, &signed);
 
         // TODO How do we get rid of all sleeps?
         sleep(Duration::from_millis(500));
 
-        channel.clear();
+        channel.clear()?;
     }
 
     Ok(())
 }
 
-fn tag_signing_stage<P: AsRef<Path>>(
-    channel: &HTTPChannel,
+/// Signs the tag of a `SignTag` operation and creates it in the local repository, returning
+/// what was signed. Everything that goes into the signature comes from the operation, so that
+/// an offline leader can finish the tag on a later invocation.
+fn finish_tag<C: Channel + ?Sized, P: AsRef<Path>>(
+    channel: &C,
+    op: &Operation,
+    keyfile: P,
+    env: &crate::git::GitEnv,
+    config: &Config,
+) -> Result<Vec<u8>> {
+    let tag = match op {
+        Operation::SignTag { tag, .. } => tag,
+        _ => unimplemented!("{} Unreachable error state!", utils::DIT_LOG),
+    };
+
+    let mut tag_string = git::create_tag_string(tag);
+
+    let mut message = Message::new();
+    message.new_signature(Duration::from_secs(tag.epoch));
+
+    let mut hashable = tag_string.as_bytes().to_vec();
+    hashable.append(&mut message.get_hashable());
+
+    let hash = message.get_sha256_hash(Some(tag_string.as_bytes().to_vec()));
+    let keyid = config::get_keyid(&env.git_dir)?;
+
+    let signature = tag_signing_stage(channel, &hashable, keyfile, config)?;
+    let sig_data = encode_sig_data(signature);
+    let hash = &hash[hash.len() - 2..];
+    message.finalize_signature(hash, keyid, sig_data);
+    let signature = message.get_formatted_message();
+    let armor = armor_binary_output(&signature);
+    tag_string.push_str(&armor);
+
+    git::create_git_tag(&tag.name, &tag_string, env)?;
+
+    Ok(hashable)
+}
+
+fn tag_signing_stage<C: Channel + ?Sized, P: AsRef<Path>>(
+    channel: &C,
     message: &[u8],
     keyfile: P,
     config: &Config,
@@ -369,14 +581,13 @@ fn tag_signing_stage<P: AsRef<Path>>(
         channel,
         message,
         &keypair,
-        config.participants,
+        config.participants.count(),
         config.threshold,
-    )
-    .unwrap())
+    )?)
 }
 
-pub fn participant_tag(
-    channel: &HTTPChannel,
+pub fn participant_tag<C: Channel + ?Sized>(
+    channel: &C,
     op: &Operation,
     env: &crate::git::GitEnv,
     config: &Config
@@ -403,6 +614,631 @@ pub fn participant_tag(
     Ok(())
 }
 
+/// Initiates the signing of an in-toto provenance statement covering the given artifacts
+/// and writes the resulting DSSE envelope to disk.
+pub fn leader_attest<C: Channel + ?Sized>(
+    channel: &C,
+    config: &Config,
+    args: Option<&ArgMatches>,
+    env: &crate::git::GitEnv,
+) -> Result<()> {
+    if let Some(args) = args {
+        let artifacts: Vec<&str> = args.values_of("artifacts").unwrap().collect();
+        let commit = git::get_commit_hash(args.value_of("commit").unwrap_or("HEAD"))?;
+        let builder = args.value_of("builder").unwrap_or(DEFAULT_BUILDER);
+        let output = args.value_of("output").unwrap_or(DEFAULT_PROVENANCE);
+
+        let keyfile = Path::join(&env.git_dir, cfg::CONFIG_DIR)
+            .join(&args.value_of("pubkey").unwrap_or("public_key.json"));
+
+        // Without a remote, the best we can do is point at the local repository
+        let source = match git::get_remote_url("origin") {
+            Ok(url) => format!("git+{}", url),
+            Err(_) => format!("git+file://{}", env.git_dir.display()),
+        };
+
+        let epoch = utils::get_current_epoch()?.as_secs();
+        let statement = Statement::new(
+            &artifacts,
+            source,
+            commit,
+            builder.to_owned(),
+            format!("{}-{}", config.project, epoch),
+            git::get_rfc3339_timestamp(epoch)?,
+        )?;
+
+        let op = Operation::SignStatement {
+            participants: config.participants.count(),
+            threshold: config.threshold,
+            statement,
+        };
+
+        channel.start_operation(&op)?;
+
+        let signed = finish_attestation(channel, &op, keyfile, output, env, config)?;
+
+        channel.end_operation(&op)?;
+        log_outcome(channel, EntryKind::Attestation, &signed);
+
+        // TODO How do we get rid of all sleeps?
+        sleep(Duration::from_millis(500));
+
+        channel.clear()?;
+    }
+
+    Ok(())
+}
+
+/// Signs the statement of a `SignStatement` operation and writes the DSSE envelope to `output`,
+/// returning what was signed
+fn finish_attestation<C: Channel + ?Sized, P: AsRef<Path>>(
+    channel: &C,
+    op: &Operation,
+    keyfile: P,
+    output: &str,
+    env: &crate::git::GitEnv,
+    config: &Config,
+) -> Result<Vec<u8>> {
+    let statement = match op {
+        Operation::SignStatement { statement, .. } => statement,
+        _ => unimplemented!("{} Unreachable error state!", utils::DIT_LOG),
+    };
+
+    let hashable = statement.get_hashable()?;
+    let keyid = config::get_keyid(&env.git_dir)?;
+
+    let signature = tag_signing_stage(channel, &hashable, keyfile, config)?;
+    let envelope = Envelope::new(statement, &keyid, encode_sig_data(signature))?;
+    envelope.write_to_file(output)?;
+
+    Ok(hashable)
+}
+
+pub fn participant_attest<C: Channel + ?Sized>(
+    channel: &C,
+    op: &Operation,
+    env: &crate::git::GitEnv,
+    config: &Config,
+) -> Result<()> {
+    let statement = match op {
+        Operation::SignStatement { statement, .. } => statement,
+        _ => unimplemented!("{} Unreachable error state!", utils::DIT_LOG),
+    };
+
+    // Participants that have a local copy of an artifact get a chance to notice that
+    // what they are about to vouch for is not what they have built themselves
+    for subject in statement.subject.iter() {
+        if let Ok(contents) = fs::read(&subject.name) {
+            let digest = provenance::sha256_hex(&contents);
+            if subject.digest.get("sha256") != Some(&digest) {
+                println!(
+                    "{}Local copy of {} does not match the digest in the statement!",
+                    utils::DIT_LOG,
+                    subject.name
+                );
+            }
+        }
+    }
+
+    let hashable = statement.get_hashable()?;
+
+    let keyfile = Path::join(&env.git_dir, cfg::CONFIG_DIR).join("public_key.json");
+    tag_signing_stage(channel, &hashable, keyfile, config)?;
+
+    Ok(())
+}
+
+/// Record what was signed in the transparency log of the channel, which the leader does once
+/// the operation has ended. The signature is valid either way, so failing to log it is
+/// reported rather than undoing the operation, though verifiers will not take it.
+fn log_outcome<C: Channel + ?Sized>(channel: &C, kind: EntryKind, signed: &[u8]) {
+    match channel.append_log(kind, &provenance::sha256_hex(signed)) {
+        Ok(Some(index)) => println!(
+            "{}Recorded as entry {} of the transparency log",
+            utils::DIT_LOG, index
+        ),
+        Ok(None) => {}
+        Err(err) => println!(
+            "{}Could not record the signature in the transparency log: {}",
+            utils::DIT_LOG, err
+        ),
+    }
+}
+
+/// Let the user pick one of the `pending` operations, which the channel then follows. `None`
+/// stands for not joining any of them (or for starting a new one, for the commands that start
+/// operations).
+pub fn choose_operation<C: Channel + ?Sized>(
+    channel: &C,
+    pending: &[(String, Operation)],
+    prompt: &str,
+) -> Result<Option<Operation>> {
+    if pending.is_empty() {
+        return Ok(None);
+    }
+
+    for (i, (_, op)) in pending.iter().enumerate() {
+        println!("{}[{}] {}", utils::DIT_LOG, i + 1, op);
+    }
+
+    let numbers: Vec<String> = (1..=pending.len()).map(|i| i.to_string()).collect();
+    let mut choices: Vec<&str> = numbers.iter().map(String::as_str).collect();
+    choices.push("n");
+
+    match pending.get(utils::get_user_choice(prompt, &choices)?) {
+        Some((session, op)) => {
+            channel.select_operation(session)?;
+            Ok(Some(op.clone()))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Take part in the pending operation `op`, which the channel follows
+pub fn participate<C: Channel + ?Sized>(
+    channel: &C,
+    op: &Operation,
+    env: &crate::git::GitEnv,
+    config: &Config,
+) -> Result<()> {
+    // TODO Could probably remove the config and just get the vars from the op
+    match op {
+        Operation::KeyGen { .. } => {
+            withdraw_on_cancel(channel, participant_keygen(channel, env, config))?;
+            println!(
+                "{}Key generation is complete, the key should be under the `.dit` folder",
+                utils::DIT_LOG
+            );
+        }
+        Operation::SignTag { .. } => {
+            withdraw_on_cancel(channel, participant_tag(channel, op, env, config))?;
+            println!("{}Tagging is done.", utils::DIT_LOG);
+        }
+        Operation::SignStatement { .. } => {
+            withdraw_on_cancel(channel, participant_attest(channel, op, env, config))?;
+            println!("{}Provenance signing is done.", utils::DIT_LOG);
+        }
+        Operation::SignKey { .. } => println!(
+            "{}The new key is being signed, which only the participants of its generation can join",
+            utils::DIT_LOG
+        ),
+        Operation::Blame {} => println!(
+            "{}The operation was aborted, wait for the leader to end it",
+            utils::DIT_LOG
+        ),
+        Operation::Idle => return Ok(()),
+    }
+    sleep(utils::USER_SLEEP);
+
+    Ok(())
+}
+
+/// Give up the place of a participant that cancelled the operation, so that someone else can
+/// take it. The channel refuses once the protocol is under way, and the place is kept for
+/// resuming.
+fn withdraw_on_cancel<C: Channel + ?Sized, T>(channel: &C, result: Result<T>) -> Result<T> {
+    if let Err(CriticalError::Protocol(ProtocolError::Cancelled)) = &result {
+        if channel.signout().is_ok() {
+            println!("{}Withdrew from the operation", utils::DIT_LOG);
+        }
+    }
+
+    result
+}
+
+pub fn install_hooks(args: Option<&ArgMatches>, env: &crate::git::GitEnv) -> Result<()> {
+    let pattern = args
+        .and_then(|args| args.value_of("pattern"))
+        .unwrap_or(hooks::DEFAULT_RELEASE_PATTERN);
+    let force = args.map_or(false, |args| args.is_present("force"));
+    let bare = args.and_then(|args| args.value_of("bare"));
+
+    let keyid = config::get_keyid(&env.git_dir)?;
+
+    let hooks_dir = match bare {
+        Some(repo) => Path::new(repo).join("hooks"),
+        None => git::get_hooks_dir()?,
+    };
+
+    for hook in hooks::install_hooks(hooks_dir, &keyid, pattern, bare.is_some(), force)? {
+        println!("{}Installed {}", utils::DIT_LOG, hook.display());
+    }
+
+    if bare.is_some() {
+        println!(
+            "{}Make sure the project key is imported into the keyring of the user receiving pushes",
+            utils::DIT_LOG
+        );
+    }
+
+    Ok(())
+}
+
+/// Print the identity key of this participant, in the form the roster of the config expects
+pub fn show_identity(env: &crate::git::GitEnv, config: &Config) -> Result<()> {
+    let path = comm::identity_path(&env.git_dir);
+    let identity = Identity::load_or_create(&path)?;
+
+    println!("{}Your identity key is kept in {}", utils::DIT_LOG, path.display());
+    println!(
+        "{}Ask the leader to add the following to the roster of the config:",
+        utils::DIT_LOG
+    );
+
+    let (name, email) = match &config.user {
+        Some(user) => (user.username.as_str(), user.email.as_str()),
+        None => ("<name>", "<email>"),
+    };
+    println!("\n[[participants]]");
+    println!("name = \"{}\"", name);
+    println!("email = \"{}\"", email);
+    println!("identity = \"{}\"", identity.public_key());
+
+    Ok(())
+}
+
+/// Publish the OpenPGP files given as arguments, or the keyfile written by the key generation,
+/// to the keyserver behind the channel
+pub fn publish_keys<C: Channel + ?Sized>(
+    channel: &C,
+    args: Option<&ArgMatches>,
+    env: &crate::git::GitEnv,
+) -> Result<()> {
+    let files: Vec<PathBuf> = match args.and_then(|args| args.values_of("files")) {
+        Some(files) => files.map(PathBuf::from).collect(),
+        None => vec![Path::join(&env.git_dir, cfg::CONFIG_DIR).join("keyfile.pgp")],
+    };
+
+    for file in files {
+        let contents = fs::read(&file)?;
+        let packets = match std::str::from_utf8(&contents) {
+            Ok(text) if text.contains("-----BEGIN PGP ") => dearmor(text)
+                .ok_or_else(|| CriticalError::User(UserError::KeyFile(file.clone())))?,
+            _ => contents,
+        };
+
+        let published = channel.publish_key(&packets)?;
+        if published.is_empty() {
+            println!("{}The channel has no keyserver to publish to", utils::DIT_LOG);
+            return Ok(());
+        }
+        for fingerprint in published {
+            println!("{}Published {} to {}", utils::DIT_LOG, file.display(), fingerprint);
+        }
+    }
+
+    Ok(())
+}
+
+/// Import the project key, as published to the keyserver behind the channel, into the keyring
+/// `git verify-tag` checks against. This picks up certifications and revocations published
+/// since the key was generated, and returns whether there was anything to import.
+pub fn fetch_project_key<C: Channel + ?Sized>(
+    channel: &C,
+    env: &crate::git::GitEnv,
+) -> Result<bool> {
+    let keyid = config::get_keyid(&env.git_dir)?;
+    let search: String = keyid.iter().map(|b| format!("{:02X}", b)).collect();
+
+    match channel.fetch_key(&format!("0x{}", search))? {
+        Some(packets) => {
+            git::import_keys(&packets)?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+/// Check that the tags given to `git verify-tag` are in the transparency log of the server,
+/// so that a release cannot be signed without a public record. The signed head of the log has
+/// to be signed by the key of the log (`log_key` under `[server]`, or else the one seen
+/// first), and has to extend the head seen last, which is then replaced by it. Tags missing
+/// from the log fail, as they do on channels without one, unless the project opts out of the
+/// log altogether.
+pub fn check_logged<C: Channel + ?Sized>(
+    channel: &C,
+    args: Option<&ArgMatches>,
+    env: &crate::git::GitEnv,
+    config: &Config,
+) -> Result<()> {
+    if !config.checks_log() {
+        return Ok(());
+    }
+
+    let unchecked = |reason: String| CriticalError::from(ProtocolError::Log(reason));
+    let head = channel
+        .tree_head()?
+        .ok_or_else(|| unchecked("the channel keeps no transparency log".to_owned()))?;
+    let path = Path::join(&env.git_dir, cfg::CONFIG_DIR).join(TRANSPARENCY_FILE);
+    let seen: Option<SignedTreeHead> = utils::read_data_from_file(&path).ok();
+
+    let log_key = config
+        .server
+        .as_ref()
+        .and_then(|server| server.log_key.as_ref())
+        .or_else(|| seen.as_ref().map(|seen| &seen.key));
+    if matches!(log_key, Some(key) if *key != head.key) {
+        let reason = format!("the tree head is signed by the unknown key {}", head.key);
+        return Err(unchecked(reason));
+    }
+    let root = match head.root() {
+        Some(root) if head.verify() && head.project == config.project => root,
+        _ => return Err(unchecked("the tree head is not signed for this project".to_owned())),
+    };
+
+    if let Some(seen) = seen.filter(|seen| seen.size > 0) {
+        let seen_root = seen
+            .root()
+            .ok_or_else(|| unchecked(format!("{} is garbled", path.display())))?;
+        let proof = if seen.size <= head.size {
+            log_hashes(channel.log_proof(ProofRequest::Consistency {
+                first: seen.size,
+                second: head.size,
+            })?)
+        } else {
+            None
+        };
+        let consistent = match proof {
+            Some(proof) => transparency::verify_consistency(
+                seen.size, head.size, &seen_root, &root, &proof,
+            ),
+            None => false,
+        };
+        if !consistent {
+            return Err(unchecked(format!(
+                "the log of {} entries does not extend the one of {} entries seen before",
+                head.size, seen.size
+            )));
+        }
+    }
+
+    let tags = args
+        .and_then(|args| args.values_of(""))
+        .into_iter()
+        .flatten()
+        .filter(|arg| !arg.starts_with('-'));
+    for tag in tags {
+        let object = git::get_tag_object(tag)?;
+        let signed = object.find(SIGNATURE_ARMOR).and_then(|start| {
+            let signature = dearmor(&object[start..])?;
+            signed_data(object[..start].as_bytes(), &signature)
+        });
+        let signed = signed
+            .ok_or_else(|| unchecked(format!("tag {} has no readable signature", tag)))?;
+
+        let logged = channel
+            .find_logged(&provenance::sha256_hex(&signed))?
+            .into_iter()
+            .find(|(index, entry)| *index < head.size && entry.kind == EntryKind::TagSignature);
+        let (index, entry) = logged
+            .ok_or_else(|| unchecked(format!("tag {} was signed without being logged", tag)))?;
+
+        let proof = ProofRequest::Inclusion {
+            index,
+            size: head.size,
+        };
+        let included = match log_hashes(channel.log_proof(proof)?) {
+            Some(proof) => {
+                let leaf = transparency::leaf_hash(&entry);
+                transparency::verify_inclusion(&leaf, index, head.size, &proof, &root)
+            }
+            None => false,
+        };
+        if !included {
+            let reason = format!("entry {} for tag {} is not part of the log", index, tag);
+            return Err(unchecked(reason));
+        }
+
+        println!(
+            "{}Tag {} is entry {} of the transparency log, signed by {} on {}",
+            utils::DIT_LOG,
+            tag,
+            index,
+            entry.signers.join(", "),
+            git::get_rfc3339_timestamp(entry.timestamp)?
+        );
+    }
+
+    fs::write(&path, serde_json::to_string(&head)?)?;
+
+    Ok(())
+}
+
+/// Hashes of a proof of the transparency log, as the server sends them in hex
+fn log_hashes(proof: Vec<String>) -> Option<Vec<transparency::Hash>> {
+    proof.iter().map(|hash| transparency::to_hash(hash)).collect()
+}
+
+/// Serve the directory given with `--path` to participants using the `relay` or `socket`
+/// channel. With `--stdio`, stdout carries the replies, so nothing else may be printed to it.
+pub fn relay(args: &ArgMatches) -> Result<()> {
+    let root = PathBuf::from(args.value_of("path").unwrap());
+    let open = move |project, timeouts| {
+        let channel = DirectoryChannel::new(
+            root.clone(),
+            project,
+            Duration::from_millis(comm::DIRECTORY_POLL_INTERVAL),
+        )
+        .with_timeouts(timeouts);
+        Box::new(channel) as Box<dyn Channel>
+    };
+
+    match args.value_of("socket") {
+        Some(socket) => {
+            eprintln!("{}Relaying messages over {}", utils::DIT_LOG, socket);
+            relay::listen(Path::new(socket), open)?;
+        }
+        None => {
+            let stdin = io::stdin();
+            relay::serve(open, stdin.lock(), io::stdout())?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The channel offline operations are carried over, kept under `.dit/offline`
+pub fn offline_channel(config: &Config, env: &crate::git::GitEnv) -> OfflineChannel {
+    let dir = Path::join(&env.git_dir, cfg::CONFIG_DIR).join("offline");
+    OfflineChannel::new(dir, config.project.clone())
+}
+
+/// The offline channel the protocol itself runs over, which checks the signatures of the
+/// imported messages when the config has a roster
+pub fn offline_protocol_channel(
+    config: &Config,
+    env: &crate::git::GitEnv,
+    index: Option<u16>,
+) -> Result<Box<dyn Channel>> {
+    let channel = offline_channel(config, env).with_index(index);
+    comm::authenticate(config, &env.git_dir, Box::new(channel))
+}
+
+/// Offline operations stop whenever they need the messages of the other participants,
+/// which is not an error as far as the user is concerned
+pub fn await_round(result: Result<()>) -> Result<bool> {
+    match result {
+        Ok(()) => Ok(true),
+        Err(CriticalError::Protocol(ProtocolError::Pending)) => {
+            println!(
+                "{}Waiting for the other participants: pass on the output of `dit round export` and import theirs with `dit round import`",
+                utils::DIT_LOG
+            );
+            Ok(false)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+pub fn round_export(
+    args: Option<&ArgMatches>,
+    env: &crate::git::GitEnv,
+    config: &Config,
+) -> Result<()> {
+    let channel = offline_channel(config, env);
+    let round = channel.export()?;
+
+    let chunked = args.map_or(false, |args| args.is_present("chunks"));
+    let (contents, default_output) = if chunked {
+        let size = match args.filter(|args| args.is_present("chunk size")) {
+            Some(args) => value_t!(args, "chunk size", usize).unwrap_or_else(|e| e.exit()),
+            None => DEFAULT_CHUNK_SIZE,
+        };
+        (round.to_chunks(size)?.join("\n"), "round.txt")
+    } else {
+        (serde_json::to_string(&round)?, "round.json")
+    };
+
+    let output = args
+        .and_then(|args| args.value_of("output"))
+        .unwrap_or(default_output);
+    fs::write(output, contents + "\n")?;
+
+    println!(
+        "{}Wrote {} messages of the operation to {}",
+        utils::DIT_LOG,
+        round.messages.len(),
+        output
+    );
+
+    Ok(())
+}
+
+/// Import the rounds exported by the other participants and advance the offline operation
+/// as far as they allow
+pub fn round_import(
+    args: Option<&ArgMatches>,
+    env: &crate::git::GitEnv,
+    config: &Config,
+) -> Result<()> {
+    let args = match args {
+        Some(args) => args,
+        None => return Ok(()),
+    };
+
+    let index = if args.is_present("index") {
+        Some(value_t!(args, "index", u16).unwrap_or_else(|e| e.exit()))
+    } else {
+        None
+    };
+    let channel = offline_channel(config, env).with_index(index);
+
+    // Whole exports are read one by one, while chunks can be split across any number of files
+    let mut rounds = Vec::new();
+    let mut chunks = String::new();
+    for path in args.values_of("files").unwrap() {
+        let contents = if path == "-" {
+            let mut contents = String::new();
+            io::stdin().read_to_string(&mut contents)?;
+            contents
+        } else {
+            fs::read_to_string(path)?
+        };
+
+        if contents.trim_start().starts_with('{') {
+            rounds.push(RoundFile::parse(&contents)?);
+        } else {
+            chunks.push_str(&contents);
+            chunks.push('\n');
+        }
+    }
+    if !chunks.trim().is_empty() {
+        rounds.push(RoundFile::parse(&chunks)?);
+    }
+
+    for round in rounds.iter() {
+        if channel.is_new_operation(round)? {
+            println!("{}{}", utils::DIT_LOG, round.operation);
+            let choice =
+                utils::get_user_choice("Participate in the pending operation?", &["y", "n"])?;
+            if choice != 0 {
+                return Ok(());
+            }
+        }
+
+        channel.import(round)?;
+    }
+
+    let pending = match channel.pending()? {
+        Some(pending) if !pending.finished => pending,
+        _ => {
+            println!("{}The offline operation is already finished", utils::DIT_LOG);
+            return Ok(());
+        }
+    };
+
+    let keyfile = Path::join(&env.git_dir, cfg::CONFIG_DIR).join("public_key.json");
+    let protocol = offline_protocol_channel(config, env, index)?;
+    let op = &pending.operation;
+    // Round files keep no transparency log, so what the leader signed is not recorded
+    let result = match (op, pending.leader) {
+        (Operation::SignTag { .. }, true) => {
+            finish_tag(&*protocol, op, keyfile, env, config).map(drop)
+        }
+        (Operation::SignTag { .. }, false) => participant_tag(&*protocol, op, env, config),
+        (Operation::SignStatement { .. }, true) => {
+            let output = args.value_of("output").unwrap_or(DEFAULT_PROVENANCE);
+            finish_attestation(&*protocol, op, keyfile, output, env, config).map(drop)
+        }
+        (Operation::SignStatement { .. }, false) => {
+            participant_attest(&*protocol, op, env, config)
+        }
+        _ => Err(CriticalError::User(UserError::RoundFile(
+            "only tags and statements are signed offline".to_owned(),
+        ))),
+    };
+
+    if await_round(result)? {
+        channel.end_operation(op)?;
+        println!(
+            "{}Signing is done, export the last round for the participants still waiting on it",
+            utils::DIT_LOG
+        );
+    }
+
+    Ok(())
+}
+
 /// Emulate `git` behaviour by passing unrecognized subcommands directly to the system `git`
 /// executable as-is.
 ///
@@ -1,11 +1,47 @@
 // Part of the code here has been taken from
 
-use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::{thread, time};
 
-use crate::errors::ProtocolError;
-use crate::errors::Result as LocalResult;
-use crate::utils::Operation;
+use crate::errors::{CriticalError, ProtocolError, Refusal, UserError};
+use crate::identity::{self, Identity};
+use crate::pgp;
+use crate::transparency::{EntryKind, LogAppend, LogEntry, ProofRequest, SignedTreeHead};
+use crate::utils::{ChannelConfig, Config, Operation, TlsConfig};
+use crate::wire;
+
+pub mod authenticated;
+pub mod deadline;
+pub mod directory;
+pub mod gitref;
+pub mod offline;
+pub mod relay;
+
+use authenticated::AuthenticatedChannel;
+use deadline::{Deadlines, Timeouts};
+use directory::DirectoryChannel;
+use gitref::GitRefChannel;
+use relay::RelayChannel;
+
+/// Default interval between fetches of the git transport, in seconds
+const GIT_POLL_INTERVAL: u64 = 10;
+/// Default interval between scans of the shared directory, in milliseconds
+pub(crate) const DIRECTORY_POLL_INTERVAL: u64 = 250;
+/// Interval between checks of the operation, for transports without notifications
+const OPERATION_POLL_INTERVAL: u64 = 250;
+/// How long the server may hold a long-polling request, in milliseconds. This has to stay
+/// below the timeout of the HTTP client, and bounds how long cancelling takes.
+const LONG_POLL_WAIT: u64 = 5_000;
+/// Default limit on waiting for a round, in seconds, for transports where all of the
+/// participants are expected to be online at the same time
+const ONLINE_ROUND_TIMEOUT: u64 = 600;
 
 use curv::cryptographic_primitives::secret_sharing::feldman_vss::VerifiableSS;
 use curv::elliptic::curves::secp256_k1::GE;
@@ -27,204 +63,1237 @@ pub struct PartyKeyPair {
 
 pub type Key = String;
 
+/// Party number within the current operation, along with the session the server issued for
+/// it, which namespaces every message of the operation
 #[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct PartySignup {
     pub number: u16,
+    pub session: String,
+}
+
+/// Request for a party number in `session`. Members of the roster ask for their place in it
+/// when generating a key, and the server hands out the lowest free number otherwise.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Signup {
+    pub session: String,
+    pub party: Option<u16>,
 }
 
 #[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct Index {
+    pub session: String,
     pub key: Key,
 }
 
 #[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct Entry {
+    pub session: String,
     pub key: Key,
     pub value: String,
 }
 
-pub trait Channel: Serialize + DeserializeOwned {
-    fn send_p2p(&self) -> Result<(), ProtocolError>;
-    fn send_broadcast(&self) -> Result<(), ProtocolError>;
-    fn start_operation(&self) -> Result<(), ProtocolError>;
-    fn end_operation(&self) -> Result<(), ProtocolError>;
-    fn check_operation(&self) -> Result<Operation, ProtocolError>;
+/// Request for the messages stored under `keys` in `session`, which the server holds for up to
+/// `wait_ms` milliseconds until all of them have arrived. Missing messages are returned as
+/// `None`.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Batch {
+    pub session: String,
+    pub keys: Vec<Key>,
+    pub wait_ms: u64,
+}
+
+/// Request for the operation of `session` (see `/get-operation`), which the server holds for up
+/// to `wait_ms` milliseconds until it differs from `known`
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct OperationWait {
+    pub session: Option<String>,
+    pub known: Operation,
+    pub wait_ms: u64,
+}
+
+/// Request to queue `operation`, taking the place of the operation of the session `after` for
+/// the participants still following that one. The identity keys of the `roster`, in order,
+/// let a server that knows its clients hold them to their place in it.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct OperationStart {
+    pub operation: Operation,
+    pub after: Option<String>,
+    pub roster: Vec<String>,
+}
+
+/// Pending operation, along with the session the server issued for it and how far along it is
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Queued {
+    pub session: String,
+    pub operation: Operation,
+    pub stage: Stage,
+}
+
+/// Where an operation stands on the server. Operations are `Proposed` when started, collect
+/// signups until every party number is taken, and are then `Running` the round of their latest
+/// message, until the leader ends them. Aborted operations are reported as `Operation::Blame`.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Stage {
+    Proposed,
+    CollectingSignups,
+    Running(String),
+    Finalized,
+    Aborted(AbortReason),
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum AbortReason {
+    /// A participant blamed the others, or a majority voted to abort without the leader
+    Blamed,
+    /// Nothing happened for longer than the server allows
+    Expired,
+    /// An operator aborted it through the admin API
+    Operator,
+}
+
+/// Range of wire versions, which clients send to `/hello` before anything else. Builds speak a
+/// single version, sent as both ends, but the range keeps the route the same for older ones.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Hello {
+    pub min: u8,
+    pub max: u8,
+}
+
+/// Transport over which the participants of a protocol exchange their messages.
+///
+/// Both `dkg::distributed_keygen` and `signing::distributed_sign` are written purely in terms
+/// of this trait, so adding a new way of reaching the other participants only requires a new
+/// implementation of it (see `HTTPChannel` for the reference one).
+pub trait Channel {
+    /// Obtain the (1-indexed) party number for the pending key generation
+    fn signup_keygen(&self) -> Result<u16, ProtocolError>;
+    /// Obtain the (1-indexed) party number for the pending signing operation
+    fn signup_sign(&self) -> Result<u16, ProtocolError>;
+    /// Obtain exactly the party number `party` for the pending key generation, which is how
+    /// members of the roster generate the key share of their place in it. Transports that
+    /// cannot hand out a particular number refuse.
+    fn claim_keygen(&self, _party: u16) -> Result<u16, ProtocolError> {
+        Err(ProtocolError::Full)
+    }
+    /// Give up the party number signed up for, so that someone else can take it. Transports
+    /// refuse once the protocol is under way.
+    fn signout(&self) -> Result<(), ProtocolError>;
+
+    /// Make `data` available to every other participant for the given round
+    fn broadcast(&self, party_num: u16, round: &str, data: String) -> Result<(), ProtocolError>;
+    /// Make `data` available only to `party_to` for the given round
+    fn send_p2p(
+        &self,
+        party_from: u16,
+        party_to: u16,
+        round: &str,
+        data: String,
+    ) -> Result<(), ProtocolError>;
+    /// Wait for the broadcasts of all `n` parties except `party_num`, ordered by party number
+    fn poll_for_broadcasts(
+        &self,
+        party_num: u16,
+        n: u16,
+        round: &str,
+    ) -> Result<Vec<String>, ProtocolError>;
+    /// Wait for the messages addressed to `party_num` by all of the other `n - 1` parties,
+    /// ordered by party number
+    fn poll_for_p2p(&self, party_num: u16, n: u16, round: &str)
+        -> Result<Vec<String>, ProtocolError>;
+
+    fn start_operation(&self, op: &Operation) -> Result<(), ProtocolError>;
+    fn end_operation(&self, op: &Operation) -> Result<(), ProtocolError>;
+    fn get_current_operation(&self) -> Result<Operation, ProtocolError>;
+    /// Signal to the other participants that the protocol could not be completed
     fn blame(&self) -> Result<(), ProtocolError>;
+    /// Remove all of the messages of the (finished) operation
+    fn clear(&self) -> Result<(), ProtocolError>;
+
+    /// Wait for the operation to change from `known`, and return the current one. Transports
+    /// that cannot be notified of changes check again after a short delay, so this may also
+    /// return `known` itself.
+    fn await_operation(&self, _known: &Operation) -> Result<Operation, ProtocolError> {
+        thread::sleep(time::Duration::from_millis(OPERATION_POLL_INTERVAL));
+        self.get_current_operation()
+    }
+
+    /// Identifier of the session signed up for, which every protocol message is tagged with
+    /// so that messages of other runs of the operation are rejected. Transports that do not
+    /// tell sessions apart have none.
+    fn session(&self) -> Option<String> {
+        None
+    }
+
+    /// Place in the roster (1-indexed) of the member that signed up as `party`, once one of
+    /// their messages has been verified. Only transports that authenticate the participants
+    /// know it.
+    fn member(&self, _party: u16) -> Option<u16> {
+        None
+    }
+
+    /// Pending operations along with their sessions, oldest first. Transports that run one
+    /// operation at a time list the current one.
+    fn list_operations(&self) -> Result<Vec<(String, Operation)>, ProtocolError> {
+        match self.get_current_operation()? {
+            Operation::Idle => Ok(vec![]),
+            op => Ok(vec![(self.session().unwrap_or_default(), op)]),
+        }
+    }
+
+    /// Follow the operation of `session`, as listed by `list_operations`, from now on. There is
+    /// nothing to choose from for transports that run one operation at a time.
+    fn select_operation(&self, _session: &str) -> Result<(), ProtocolError> {
+        Ok(())
+    }
+
+    /// Result of a protocol stage stored by an earlier, interrupted run of the same operation.
+    /// Only transports that let the protocol resume across invocations keep these.
+    fn load_stage(&self, _stage: &str) -> Option<String> {
+        None
+    }
+    fn store_stage(&self, _stage: &str, _data: String) -> Result<(), ProtocolError> {
+        Ok(())
+    }
+
+    /// Publish OpenPGP certificates of the project, or signatures on them such as revocations,
+    /// returning the fingerprints of the certificates they changed. Only transports backed by
+    /// a keyserver take them, and the others publish nothing.
+    fn publish_key(&self, _packets: &[u8]) -> Result<Vec<String>, ProtocolError> {
+        Ok(vec![])
+    }
+
+    /// Certificates matching `search` (such as a key ID with a `0x` prefix), if they were
+    /// published through the transport
+    fn fetch_key(&self, _search: &str) -> Result<Option<Vec<u8>>, ProtocolError> {
+        Ok(None)
+    }
+
+    /// Record the outcome of the operation last ended in the transparency log of the transport,
+    /// `digest` being the SHA-256 digest (in hex) of what was signed. Returns the index of the
+    /// entry, or `None` for transports that keep no log.
+    fn append_log(&self, _kind: EntryKind, _digest: &str) -> Result<Option<u64>, ProtocolError> {
+        Ok(None)
+    }
+
+    /// Current signed head of the transparency log, if the transport keeps one
+    fn tree_head(&self) -> Result<Option<SignedTreeHead>, ProtocolError> {
+        Ok(None)
+    }
+
+    /// Entries of the transparency log with the given digest, along with their indices
+    fn find_logged(&self, _digest: &str) -> Result<Vec<(u64, LogEntry)>, ProtocolError> {
+        Ok(vec![])
+    }
+
+    /// Inclusion or consistency proof for the transparency log, as hashes in hex
+    fn log_proof(&self, _proof: ProofRequest) -> Result<Vec<String>, ProtocolError> {
+        Ok(vec![])
+    }
+
+    /// Blame the other participants and produce the error the protocol should terminate with.
+    /// Failing to notify the others does not change the outcome, so that error is dropped.
+    fn abort(&self) -> ProtocolError {
+        let _ = self.blame();
+        ProtocolError::Blame
+    }
+}
+
+/// Party numbers a signup for a key generation among `participants` may take: just `party`
+/// when claiming one, and any of them otherwise. Claims out of range get none at all.
+pub(crate) fn keygen_slots(participants: u16, party: Option<u16>) -> Option<RangeInclusive<u16>> {
+    match party {
+        Some(party) if (1..=participants).contains(&party) => Some(party..=party),
+        Some(_) => None,
+        None => Some(1..=participants),
+    }
+}
+
+/// Round names are made of lowercase letters, digits and dashes, like `sign-round1-echo`.
+/// Transports that make them part of a path check them against this, as they may come from
+/// someone else.
+pub(crate) fn is_round_name(round: &str) -> bool {
+    !round.is_empty()
+        && round
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+}
+
+/// Distinguishes the files and claims written by channels sharing a process, as in `dit relay`
+static WRITES: AtomicUsize = AtomicUsize::new(0);
+
+pub(crate) fn unique_suffix() -> String {
+    let nanos = time::SystemTime::now()
+        .duration_since(time::SystemTime::UNIX_EPOCH)
+        .map(|time| time.as_nanos())
+        .unwrap_or(0);
+    let count = WRITES.fetch_add(1, Ordering::SeqCst);
+
+    format!("{}-{}-{}", nanos, std::process::id(), count)
+}
+
+/// Session joined through a `Mailbox`, along with the party number we signed up as
+pub(crate) struct Session {
+    pub id: String,
+    pub party: Option<u16>,
+}
+
+/// Medium the `git` and `directory` channels leave their messages on, where every message of
+/// a session lives under a path such as `<round>/<from>[-<to>]`, `signup/<index>` or
+/// `blame/<party>`, and can be created only once.
+///
+/// Signups, rounds and blame work the same on both, on top of the few operations below.
+pub(crate) trait Mailbox {
+    fn session_state(&self) -> &RefCell<Option<Session>>;
+    fn deadlines(&self) -> &Deadlines;
+    fn poll_interval(&self) -> time::Duration;
+
+    /// Catch up with what the other participants left on the medium
+    fn refresh(&self) -> Result<(), ProtocolError>;
+    /// Store `data` under `path` in session `id`, returning whether it was us who stored it
+    /// rather than someone having gotten there first
+    fn create(&self, id: &str, path: &str, data: &str) -> Result<bool, ProtocolError>;
+    /// Contents stored under `path` in session `id`, as of the last refresh
+    fn read(&self, id: &str, path: &str) -> Result<Option<String>, ProtocolError>;
+    fn remove(&self, id: &str, path: &str) -> Result<(), ProtocolError>;
+    /// Whether anything is stored below `dir` in session `id`
+    fn any_below(&self, id: &str, dir: &str) -> Result<bool, ProtocolError>;
+
+    fn session_id(&self) -> Result<String, ProtocolError> {
+        match &*self.session_state().borrow() {
+            Some(session) => Ok(session.id.clone()),
+            None => Err(ProtocolError::Connection),
+        }
+    }
+
+    fn party(&self) -> Option<u16> {
+        self.session_state()
+            .borrow()
+            .as_ref()
+            .and_then(|session| session.party)
+    }
+
+    /// Take the first free party number among `slots` in session `id`, by creating
+    /// `signup/<index>` with contents unique to us
+    fn signup(&self, id: String, slots: RangeInclusive<u16>) -> Result<u16, ProtocolError> {
+        *self.session_state().borrow_mut() = Some(Session {
+            id: id.clone(),
+            party: None,
+        });
+        self.deadlines().start();
+        let claim = unique_suffix();
+
+        for index in slots {
+            if self.create(&id, &format!("signup/{}", index), &claim)? {
+                if let Some(session) = self.session_state().borrow_mut().as_mut() {
+                    session.party = Some(index);
+                }
+                return Ok(index);
+            }
+        }
+
+        Err(ProtocolError::Full)
+    }
+
+    fn signout_party(&self) -> Result<(), ProtocolError> {
+        match self.party() {
+            Some(index) => self.remove(&self.session_id()?, &format!("signup/{}", index)),
+            None => Ok(()),
+        }
+    }
+
+    /// Leave a message under `path` of the current session, which nobody may have left
+    /// before us
+    fn post(&self, path: &str, data: &str) -> Result<(), ProtocolError> {
+        if self.create(&self.session_id()?, path, data)? {
+            Ok(())
+        } else {
+            Err(ProtocolError::Connection)
+        }
+    }
+
+    /// Any participant aborting the session leaves `blame/<party>` behind
+    fn is_blamed(&self, id: &str) -> Result<bool, ProtocolError> {
+        self.any_below(id, "blame")
+    }
+
+    fn blame_session(&self) -> Result<(), ProtocolError> {
+        let party = self.party().unwrap_or(0);
+        self.create(&self.session_id()?, &format!("blame/{}", party), "blame")
+            .map(|_| ())
+    }
+
+    /// Wait until the messages of every party in `paths` exist, then return their contents
+    /// in order
+    fn wait_for(
+        &self,
+        round: &str,
+        paths: Vec<(u16, String)>,
+    ) -> Result<Vec<String>, ProtocolError> {
+        let id = self.session_id()?;
+        let wait = self.deadlines().round();
+
+        loop {
+            self.refresh()?;
+
+            let mut messages = Vec::with_capacity(paths.len());
+            let mut missing = Vec::new();
+            for (party, path) in paths.iter() {
+                match self.read(&id, path)? {
+                    Some(message) => messages.push(message),
+                    None => missing.push(*party),
+                }
+            }
+
+            if missing.is_empty() {
+                return Ok(messages);
+            }
+
+            if self.is_blamed(&id)? {
+                return Err(ProtocolError::Blame);
+            }
+
+            if let Err(err) = wait.check(round, missing) {
+                let _ = self.blame_session();
+                return Err(err);
+            }
+            thread::sleep(self.poll_interval());
+        }
+    }
+
+    fn collect_broadcasts(
+        &self,
+        party_num: u16,
+        n: u16,
+        round: &str,
+    ) -> Result<Vec<String>, ProtocolError> {
+        let paths = (1..=n)
+            .filter(|&i| i != party_num)
+            .map(|i| (i, format!("{}/{}", round, i)))
+            .collect();
+
+        self.wait_for(round, paths)
+    }
+
+    fn collect_p2p(
+        &self,
+        party_num: u16,
+        n: u16,
+        round: &str,
+    ) -> Result<Vec<String>, ProtocolError> {
+        let paths = (1..=n)
+            .filter(|&i| i != party_num)
+            .map(|i| (i, format!("{}/{}-{}", round, i, party_num)))
+            .collect();
+
+        self.wait_for(round, paths)
+    }
+}
+
+/// Set up the transport selected in the config, with `repo` being the root of the local
+/// repository.
+pub fn from_config(config: &Config, repo: PathBuf) -> crate::errors::Result<Box<dyn Channel>> {
+    let project = config.project.clone();
+
+    let channel: Box<dyn Channel> = match &config.channel {
+        Some(ChannelConfig::Git {
+            remote,
+            poll_interval,
+        }) => Box::new(
+            GitRefChannel::new(
+                repo.clone(),
+                remote.clone(),
+                project,
+                time::Duration::from_secs(poll_interval.unwrap_or(GIT_POLL_INTERVAL)),
+            )
+            .with_timeouts(timeouts(config, None)),
+        ),
+        Some(ChannelConfig::Directory {
+            path,
+            poll_interval_ms,
+        }) => Box::new(
+            DirectoryChannel::new(
+                repo.join(path),
+                project,
+                time::Duration::from_millis(poll_interval_ms.unwrap_or(DIRECTORY_POLL_INTERVAL)),
+            )
+            .with_timeouts(timeouts(config, Some(ONLINE_ROUND_TIMEOUT))),
+        ),
+        // The relay enforces the timeouts, as it is the one waiting for the messages
+        Some(ChannelConfig::Relay { command }) => Box::new(RelayChannel::command(
+            command.clone(),
+            project,
+            timeouts(config, Some(ONLINE_ROUND_TIMEOUT)),
+        )),
+        Some(ChannelConfig::Socket { path }) => Box::new(RelayChannel::socket(
+            repo.join(path),
+            project,
+            timeouts(config, Some(ONLINE_ROUND_TIMEOUT)),
+        )),
+        None => match &config.server {
+            Some(server) => {
+                let scheme = if server.tls.is_some() { "https" } else { "http" };
+                let address = format!("{}://{}:{}", scheme, server.address, server.port);
+
+                let mut channel = HTTPChannel::new(address, project)
+                    .with_client(http_client(server.tls.as_ref(), &repo)?)
+                    .with_timeouts(timeouts(config, Some(ONLINE_ROUND_TIMEOUT)))
+                    .with_roster(roster(config))
+                    .with_resume(repo.join(".dit").join("resume.json"));
+                if let Some(token_file) = &server.token_file {
+                    let token = fs::read_to_string(repo.join(token_file))?;
+                    channel = channel.with_token(token.trim().to_owned());
+                }
+
+                Box::new(channel)
+            }
+            None => return Err(CriticalError::User(UserError::MissingChannel)),
+        },
+    };
+
+    authenticate(config, &repo, channel)
+}
+
+/// Client for the HTTP channel, verifying the server and identifying this participant as
+/// set up in the `[server.tls]` table, whose paths are resolved against `repo`
+pub fn http_client(
+    tls: Option<&TlsConfig>,
+    repo: &Path,
+) -> crate::errors::Result<reqwest::blocking::Client> {
+    let tls = match tls {
+        Some(tls) => tls,
+        None => return Ok(reqwest::blocking::Client::new()),
+    };
+    let read = |path: &PathBuf| fs::read(repo.join(path));
+
+    let mut builder = reqwest::blocking::Client::builder().use_rustls_tls();
+    if let Some(ca_bundle) = &tls.ca_bundle {
+        for certificate in reqwest::Certificate::from_pem_bundle(&read(ca_bundle)?)? {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+    // Pinning leaves the pinned certificate as the only one trusted
+    if let Some(pinned) = &tls.pinned_certificate {
+        builder = builder
+            .tls_built_in_root_certs(false)
+            .add_root_certificate(reqwest::Certificate::from_pem(&read(pinned)?)?);
+    }
+
+    match (&tls.client_certificate, &tls.client_key) {
+        (Some(certificate), Some(key)) => {
+            let mut pem = read(certificate)?;
+            pem.extend(read(key)?);
+            builder = builder.identity(reqwest::Identity::from_pem(&pem)?);
+        }
+        (Some(certificate), None) => {
+            // The key may also come after the certificate in the same file
+            builder = builder.identity(reqwest::Identity::from_pem(&read(certificate)?)?);
+        }
+        (None, Some(_)) => {
+            return Err(CriticalError::User(UserError::ClientKeyWithoutCertificate))
+        }
+        (None, None) => {}
+    }
+
+    Ok(builder.build()?)
+}
+
+/// The timeouts of the config, with a limit of 0 meaning no limit at all. Transports meant
+/// for participants that come and go only time out when configured to.
+fn timeouts(config: &Config, default_round: Option<u64>) -> Timeouts {
+    let configured = config.timeouts.clone().unwrap_or_default();
+    let limit = |secs: u64| Some(secs).filter(|&secs| secs > 0).map(time::Duration::from_secs);
+
+    Timeouts {
+        round: configured.round.or(default_round).and_then(limit),
+        operation: configured.operation.and_then(limit),
+    }
+}
+
+/// Where each participant keeps the key they sign their protocol messages with
+pub fn identity_path(repo: &Path) -> PathBuf {
+    repo.join(".dit").join("identity.json")
+}
+
+/// Identity keys of the members of the roster, in order, if the config has one
+fn roster(config: &Config) -> Vec<String> {
+    config
+        .members()
+        .unwrap_or_default()
+        .iter()
+        .map(|member| member.identity.clone())
+        .collect()
+}
+
+/// Only accept messages signed by a member of the roster, when the config has one
+pub fn authenticate(
+    config: &Config,
+    repo: &Path,
+    channel: Box<dyn Channel>,
+) -> crate::errors::Result<Box<dyn Channel>> {
+    if config.members().is_none() {
+        return Ok(channel);
+    }
+    let roster = roster(config);
+    let identity = Identity::load_or_create(&identity_path(repo))?;
+
+    Ok(Box::new(AuthenticatedChannel::new(
+        channel,
+        config.project.clone(),
+        identity,
+        roster,
+    )))
+}
+
+/// What a participant needs to pick up an operation on the server where they left off, say
+/// after a crash: the party number they got, and the results of the protocol stages that drew
+/// fresh randomness, which have to be the same as the first time around
+#[derive(Serialize, Deserialize)]
+struct Resume {
+    session: String,
+    party: u16,
+    stages: BTreeMap<String, String>,
 }
 
 pub struct HTTPChannel {
-    client: reqwest::Client,
+    client: reqwest::blocking::Client,
     address: String,
     retries: u8,
     retry_delay: time::Duration,
-    project: String
+    project: String,
+    deadlines: Deadlines,
+    token: Option<String>,
+    roster: Vec<String>,
+    version: Cell<Option<u8>>,
+    /// Session of the operation this channel follows, which the server issues when it is
+    /// started
+    session: RefCell<Option<String>>,
+    /// Session of the operation this channel ended last, in whose place the next one it starts
+    /// is put
+    ended: RefCell<Option<String>>,
+    /// Stage of the operation followed, as of the last time the server was asked
+    stage: RefCell<Option<Stage>>,
+    /// Party number signed up for in the session
+    party: Cell<Option<u16>>,
+    /// Where the `Resume` record of the operation signed up for is kept, if anywhere
+    resume: Option<PathBuf>,
 }
 
 impl HTTPChannel {
     pub fn new(server: String, project: String) -> HTTPChannel {
         HTTPChannel {
-            client: reqwest::Client::new(),
+            client: reqwest::blocking::Client::new(),
             retries: 3,
             address: String::from(server),
             retry_delay: time::Duration::from_millis(250),
-            project
+            project,
+            deadlines: Deadlines::new(Timeouts::default()),
+            token: None,
+            roster: Vec::new(),
+            version: Cell::new(None),
+            session: RefCell::new(None),
+            ended: RefCell::new(None),
+            stage: RefCell::new(None),
+            party: Cell::new(None),
+            resume: None,
         }
     }
 
-    fn postb<T>(&self, path: &str, body: T) -> Option<String>
+    pub fn with_client(mut self, client: reqwest::blocking::Client) -> HTTPChannel {
+        self.client = client;
+        self
+    }
+
+    /// Give up on participants that take longer than `timeouts` to send their messages
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> HTTPChannel {
+        self.deadlines = Deadlines::new(timeouts);
+        self
+    }
+
+    /// Identify this participant to the server with a bearer `token`
+    pub fn with_token(mut self, token: String) -> HTTPChannel {
+        self.token = Some(token);
+        self
+    }
+
+    /// Send the identity keys of the roster along with the operations this channel starts
+    pub fn with_roster(mut self, roster: Vec<String>) -> HTTPChannel {
+        self.roster = roster;
+        self
+    }
+
+    /// Keep what it takes to resume the operation signed up for in the file at `path`, which
+    /// holds secrets of the protocol until the operation is over
+    pub fn with_resume(mut self, path: PathBuf) -> HTTPChannel {
+        self.resume = Some(path);
+        self
+    }
+
+    /// Post `body` along with the name of the project, returning the status and body of the
+    /// response
+    fn send<T>(&self, path: &str, body: T) -> Result<(reqwest::StatusCode, String), ProtocolError>
     where
         T: serde::ser::Serialize,
     {
         let msg = (self.project.clone(), body);
 
         for _ in 1..self.retries {
-            let res = self
+            let mut req = self
                 .client
                 .post(&format!("{}/{}", self.address, path))
-                .json(&msg)
-                .send();
+                .json(&msg);
+            if let Some(token) = &self.token {
+                req = req.bearer_auth(token);
+            }
 
-            if let Ok(mut res) = res {
-                return Some(res.text().unwrap());
+            if let Ok(res) = req.send() {
+                let status = res.status();
+                let text = res.text().map_err(|_| ProtocolError::Connection)?;
+                return Ok((status, text));
             }
             thread::sleep(self.retry_delay);
         }
-        None
+        Err(ProtocolError::Connection)
     }
 
-    pub fn broadcast(&self, party_num: u16, round: &str, data: String) -> Result<(), ()> {
-        // TODO Probably need more of these
-        let key = format!("{}-{}", party_num, round);
+    /// Post `body`, turning the reason the server gives for refusing it into the matching
+    /// error. Server errors without a reason are treated like connection failures.
+    fn postb<T>(&self, path: &str, body: T) -> Result<String, ProtocolError>
+    where
+        T: serde::ser::Serialize,
+    {
+        let (status, text) = self.send(path, body)?;
+        if status.is_success() {
+            return Ok(text);
+        }
+
+        match serde_json::from_str::<Refusal>(&text) {
+            Ok(refusal) => Err(refusal.into()),
+            Err(_) if status == reqwest::StatusCode::FORBIDDEN => Err(ProtocolError::Unauthorized),
+            Err(_) => Err(ProtocolError::Connection),
+        }
+    }
+
+    /// Check that the server speaks the version of the wire format of this build, the first
+    /// time it is reached, so that mismatched builds fail up front rather than on the first
+    /// garbled response
+    fn hello(&self) -> Result<u8, ProtocolError> {
+        if let Some(version) = self.version.get() {
+            return Ok(version);
+        }
+
+        let hello = Hello {
+            min: wire::VERSION,
+            max: wire::VERSION,
+        };
+        // The answer has the same shape across versions, so it is read whatever the status
+        let (status, res_body) = self.send("hello", hello)?;
+        if status == reqwest::StatusCode::FORBIDDEN {
+            return Err(ProtocolError::Unauthorized);
+        }
+        // Servers that predate versioning do not know the route at all
+        let res: Result<u8, Hello> =
+            serde_json::from_str(&res_body).map_err(|_| ProtocolError::ServerVersion(None))?;
+
+        match res {
+            Ok(version) if version == wire::VERSION => {
+                self.version.set(Some(version));
+                Ok(version)
+            }
+            Ok(version) => Err(ProtocolError::ServerVersion(Some((version, version)))),
+            Err(server) => Err(ProtocolError::ServerVersion(Some((server.min, server.max)))),
+        }
+    }
+
+    fn post<T>(&self, path: &str, body: T) -> Result<String, ProtocolError>
+    where
+        T: serde::ser::Serialize,
+    {
+        self.hello()?;
+        self.postb(path, body)
+    }
+
+    /// Post `body` and decode the server response, treating both unreachable servers and
+    /// garbled responses as connection failures
+    fn request<T, R>(&self, path: &str, body: T) -> Result<R, ProtocolError>
+    where
+        T: serde::ser::Serialize,
+        R: DeserializeOwned,
+    {
+        let res_body = self.post(path, body)?;
+        serde_json::from_str(&res_body).map_err(|_| ProtocolError::Connection)
+    }
+
+    /// Wait for the messages of `round` that the given parties stored under their keys, in
+    /// order. The server only answers once all of them have arrived (or it has held the
+    /// request for long enough), so there is no need to sleep between requests.
+    fn poll(&self, round: &str, keys: Vec<(u16, Key)>) -> Result<Vec<String>, ProtocolError> {
+        let (parties, keys): (Vec<u16>, Vec<Key>) = keys.into_iter().unzip();
+        let session = self.session_id()?;
+        let wait = self.deadlines.round();
+
+        loop {
+            let wait_ms = match wait.remaining() {
+                Some(remaining) => LONG_POLL_WAIT.min(remaining.as_millis() as u64),
+                None => LONG_POLL_WAIT,
+            };
+            let batch = Batch {
+                session: session.clone(),
+                keys: keys.clone(),
+                wait_ms,
+            };
+
+            let answer: Vec<Option<String>> = match self.request("get-batch", batch) {
+                Ok(answer) => answer,
+                // The server may be restarting, and picks up where it left off once it is back
+                Err(ProtocolError::Connection) => {
+                    wait.check(round, parties.clone())?;
+                    thread::sleep(self.retry_delay);
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+            let missing = parties
+                .iter()
+                .zip(answer.iter())
+                .filter(|(_, message)| message.is_none())
+                .map(|(party, _)| *party)
+                .collect::<Vec<_>>();
+
+            if missing.is_empty() {
+                return Ok(answer.into_iter().flatten().collect());
+            }
+
+            if let Operation::Blame {} = self.get_current_operation()? {
+                return Err(self.aborted());
+            }
+
+            if let Err(err) = wait.check(round, missing) {
+                let _ = self.blame();
+                return Err(err);
+            }
+        }
+    }
+
+    fn signup(&self, path: &str, party: Option<u16>) -> Result<u16, ProtocolError> {
+        // Without a choice of operation, join the oldest one
+        if self.session.borrow().is_none() {
+            self.get_current_operation()?;
+        }
+
+        let session = self.session_id()?;
+        let res: PartySignup = match self.resumed() {
+            // Picking up after a crash, with the number handed out the first time around
+            Some(resume) => self.request(
+                "rejoin",
+                PartySignup {
+                    number: resume.party,
+                    session,
+                },
+            )?,
+            None => {
+                let res: PartySignup = self.request(path, Signup { session, party })?;
+                self.record(&Resume {
+                    session: res.session.clone(),
+                    party: res.number,
+                    stages: BTreeMap::new(),
+                })?;
+                res
+            }
+        };
+
+        self.deadlines.start();
+        self.party.set(Some(res.number));
+        *self.session.borrow_mut() = Some(res.session);
+        Ok(res.number)
+    }
+
+    /// The `Resume` record of an earlier run of the operation followed, if there is one
+    fn resumed(&self) -> Option<Resume> {
+        let contents = fs::read_to_string(self.resume.as_ref()?).ok()?;
+        let resume: Resume = serde_json::from_str(&contents).ok()?;
+
+        Some(resume).filter(|resume| self.session.borrow().as_ref() == Some(&resume.session))
+    }
+
+    /// Replace the `Resume` record, which only ever covers one operation. It holds the secrets
+    /// of the stages, so only its owner may read it, and it is written next to the record and
+    /// moved over it, so that a crash never leaves half of one behind.
+    fn record(&self, resume: &Resume) -> Result<(), ProtocolError> {
+        let path = match &self.resume {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let contents = serde_json::to_string(resume).map_err(|_| ProtocolError::Connection)?;
+        let temp = path.with_extension("tmp");
+
+        path.parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| {
+                // Left behind by a run that crashed while writing
+                let _ = fs::remove_file(&temp);
+                identity::create_secret_file(&temp)?.write_all(contents.as_bytes())
+            })
+            .and_then(|_| fs::rename(&temp, path))
+            .map_err(|_| ProtocolError::Connection)
+    }
+
+    /// Drop the `Resume` record once the operation is over, as its stages must never be used
+    /// again
+    fn forget(&self) {
+        if let Some(path) = &self.resume {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    /// Follow the operation the server resolved the session to, if any
+    fn follow(&self, queued: Option<Queued>) -> Operation {
+        // Once the operation signed up for is over, there is nothing left to resume
+        let over = match &queued {
+            Some(queued) => {
+                self.session.borrow().as_ref() != Some(&queued.session)
+                    || matches!(queued.stage, Stage::Finalized | Stage::Aborted(_))
+            }
+            None => true,
+        };
+        if over && self.resumed().is_some() {
+            self.forget();
+        }
+
+        match queued {
+            Some(queued) => {
+                *self.session.borrow_mut() = Some(queued.session);
+                *self.stage.borrow_mut() = Some(queued.stage);
+                queued.operation
+            }
+            None => Operation::Idle,
+        }
+    }
+
+    /// Why the operation followed was aborted, as far as the participants are concerned
+    fn aborted(&self) -> ProtocolError {
+        match *self.stage.borrow() {
+            Some(Stage::Aborted(AbortReason::Expired)) => ProtocolError::Expired,
+            _ => ProtocolError::Blame,
+        }
+    }
+
+    fn session_id(&self) -> Result<String, ProtocolError> {
+        self.session.borrow().clone().ok_or(ProtocolError::Connection)
+    }
+
+    /// Store `data` under `key` in the session of the current operation, which the server
+    /// refuses once the operation has ended
+    fn set(&self, key: Key, data: String) -> Result<(), ProtocolError> {
         let entry = Entry {
-            key: key.clone(),
+            session: self.session_id()?,
+            key,
             value: data,
         };
 
-        let res_body = self.postb("set", entry).unwrap();
-        serde_json::from_str(&res_body).unwrap()
+        self.post("set", entry).map(|_| ())
+    }
+}
+
+impl Channel for HTTPChannel {
+    fn signup_keygen(&self) -> Result<u16, ProtocolError> {
+        self.signup("signupkeygen", None)
     }
 
-    pub fn sendp2p(
+    fn signup_sign(&self) -> Result<u16, ProtocolError> {
+        self.signup("signupsign", None)
+    }
+
+    fn claim_keygen(&self, party: u16) -> Result<u16, ProtocolError> {
+        self.signup("signupkeygen", Some(party))
+    }
+
+    fn signout(&self) -> Result<(), ProtocolError> {
+        let number = match self.party.get() {
+            Some(number) => number,
+            None => return Ok(()),
+        };
+        let signout = PartySignup {
+            number,
+            session: self.session_id()?,
+        };
+
+        self.post("signout", signout)?;
+        self.party.set(None);
+        self.forget();
+        Ok(())
+    }
+
+    fn broadcast(&self, party_num: u16, round: &str, data: String) -> Result<(), ProtocolError> {
+        // TODO Probably need more of these
+        let key = format!("{}-{}", party_num, round);
+        self.set(key, data)
+    }
+
+    fn send_p2p(
         &self,
         party_from: u16,
         party_to: u16,
         round: &str,
         data: String,
-    ) -> LocalResult<()> {
+    ) -> Result<(), ProtocolError> {
         let key = format!("{}-{}-{}", party_from, party_to, round);
+        self.set(key, data)
+    }
 
-        let entry = Entry { key, value: data };
-        // TODO Do some more checking for this
-        let res_body = self.postb("set", entry).unwrap();
-        let res: Result<(), ()> = serde_json::from_str(&res_body).unwrap();
+    fn poll_for_broadcasts(
+        &self,
+        party_num: u16,
+        n: u16,
+        round: &str,
+    ) -> Result<Vec<String>, ProtocolError> {
+        let keys = (1..=n)
+            .filter(|&i| i != party_num)
+            .map(|i| (i, format!("{}-{}", i, round)))
+            .collect();
+        self.poll(round, keys)
+    }
 
-        if let Ok(_) = res {
-            Ok(())
-        } else {
-            panic!("Could not set value!");
-        }
+    fn poll_for_p2p(
+        &self,
+        party_num: u16,
+        n: u16,
+        round: &str,
+    ) -> Result<Vec<String>, ProtocolError> {
+        let keys = (1..=n)
+            .filter(|&i| i != party_num)
+            .map(|i| (i, format!("{}-{}-{}", i, party_num, round)))
+            .collect();
+        self.poll(round, keys)
     }
 
-    pub fn poll_for_broadcasts(&self, party_num: u16, n: u16, round: &str) -> Vec<String> {
-        let mut ans_vec = Vec::new();
-        for i in 1..=n {
-            if i != party_num {
-                let key = format!("{}-{}", i, round);
-                let index = Index { key };
-                loop {
-                    // add delay to allow the server to process request:
-                    thread::sleep(self.retry_delay);
-                    let res_body = self.postb("get", index.clone()).unwrap();
-                    let answer: Result<Entry, ()> = serde_json::from_str(&res_body).unwrap();
-                    if let Ok(answer) = answer {
-                        ans_vec.push(answer.value);
-                        break;
-                    }
-                }
-            }
-        }
-        ans_vec
+    fn start_operation(&self, op: &Operation) -> Result<(), ProtocolError> {
+        let start = OperationStart {
+            operation: op.clone(),
+            after: self.ended.borrow_mut().take(),
+            roster: self.roster.clone(),
+        };
+
+        let session: String = self.request("start-operation", start)?;
+        *self.session.borrow_mut() = Some(session);
+        Ok(())
     }
 
-    pub fn poll_for_p2p(&self, party_num: u16, n: u16, round: &str) -> Vec<String> {
-        let mut ans_vec = Vec::new();
-        for i in 1..=n {
-            if i != party_num {
-                let key = format!("{}-{}-{}", i, party_num, round);
-                let index = Index { key };
-                loop {
-                    // add delay to allow the server to process request:
-                    thread::sleep(self.retry_delay);
-                    let res_body = &self.postb("get", index.clone()).unwrap();
+    fn end_operation(&self, _op: &Operation) -> Result<(), ProtocolError> {
+        let session = self.session_id()?;
+        self.post("end-operation", &session)?;
+        self.forget();
 
-                    let answer: Result<Entry, ()> = serde_json::from_str(&res_body).unwrap();
-                    if let Ok(answer) = answer {
-                        ans_vec.push(answer.value);
-                        break;
-                    }
-                }
-            }
-        }
-        ans_vec
+        *self.session.borrow_mut() = None;
+        *self.ended.borrow_mut() = Some(session);
+        Ok(())
     }
 
-    pub fn start_operation(&self, op: &Operation) {
-        self.postb("start-operation", op);
+    fn get_current_operation(&self) -> Result<Operation, ProtocolError> {
+        let session = self.session.borrow().clone();
+        let queued: Option<Queued> = self.request("get-operation", session)?;
+        Ok(self.follow(queued))
+    }
+
+    fn await_operation(&self, known: &Operation) -> Result<Operation, ProtocolError> {
+        let wait = OperationWait {
+            session: self.session.borrow().clone(),
+            known: known.clone(),
+            wait_ms: LONG_POLL_WAIT,
+        };
+        let queued: Option<Queued> = self.request("await-operation", wait)?;
+        Ok(self.follow(queued))
     }
 
-    pub fn end_operation(&self, op: &Operation) {
-        self.postb("end-operation", op);
+    fn blame(&self) -> Result<(), ProtocolError> {
+        self.post("blame", self.session_id()?).map(|_| ())
     }
 
-    pub fn get_current_operation(&self) -> Result<Operation, ProtocolError> {
-        match &self.postb("get-operation", 0) {
-            Some(res) => Ok(serde_json::from_str(&res).unwrap()),
-            None => Err(ProtocolError::Connection)
+    fn clear(&self) -> Result<(), ProtocolError> {
+        self.post("clear", 0).map(|_| ())
+    }
+
+    fn session(&self) -> Option<String> {
+        self.session.borrow().clone()
+    }
+
+    fn list_operations(&self) -> Result<Vec<(String, Operation)>, ProtocolError> {
+        let queued: Vec<Queued> = self.request("operations", 0)?;
+
+        Ok(queued
+            .into_iter()
+            .map(|queued| (queued.session, queued.operation))
+            .collect())
+    }
+
+    fn select_operation(&self, session: &str) -> Result<(), ProtocolError> {
+        *self.session.borrow_mut() = Some(session.to_owned());
+        Ok(())
+    }
+
+    fn load_stage(&self, stage: &str) -> Option<String> {
+        self.resumed()?.stages.remove(stage)
+    }
+
+    fn store_stage(&self, stage: &str, data: String) -> Result<(), ProtocolError> {
+        match self.resumed() {
+            Some(mut resume) => {
+                resume.stages.insert(stage.to_owned(), data);
+                self.record(&resume)
+            }
+            None => Ok(()),
         }
     }
 
-    pub fn signup_keygen(&self) -> Result<u16, ProtocolError> {
-        let key = "signup-keygen".to_string();
+    fn publish_key(&self, packets: &[u8]) -> Result<Vec<String>, ProtocolError> {
+        self.request("publish-key", base64::encode(packets))
+    }
 
-        let res_body: String = self.postb("signupkeygen", key).unwrap();
-        let res_body: Result<PartySignup, ()> = serde_json::from_str(&res_body).unwrap();
+    /// Look the certificates up over HKP, like any other keyserver client would
+    fn fetch_key(&self, search: &str) -> Result<Option<Vec<u8>>, ProtocolError> {
+        let url = format!(
+            "{}/pks/lookup?op=get&options=mr&search={}",
+            self.address, search
+        );
+        let res = self
+            .client
+            .get(&url)
+            .send()
+            .map_err(|_| ProtocolError::Connection)?;
 
-        if let Ok(res) = res_body {
-            Ok(res.number)
-        } else {
-            Err(ProtocolError::Full)
+        match res.status() {
+            reqwest::StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => {
+                let armored = res.text().map_err(|_| ProtocolError::Connection)?;
+                pgp::dearmor(&armored)
+                    .map(Some)
+                    .ok_or(ProtocolError::Connection)
+            }
+            _ => Err(ProtocolError::Connection),
         }
     }
 
-    pub fn signup_sign(&self) -> Result<u16, ProtocolError> {
-        let key = "signup-sign".to_string();
+    fn append_log(&self, kind: EntryKind, digest: &str) -> Result<Option<u64>, ProtocolError> {
+        // The server only logs operations that were finalized, under the session they had
+        let session = self.ended.borrow().clone();
+        let append = LogAppend {
+            session: session.ok_or(ProtocolError::Connection)?,
+            kind,
+            digest: digest.to_owned(),
+        };
 
-        let res_body: String = self.postb("signupsign", key).unwrap();
+        self.request("log/append", append).map(Some)
+    }
 
-        let res_body: Result<PartySignup, ()> = serde_json::from_str(&res_body).unwrap();
+    fn tree_head(&self) -> Result<Option<SignedTreeHead>, ProtocolError> {
+        self.request("log/head", 0).map(Some)
+    }
 
-        if let Ok(res) = res_body {
-            Ok(res.number)
-        } else {
-            Err(ProtocolError::Full)
-        }
+    fn find_logged(&self, digest: &str) -> Result<Vec<(u64, LogEntry)>, ProtocolError> {
+        self.request("log/find", digest)
     }
 
-    pub fn signout(&self) -> Result<(), ProtocolError> {
-        let key = "sign".to_owned();
+    fn log_proof(&self, proof: ProofRequest) -> Result<Vec<String>, ProtocolError> {
+        self.request("log/proof", proof)
+    }
+}
 
-        let res_body: String = self.postb("signoutsign", key).unwrap();
-        let res_body: Result<PartySignup, ()> = serde_json::from_str(&res_body).unwrap();
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::testing::temp_dir;
 
-        if let Ok(_) = res_body {
-            Ok(())
-        } else {
-            Err(ProtocolError::Full)
-        }
+    /// Run a key generation between two parties far enough to go through everything the
+    /// protocol needs of a transport, returning the operation, which is left to end
+    pub(crate) fn exchange_round(leader: &dyn Channel, participant: &dyn Channel) -> Operation {
+        let op = Operation::KeyGen {
+            participants: 2,
+            leader: "leader".into(),
+            email: "leader@example.com".into(),
+            epoch: 0,
+        };
+        leader.start_operation(&op).unwrap();
+        assert_eq!(participant.get_current_operation().unwrap(), op);
+
+        assert_eq!(leader.signup_keygen().unwrap(), 1);
+        assert_eq!(participant.signup_keygen().unwrap(), 2);
+        assert!(matches!(leader.signup_keygen(), Err(ProtocolError::Full)));
+        assert_eq!(leader.session(), participant.session());
+
+        // A party that withdraws gets its number back when signing up again
+        participant.signout().unwrap();
+        assert_eq!(participant.claim_keygen(2).unwrap(), 2);
+
+        leader.broadcast(1, "round1", "from leader".into()).unwrap();
+        participant
+            .broadcast(2, "round1", "from participant".into())
+            .unwrap();
+        participant
+            .send_p2p(2, 1, "round2", "secret".into())
+            .unwrap();
+        // Messages cannot be replaced once sent
+        assert!(participant
+            .broadcast(2, "round1", "changed my mind".into())
+            .is_err());
+
+        assert_eq!(
+            leader.poll_for_broadcasts(1, 2, "round1").unwrap(),
+            vec!["from participant".to_owned()]
+        );
+        assert_eq!(
+            participant.poll_for_broadcasts(2, 2, "round1").unwrap(),
+            vec!["from leader".to_owned()]
+        );
+        assert_eq!(
+            leader.poll_for_p2p(1, 2, "round2").unwrap(),
+            vec!["secret".to_owned()]
+        );
+
+        participant.blame().unwrap();
+        assert_eq!(leader.get_current_operation().unwrap(), Operation::Blame {});
+        assert!(matches!(
+            leader.poll_for_p2p(1, 2, "round3"),
+            Err(ProtocolError::Blame)
+        ));
+
+        op
     }
 
-    pub fn clear(&self) {
-        self.postb("clear", 0);
+    #[test]
+    fn only_the_owner_reads_the_resume_record() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = temp_dir("resume");
+        let path = dir.join("resume.json");
+        let channel =
+            HTTPChannel::new("http://localhost".into(), "test".into()).with_resume(path.clone());
+
+        let mut resume = Resume {
+            session: "session".into(),
+            party: 2,
+            stages: BTreeMap::new(),
+        };
+        channel.record(&resume).unwrap();
+        resume.stages.insert("dkg".into(), "secret".into());
+        channel.record(&resume).unwrap();
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert!(fs::read_to_string(&path).unwrap().contains("secret"));
+        assert!(!path.with_extension("tmp").exists());
+
+        fs::remove_dir_all(dir).unwrap();
     }
 
+    #[test]
+    fn client_key_needs_its_certificate() {
+        let tls = TlsConfig {
+            client_key: Some("certs/alice-key.pem".into()),
+            ..TlsConfig::default()
+        };
+
+        assert!(matches!(
+            http_client(Some(&tls), Path::new(".")),
+            Err(CriticalError::User(UserError::ClientKeyWithoutCertificate))
+        ));
+    }
 }
@@ -0,0 +1,410 @@
+use serde::{Deserialize, Serialize};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+
+use super::Channel;
+use crate::errors::ProtocolError;
+use crate::identity::{self, Identity};
+use crate::transparency::{EntryKind, LogEntry, ProofRequest, SignedTreeHead};
+use crate::utils::Operation;
+
+/// Wraps another transport so that every broadcast and point-to-point message carries the
+/// signature of its sender's identity key, and only messages signed by a member of the
+/// roster are ever handed to the protocol.
+///
+/// In a key generation, the member listed `n`-th in the roster is party `n`, so that every
+/// key share belongs to a known person. Signers are whoever signs up first, so there a party
+/// number is bound to the identity of the first message that arrives under it, for the rest
+/// of the operation. Either way, a party that sends under a different identity, or an
+/// identity that shows up under two party numbers, is rejected.
+pub struct AuthenticatedChannel {
+    inner: Box<dyn Channel>,
+    project: String,
+    identity: Identity,
+    roster: Vec<String>,
+    bound: RefCell<HashMap<u16, String>>,
+    /// Whether the operation signed up for is a key generation
+    keygen: Cell<bool>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SignedMessage {
+    identity: String,
+    signature: String,
+    payload: String,
+}
+
+impl AuthenticatedChannel {
+    pub fn new(
+        inner: Box<dyn Channel>,
+        project: String,
+        identity: Identity,
+        roster: Vec<String>,
+    ) -> AuthenticatedChannel {
+        AuthenticatedChannel {
+            inner,
+            project,
+            identity,
+            roster,
+            bound: RefCell::new(HashMap::new()),
+            keygen: Cell::new(false),
+        }
+    }
+
+    /// Everything the signature covers, so that a message cannot be passed off as belonging
+    /// to another project, session, round, sender or recipient
+    fn signed_bytes(&self, round: &str, from: u16, to: Option<u16>, payload: &str) -> Vec<u8> {
+        let session = self.inner.session().unwrap_or_default();
+        let to = to.map_or("*".to_owned(), |to| to.to_string());
+        format!(
+            "{}\n{}\n{}\n{}\n{}\n{}",
+            self.project, session, round, from, to, payload
+        )
+        .into_bytes()
+    }
+
+    fn sign(&self, round: &str, from: u16, to: Option<u16>, payload: String) -> String {
+        let message = SignedMessage {
+            identity: self.identity.public_key(),
+            signature: self
+                .identity
+                .sign(&self.signed_bytes(round, from, to, &payload)),
+            payload,
+        };
+
+        serde_json::to_string(&message).unwrap()
+    }
+
+    fn verify(
+        &self,
+        round: &str,
+        from: u16,
+        to: Option<u16>,
+        data: &str,
+    ) -> Result<String, ProtocolError> {
+        let rejected = || {
+            let _ = self.inner.blame();
+            ProtocolError::Authentication(from, round.to_owned())
+        };
+
+        let message: SignedMessage = serde_json::from_str(data).map_err(|_| rejected())?;
+        if !self.roster.contains(&message.identity) {
+            return Err(rejected());
+        }
+
+        let signed = self.signed_bytes(round, from, to, &message.payload);
+        if !identity::verify(&message.identity, &signed, &message.signature) {
+            return Err(rejected());
+        }
+
+        self.bind(from, &message.identity).map_err(|_| rejected())?;
+        Ok(message.payload)
+    }
+
+    fn bind(&self, party: u16, identity: &str) -> Result<(), ()> {
+        if self.keygen.get() && self.place(identity) != Some(party) {
+            return Err(());
+        }
+        let mut bound = self.bound.borrow_mut();
+
+        match bound.get(&party) {
+            Some(existing) if existing == identity => Ok(()),
+            Some(_) => Err(()),
+            None if bound.values().any(|existing| existing == identity) => Err(()),
+            None => {
+                bound.insert(party, identity.to_owned());
+                Ok(())
+            }
+        }
+    }
+
+    /// Party numbers are handed out anew for every operation
+    fn signed_up(&self, party: u16, keygen: bool) -> u16 {
+        let mut bound = self.bound.borrow_mut();
+        bound.clear();
+        bound.insert(party, self.identity.public_key());
+        self.keygen.set(keygen);
+
+        party
+    }
+
+    /// Place of `identity` in the roster, counting from 1 like party numbers
+    fn place(&self, identity: &str) -> Option<u16> {
+        self.roster
+            .iter()
+            .position(|member| member == identity)
+            .map(|index| index as u16 + 1)
+    }
+}
+
+impl Channel for AuthenticatedChannel {
+    fn signup_keygen(&self) -> Result<u16, ProtocolError> {
+        match self.place(&self.identity.public_key()) {
+            Some(place) => self.claim_keygen(place),
+            None => Err(ProtocolError::Unlisted),
+        }
+    }
+
+    fn signup_sign(&self) -> Result<u16, ProtocolError> {
+        if self.place(&self.identity.public_key()).is_none() {
+            return Err(ProtocolError::Unlisted);
+        }
+
+        self.inner
+            .signup_sign()
+            .map(|party| self.signed_up(party, false))
+    }
+
+    fn claim_keygen(&self, party: u16) -> Result<u16, ProtocolError> {
+        self.inner
+            .claim_keygen(party)
+            .map(|party| self.signed_up(party, true))
+    }
+
+    fn signout(&self) -> Result<(), ProtocolError> {
+        self.inner.signout()
+    }
+
+    fn broadcast(&self, party_num: u16, round: &str, data: String) -> Result<(), ProtocolError> {
+        let signed = self.sign(round, party_num, None, data);
+        self.inner.broadcast(party_num, round, signed)
+    }
+
+    fn send_p2p(
+        &self,
+        party_from: u16,
+        party_to: u16,
+        round: &str,
+        data: String,
+    ) -> Result<(), ProtocolError> {
+        let signed = self.sign(round, party_from, Some(party_to), data);
+        self.inner.send_p2p(party_from, party_to, round, signed)
+    }
+
+    fn poll_for_broadcasts(
+        &self,
+        party_num: u16,
+        n: u16,
+        round: &str,
+    ) -> Result<Vec<String>, ProtocolError> {
+        let messages = self.inner.poll_for_broadcasts(party_num, n, round)?;
+        let senders = (1..=n).filter(|&i| i != party_num);
+
+        senders
+            .zip(messages.iter())
+            .map(|(i, message)| self.verify(round, i, None, message))
+            .collect()
+    }
+
+    fn poll_for_p2p(
+        &self,
+        party_num: u16,
+        n: u16,
+        round: &str,
+    ) -> Result<Vec<String>, ProtocolError> {
+        let messages = self.inner.poll_for_p2p(party_num, n, round)?;
+        let senders = (1..=n).filter(|&i| i != party_num);
+
+        senders
+            .zip(messages.iter())
+            .map(|(i, message)| self.verify(round, i, Some(party_num), message))
+            .collect()
+    }
+
+    fn start_operation(&self, op: &Operation) -> Result<(), ProtocolError> {
+        self.inner.start_operation(op)
+    }
+
+    fn end_operation(&self, op: &Operation) -> Result<(), ProtocolError> {
+        self.inner.end_operation(op)
+    }
+
+    fn get_current_operation(&self) -> Result<Operation, ProtocolError> {
+        self.inner.get_current_operation()
+    }
+
+    fn await_operation(&self, known: &Operation) -> Result<Operation, ProtocolError> {
+        self.inner.await_operation(known)
+    }
+
+    fn session(&self) -> Option<String> {
+        self.inner.session()
+    }
+
+    fn member(&self, party: u16) -> Option<u16> {
+        self.place(self.bound.borrow().get(&party)?)
+    }
+
+    fn list_operations(&self) -> Result<Vec<(String, Operation)>, ProtocolError> {
+        self.inner.list_operations()
+    }
+
+    fn select_operation(&self, session: &str) -> Result<(), ProtocolError> {
+        self.inner.select_operation(session)
+    }
+
+    fn blame(&self) -> Result<(), ProtocolError> {
+        self.inner.blame()
+    }
+
+    fn clear(&self) -> Result<(), ProtocolError> {
+        self.inner.clear()
+    }
+
+    fn load_stage(&self, stage: &str) -> Option<String> {
+        self.inner.load_stage(stage)
+    }
+
+    fn store_stage(&self, stage: &str, data: String) -> Result<(), ProtocolError> {
+        self.inner.store_stage(stage, data)
+    }
+
+    fn publish_key(&self, packets: &[u8]) -> Result<Vec<String>, ProtocolError> {
+        self.inner.publish_key(packets)
+    }
+
+    fn fetch_key(&self, search: &str) -> Result<Option<Vec<u8>>, ProtocolError> {
+        self.inner.fetch_key(search)
+    }
+
+    fn append_log(&self, kind: EntryKind, digest: &str) -> Result<Option<u64>, ProtocolError> {
+        self.inner.append_log(kind, digest)
+    }
+
+    fn tree_head(&self) -> Result<Option<SignedTreeHead>, ProtocolError> {
+        self.inner.tree_head()
+    }
+
+    fn find_logged(&self, digest: &str) -> Result<Vec<(u64, LogEntry)>, ProtocolError> {
+        self.inner.find_logged(digest)
+    }
+
+    fn log_proof(&self, proof: ProofRequest) -> Result<Vec<String>, ProtocolError> {
+        self.inner.log_proof(proof)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::directory::DirectoryChannel;
+    use super::*;
+    use crate::testing::temp_dir;
+    use std::path::Path;
+    use std::time;
+
+    fn party(root: &Path, name: &str, roster: &[String]) -> AuthenticatedChannel {
+        let inner = DirectoryChannel::new(
+            root.join("shared"),
+            "test".into(),
+            time::Duration::from_millis(10),
+        );
+        let identity = Identity::load_or_create(&root.join(name)).unwrap();
+
+        AuthenticatedChannel::new(Box::new(inner), "test".into(), identity, roster.to_vec())
+    }
+
+    #[test]
+    fn rejects_parties_outside_the_roster() {
+        let root = temp_dir("authenticated");
+        let members: Vec<String> = ["leader", "member"]
+            .iter()
+            .map(|name| {
+                Identity::load_or_create(&root.join(name))
+                    .unwrap()
+                    .public_key()
+            })
+            .collect();
+
+        let leader = party(&root, "leader", &members);
+        let member = party(&root, "member", &members);
+        let outsider = party(&root, "outsider", &members);
+
+        let op = Operation::KeyGen {
+            participants: 3,
+            leader: "leader".into(),
+            email: "leader@example.com".into(),
+            epoch: 0,
+        };
+        leader.start_operation(&op).unwrap();
+        assert_eq!(leader.signup_keygen().unwrap(), 1);
+        assert_eq!(member.signup_keygen().unwrap(), 2);
+        assert!(matches!(
+            outsider.signup_keygen(),
+            Err(ProtocolError::Unlisted)
+        ));
+        // Which does not stop it from taking the free slot by itself
+        assert_eq!(outsider.claim_keygen(3).unwrap(), 3);
+
+        leader.broadcast(1, "round1", "from leader".into()).unwrap();
+        member.broadcast(2, "round1", "from member".into()).unwrap();
+        outsider
+            .broadcast(3, "round1", "from outsider".into())
+            .unwrap();
+        leader.send_p2p(1, 2, "round2", "to member".into()).unwrap();
+
+        // The outsider can read what is not encrypted, but nobody accepts its messages
+        assert_eq!(
+            outsider.poll_for_broadcasts(3, 2, "round1").unwrap(),
+            vec!["from leader".to_owned(), "from member".to_owned()]
+        );
+        assert!(matches!(
+            member.poll_for_broadcasts(2, 3, "round1"),
+            Err(ProtocolError::Authentication(3, _))
+        ));
+
+        assert_eq!(
+            member.poll_for_p2p(2, 2, "round2").unwrap(),
+            vec!["to member".to_owned()]
+        );
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn generates_key_shares_in_roster_order() {
+        let root = temp_dir("roster");
+        let members: Vec<String> = ["first", "second"]
+            .iter()
+            .map(|name| {
+                Identity::load_or_create(&root.join(name))
+                    .unwrap()
+                    .public_key()
+            })
+            .collect();
+
+        let first = party(&root, "first", &members);
+        let second = party(&root, "second", &members);
+
+        let op = Operation::KeyGen {
+            participants: 2,
+            leader: "second".into(),
+            email: "second@example.com".into(),
+            epoch: 0,
+        };
+        second.start_operation(&op).unwrap();
+        // Whoever comes first, the places in the roster decide the party numbers
+        assert_eq!(second.signup_keygen().unwrap(), 2);
+        assert_eq!(first.signup_keygen().unwrap(), 1);
+
+        second.broadcast(2, "round1", "from second".into()).unwrap();
+        assert_eq!(
+            first.poll_for_broadcasts(1, 2, "round1").unwrap(),
+            vec!["from second".to_owned()]
+        );
+        assert_eq!(first.member(2), Some(2));
+
+        // Members can only send under their own place
+        let forged = first.sign("round2", 2, None, "from first".into());
+        assert!(first.verify("round2", 2, None, &forged).is_err());
+
+        // Nor can their messages be replayed into a later operation
+        let genuine = first.sign("round2", 1, None, "from first".into());
+        assert!(second.verify("round2", 1, None, &genuine).is_ok());
+        second.end_operation(&op).unwrap();
+        second.start_operation(&op).unwrap();
+        assert_eq!(second.signup_keygen().unwrap(), 2);
+        assert!(second.verify("round2", 1, None, &genuine).is_err());
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,121 @@
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::errors::ProtocolError;
+
+/// Set once the user asks for the running operation to stop (with Ctrl-C)
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+
+/// Make every transport give up at its next check for messages
+pub fn cancel() {
+    CANCELLED.store(true, Ordering::SeqCst);
+}
+
+pub fn is_cancelled() -> bool {
+    CANCELLED.load(Ordering::SeqCst)
+}
+
+/// How long a transport waits for the other participants, with `None` waiting indefinitely
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Timeouts {
+    /// Limit on waiting for the messages of any single round
+    pub round: Option<Duration>,
+    /// Limit on the whole operation, counted from signing up for it
+    pub operation: Option<Duration>,
+}
+
+/// Deadlines of the operation a transport is taking part in
+pub struct Deadlines {
+    timeouts: Timeouts,
+    started: Cell<Option<Instant>>,
+}
+
+impl Deadlines {
+    pub fn new(timeouts: Timeouts) -> Deadlines {
+        Deadlines {
+            timeouts,
+            started: Cell::new(None),
+        }
+    }
+
+    /// Start counting towards the deadline of the operation
+    pub fn start(&self) {
+        self.started.set(Some(Instant::now()));
+    }
+
+    /// Deadline of a round whose messages are awaited from now on, which is cut short by the
+    /// deadline of the operation
+    pub fn round(&self) -> Wait {
+        let now = Instant::now();
+        let round = self.timeouts.round.map(|timeout| now + timeout);
+        let operation = match (self.started.get(), self.timeouts.operation) {
+            (Some(started), Some(timeout)) => Some(started + timeout),
+            _ => None,
+        };
+
+        Wait {
+            until: match (round, operation) {
+                (Some(round), Some(operation)) => Some(round.min(operation)),
+                (round, operation) => round.or(operation),
+            },
+        }
+    }
+}
+
+pub struct Wait {
+    until: Option<Instant>,
+}
+
+impl Wait {
+    /// Fail once the deadline has passed, reporting the parties whose messages for `round` are
+    /// still `missing`, or once the user has cancelled the operation
+    pub fn check(&self, round: &str, missing: Vec<u16>) -> Result<(), ProtocolError> {
+        if is_cancelled() {
+            return Err(ProtocolError::Cancelled);
+        }
+
+        match self.until {
+            Some(until) if Instant::now() >= until => {
+                Err(ProtocolError::Timeout(round.to_owned(), missing))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Time left until the deadline, for transports that block while waiting
+    pub fn remaining(&self) -> Option<Duration> {
+        self.until
+            .map(|until| until.saturating_duration_since(Instant::now()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rounds_end_with_the_operation() {
+        let unbounded = Deadlines::new(Timeouts::default());
+        unbounded.start();
+        assert!(unbounded.round().check("sign-round1", vec![2]).is_ok());
+        assert_eq!(unbounded.round().remaining(), None);
+
+        let deadlines = Deadlines::new(Timeouts {
+            round: Some(Duration::from_secs(600)),
+            operation: Some(Duration::from_secs(0)),
+        });
+        // The operation only counts from the signup
+        assert!(deadlines.round().remaining().unwrap() > Duration::from_secs(500));
+
+        deadlines.start();
+        match deadlines.round().check("sign-round1", vec![2, 3]) {
+            Err(ProtocolError::Timeout(round, missing)) => {
+                assert_eq!(round, "sign-round1");
+                assert_eq!(missing, vec![2, 3]);
+            }
+            _ => panic!("the operation should have timed out"),
+        }
+    }
+}
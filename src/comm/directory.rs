@@ -0,0 +1,398 @@
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::{thread, time};
+
+use super::deadline::{Deadlines, Timeouts};
+use super::{is_round_name, keygen_slots, unique_suffix, Channel, Mailbox, Session};
+use crate::errors::ProtocolError;
+use crate::utils::Operation;
+
+/// Locks older than this are assumed to have been left behind by a crashed participant
+const STALE_LOCK: time::Duration = time::Duration::from_secs(30);
+
+/// Transport exchanging round messages as files in a shared directory, laid out as
+/// `<path>/<project>/<session>/<round>/<from>[-<to>]`, next to the `operation` file
+/// describing the pending operation.
+///
+/// Every file is written to a temporary name, synced, and then hard-linked into place, so
+/// readers never observe partial messages and two parties can never both create the same
+/// file. Starting, ending and clearing operations additionally take a lock file. None of
+/// this relies on more than `O_EXCL` and `link(2)` semantics, so the directory can live on
+/// NFS, or on a USB stick that gets carried between machines.
+pub struct DirectoryChannel {
+    root: PathBuf,
+    poll_interval: time::Duration,
+    session: RefCell<Option<Session>>,
+    deadlines: Deadlines,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PendingOperation {
+    session: String,
+    operation: Operation,
+}
+
+/// Held while the project-wide state is being changed, and released on drop
+struct Lock {
+    path: PathBuf,
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+impl DirectoryChannel {
+    pub fn new(path: PathBuf, project: String, poll_interval: time::Duration) -> DirectoryChannel {
+        DirectoryChannel {
+            root: path.join(project),
+            poll_interval,
+            session: RefCell::new(None),
+            deadlines: Deadlines::new(Timeouts::default()),
+        }
+    }
+
+    /// Give up on participants that take longer than `timeouts` to send their messages
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> DirectoryChannel {
+        self.deadlines = Deadlines::new(timeouts);
+        self
+    }
+
+    fn operation_file(&self) -> PathBuf {
+        self.root.join("operation")
+    }
+
+    /// Where the message under `path` of session `id` lives. Both come from the directory or
+    /// the protocol, and may only name a place inside the directory of the session.
+    fn entry_path(&self, id: &str, path: &str) -> Result<PathBuf, ProtocolError> {
+        let mut components = std::iter::once(id).chain(path.split('/'));
+        if !components.all(is_round_name) {
+            return Err(ProtocolError::Connection);
+        }
+
+        Ok(self.root.join(id).join(path))
+    }
+
+    /// Atomically create `path` with the given contents, returning whether it was us who
+    /// created it
+    fn write_new(&self, path: &Path, data: &[u8]) -> Result<bool, ProtocolError> {
+        let dir = path.parent().ok_or(ProtocolError::Connection)?;
+        fs::create_dir_all(dir).map_err(|_| ProtocolError::Connection)?;
+
+        let temp = dir.join(format!(".tmp-{}", unique_suffix()));
+        let written = File::create(&temp)
+            .and_then(|mut file| {
+                file.write_all(data)?;
+                file.sync_all()
+            })
+            .and_then(|_| fs::hard_link(&temp, path));
+        let _ = fs::remove_file(&temp);
+
+        match written {
+            Ok(_) => {
+                sync_dir(dir).map_err(|_| ProtocolError::Connection)?;
+                Ok(true)
+            }
+            Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => Ok(false),
+            Err(_) => Err(ProtocolError::Connection),
+        }
+    }
+
+    fn lock(&self) -> Result<Lock, ProtocolError> {
+        fs::create_dir_all(&self.root).map_err(|_| ProtocolError::Connection)?;
+        let path = self.root.join("lock");
+
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    let _ = file.write_all(unique_suffix().as_bytes());
+                    return Ok(Lock { path });
+                }
+                Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                    let stale = fs::metadata(&path)
+                        .and_then(|meta| meta.modified())
+                        .ok()
+                        .and_then(|modified| modified.elapsed().ok())
+                        .map(|age| age > STALE_LOCK)
+                        .unwrap_or(false);
+                    if stale {
+                        let _ = fs::remove_file(&path);
+                    } else {
+                        thread::sleep(self.poll_interval);
+                    }
+                }
+                Err(_) => return Err(ProtocolError::Connection),
+            }
+        }
+    }
+
+    fn current(&self) -> Result<Option<PendingOperation>, ProtocolError> {
+        match fs::read_to_string(self.operation_file()) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .map(Some)
+                .map_err(|_| ProtocolError::Connection),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(_) => Err(ProtocolError::Connection),
+        }
+    }
+
+    /// Sign up for the pending key generation, as `party` when given
+    fn signup_keygen_as(&self, party: Option<u16>) -> Result<u16, ProtocolError> {
+        match self.current()? {
+            Some(PendingOperation {
+                session,
+                operation: Operation::KeyGen { participants, .. },
+            }) => match keygen_slots(participants, party) {
+                Some(slots) => self.signup(session, slots),
+                None => Err(ProtocolError::Full),
+            },
+            _ => Err(ProtocolError::Full),
+        }
+    }
+}
+
+impl Mailbox for DirectoryChannel {
+    fn session_state(&self) -> &RefCell<Option<Session>> {
+        &self.session
+    }
+
+    fn deadlines(&self) -> &Deadlines {
+        &self.deadlines
+    }
+
+    fn poll_interval(&self) -> time::Duration {
+        self.poll_interval
+    }
+
+    /// Everything is read straight from the directory
+    fn refresh(&self) -> Result<(), ProtocolError> {
+        Ok(())
+    }
+
+    fn create(&self, id: &str, path: &str, data: &str) -> Result<bool, ProtocolError> {
+        self.write_new(&self.entry_path(id, path)?, data.as_bytes())
+    }
+
+    fn read(&self, id: &str, path: &str) -> Result<Option<String>, ProtocolError> {
+        match fs::read_to_string(self.entry_path(id, path)?) {
+            Ok(contents) => Ok(Some(contents)),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(_) => Err(ProtocolError::Connection),
+        }
+    }
+
+    fn remove(&self, id: &str, path: &str) -> Result<(), ProtocolError> {
+        fs::remove_file(self.entry_path(id, path)?).map_err(|_| ProtocolError::Connection)
+    }
+
+    fn any_below(&self, id: &str, dir: &str) -> Result<bool, ProtocolError> {
+        Ok(fs::read_dir(self.entry_path(id, dir)?)
+            .map(|mut entries| entries.next().is_some())
+            .unwrap_or(false))
+    }
+}
+
+impl Channel for DirectoryChannel {
+    fn signup_keygen(&self) -> Result<u16, ProtocolError> {
+        self.signup_keygen_as(None)
+    }
+
+    fn signup_sign(&self) -> Result<u16, ProtocolError> {
+        let pending = match self.current()? {
+            Some(pending) => pending,
+            None => return Err(ProtocolError::Full),
+        };
+
+        let threshold = match pending.operation {
+            Operation::SignTag { threshold, .. } => threshold,
+            Operation::SignKey { threshold, .. } => threshold,
+            Operation::SignStatement { threshold, .. } => threshold,
+            _ => return Err(ProtocolError::Full),
+        };
+
+        self.signup(pending.session, 1..=threshold + 1)
+    }
+
+    fn claim_keygen(&self, party: u16) -> Result<u16, ProtocolError> {
+        self.signup_keygen_as(Some(party))
+    }
+
+    fn signout(&self) -> Result<(), ProtocolError> {
+        self.signout_party()
+    }
+
+    fn broadcast(&self, party_num: u16, round: &str, data: String) -> Result<(), ProtocolError> {
+        self.post(&format!("{}/{}", round, party_num), &data)
+    }
+
+    fn send_p2p(
+        &self,
+        party_from: u16,
+        party_to: u16,
+        round: &str,
+        data: String,
+    ) -> Result<(), ProtocolError> {
+        self.post(&format!("{}/{}-{}", round, party_from, party_to), &data)
+    }
+
+    fn poll_for_broadcasts(
+        &self,
+        party_num: u16,
+        n: u16,
+        round: &str,
+    ) -> Result<Vec<String>, ProtocolError> {
+        self.collect_broadcasts(party_num, n, round)
+    }
+
+    fn poll_for_p2p(
+        &self,
+        party_num: u16,
+        n: u16,
+        round: &str,
+    ) -> Result<Vec<String>, ProtocolError> {
+        self.collect_p2p(party_num, n, round)
+    }
+
+    fn start_operation(&self, op: &Operation) -> Result<(), ProtocolError> {
+        let _lock = self.lock()?;
+
+        // Unlike the server, which queues operations, this channel runs one at a time, and
+        // another operation still being in progress is not an error
+        if self.current()?.is_some() {
+            return Ok(());
+        }
+
+        let pending = PendingOperation {
+            session: unique_suffix(),
+            operation: op.clone(),
+        };
+        let data = serde_json::to_vec(&pending).map_err(|_| ProtocolError::Connection)?;
+
+        if self.write_new(&self.operation_file(), &data)? {
+            *self.session.borrow_mut() = Some(Session {
+                id: pending.session,
+                party: None,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn end_operation(&self, _op: &Operation) -> Result<(), ProtocolError> {
+        let _lock = self.lock()?;
+        let id = match self.session_id() {
+            Ok(id) => id,
+            Err(_) => return Ok(()),
+        };
+
+        // Only remove the operation we have been part of, not one that has replaced it
+        match self.current()? {
+            Some(pending) if pending.session == id => {
+                fs::remove_file(self.operation_file()).map_err(|_| ProtocolError::Connection)?;
+                sync_dir(&self.root).map_err(|_| ProtocolError::Connection)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn get_current_operation(&self) -> Result<Operation, ProtocolError> {
+        match self.current()? {
+            Some(pending) if self.is_blamed(&pending.session)? => Ok(Operation::Blame {}),
+            Some(pending) => Ok(pending.operation),
+            None => Ok(Operation::Idle),
+        }
+    }
+
+    fn await_operation(&self, _known: &Operation) -> Result<Operation, ProtocolError> {
+        thread::sleep(self.poll_interval);
+        self.get_current_operation()
+    }
+
+    fn session(&self) -> Option<String> {
+        self.session_id().ok()
+    }
+
+    fn blame(&self) -> Result<(), ProtocolError> {
+        self.blame_session()
+    }
+
+    fn clear(&self) -> Result<(), ProtocolError> {
+        let _lock = self.lock()?;
+
+        // This removes the messages of every past session right away, rather than once they
+        // expire like on the server, but it leaves any operation started in the meantime alone
+        let pending = self.current()?.map(|pending| pending.session);
+        let entries = fs::read_dir(&self.root).map_err(|_| ProtocolError::Connection)?;
+
+        for entry in entries {
+            let entry = entry.map_err(|_| ProtocolError::Connection)?;
+            let is_dir = entry.file_type().map(|kind| kind.is_dir()).unwrap_or(false);
+
+            if is_dir && Some(entry.file_name().to_string_lossy().into_owned()) != pending {
+                fs::remove_dir_all(entry.path()).map_err(|_| ProtocolError::Connection)?;
+            }
+        }
+
+        sync_dir(&self.root).map_err(|_| ProtocolError::Connection)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::comm::test::exchange_round;
+    use crate::testing::temp_dir;
+
+    #[test]
+    fn exchange_through_shared_directory() {
+        let root = temp_dir("directory");
+        let interval = time::Duration::from_millis(10);
+        let leader = DirectoryChannel::new(root.clone(), "test".into(), interval);
+        let participant = DirectoryChannel::new(root.clone(), "test".into(), interval);
+
+        let op = exchange_round(&leader, &participant);
+
+        // Every message is a file of its own, and no lock or temporary file stays behind
+        let session = root.join("test").join(participant.session().unwrap());
+        assert_eq!(
+            fs::read_to_string(session.join("round1").join("1")).unwrap(),
+            "from leader"
+        );
+        assert_eq!(
+            fs::read_to_string(session.join("round2").join("2-1")).unwrap(),
+            "secret"
+        );
+        let mut names: Vec<String> = fs::read_dir(session.join("round1"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["1", "2"]);
+        assert!(!root.join("test").join("lock").exists());
+
+        // Nor does anything get written outside of the directory of the session
+        assert!(matches!(
+            leader.broadcast(1, "../../escaped", "from leader".into()),
+            Err(ProtocolError::Connection)
+        ));
+        assert!(!root.join("escaped").exists());
+
+        leader.end_operation(&op).unwrap();
+        leader.clear().unwrap();
+        assert_eq!(
+            participant.get_current_operation().unwrap(),
+            Operation::Idle
+        );
+        assert_eq!(fs::read_dir(root.join("test")).unwrap().count(), 0);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
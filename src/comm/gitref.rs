@@ -0,0 +1,454 @@
+use std::cell::RefCell;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+use std::{thread, time};
+
+use super::deadline::{Deadlines, Timeouts};
+use super::{keygen_slots, Channel, Mailbox, Session};
+use crate::errors::ProtocolError;
+use crate::utils::Operation;
+
+const GIT: &str = "git";
+
+/// Asynchronous transport that stores every protocol message as a blob in a shared git
+/// remote, under `refs/dit/<project>/<session>/<round>/<from>[-<to>]`.
+///
+/// Participants do not need to be online at the same time: a round only completes once
+/// everyone has pushed their message, and the polling interval can be set to hours if
+/// need be. The pending operation itself lives at `refs/dit/<project>/operation`, and the
+/// object ID of its blob doubles as the session identifier.
+///
+/// Refs are created with `--force-with-lease=<ref>:`, so that the remote rejects the push
+/// if someone got there first, which is what makes party signups and operation starts
+/// race-free without any coordination beyond the remote itself.
+pub struct GitRefChannel {
+    repo: PathBuf,
+    remote: String,
+    project: String,
+    poll_interval: time::Duration,
+    session: RefCell<Option<Session>>,
+    deadlines: Deadlines,
+}
+
+impl GitRefChannel {
+    pub fn new(
+        repo: PathBuf,
+        remote: String,
+        project: String,
+        poll_interval: time::Duration,
+    ) -> GitRefChannel {
+        GitRefChannel {
+            repo,
+            remote,
+            project,
+            poll_interval,
+            session: RefCell::new(None),
+            deadlines: Deadlines::new(Timeouts::default()),
+        }
+    }
+
+    /// Give up on participants that take longer than `timeouts` to push their messages
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> GitRefChannel {
+        self.deadlines = Deadlines::new(timeouts);
+        self
+    }
+
+    fn git(&self, args: &[&str]) -> Result<Output, ProtocolError> {
+        Command::new(GIT)
+            .arg("-C")
+            .arg(&self.repo)
+            .args(args)
+            .output()
+            .map_err(|_| ProtocolError::Connection)
+    }
+
+    fn operation_ref(&self) -> String {
+        format!("refs/dit/{}/operation", self.project)
+    }
+
+    fn session_ref(&self, id: &str, path: &str) -> String {
+        format!("refs/dit/{}/{}/{}", self.project, id, path)
+    }
+
+    /// Mirror the remote state of the project into the local repository. Pruning takes care
+    /// of operations and sessions that have been cleared in the meantime.
+    fn fetch(&self) -> Result<(), ProtocolError> {
+        let refspec = format!("+refs/dit/{0}/*:refs/dit/{0}/*", self.project);
+        let fetch = self.git(&["fetch", "--quiet", "--prune", &self.remote, &refspec])?;
+
+        if fetch.status.success() {
+            Ok(())
+        } else {
+            Err(ProtocolError::Connection)
+        }
+    }
+
+    fn resolve(&self, name: &str) -> Result<Option<String>, ProtocolError> {
+        let resolved = self.git(&["rev-parse", "--verify", "--quiet", name])?;
+
+        if resolved.status.success() {
+            Ok(Some(
+                String::from_utf8_lossy(&resolved.stdout).trim().to_owned(),
+            ))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn read_blob(&self, oid: &str) -> Result<String, ProtocolError> {
+        let blob = self.git(&["cat-file", "blob", oid])?;
+
+        if blob.status.success() {
+            String::from_utf8(blob.stdout).map_err(|_| ProtocolError::Connection)
+        } else {
+            Err(ProtocolError::Connection)
+        }
+    }
+
+    fn write_blob(&self, data: &str) -> Result<String, ProtocolError> {
+        let mut hash = Command::new(GIT)
+            .arg("-C")
+            .arg(&self.repo)
+            .args(["hash-object", "-w", "--stdin"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|_| ProtocolError::Connection)?;
+
+        hash.stdin
+            .take()
+            .ok_or(ProtocolError::Connection)?
+            .write_all(data.as_bytes())
+            .map_err(|_| ProtocolError::Connection)?;
+
+        let output = hash
+            .wait_with_output()
+            .map_err(|_| ProtocolError::Connection)?;
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+    }
+
+    /// Point `name` at a new blob containing `data`, both locally and on the remote, but only
+    /// if the ref does not exist yet. Returns the object ID on success and `None` if the
+    /// ref had already been taken.
+    fn create_ref(&self, name: &str, data: &str) -> Result<Option<String>, ProtocolError> {
+        let oid = self.write_blob(data)?;
+
+        let lease = format!("--force-with-lease={}:", name);
+        let refspec = format!("{}:{}", oid, name);
+        let push = self.git(&["push", "--quiet", &lease, &self.remote, &refspec])?;
+
+        if !push.status.success() {
+            return Ok(None);
+        }
+
+        self.git(&["update-ref", name, &oid])?;
+        Ok(Some(oid))
+    }
+
+    /// Sign up for the pending key generation, as `party` when given
+    fn signup_keygen_as(&self, party: Option<u16>) -> Result<u16, ProtocolError> {
+        self.fetch()?;
+        let (id, operation) = match self.current_session()? {
+            Some(session) => session,
+            None => return Err(ProtocolError::Full),
+        };
+
+        match operation {
+            Operation::KeyGen { participants, .. } => match keygen_slots(participants, party) {
+                Some(slots) => self.signup(id, slots),
+                None => Err(ProtocolError::Full),
+            },
+            _ => Err(ProtocolError::Full),
+        }
+    }
+
+    fn current_session(&self) -> Result<Option<(String, Operation)>, ProtocolError> {
+        match self.resolve(&self.operation_ref())? {
+            Some(oid) => {
+                let operation = serde_json::from_str(&self.read_blob(&oid)?)
+                    .map_err(|_| ProtocolError::Connection)?;
+                Ok(Some((oid, operation)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Delete the given refs on the remote, and prune them locally
+    fn delete_refs(&self, names: &[String]) -> Result<(), ProtocolError> {
+        if names.is_empty() {
+            return Ok(());
+        }
+
+        let refspecs: Vec<String> = names.iter().map(|name| format!(":{}", name)).collect();
+        let mut args = vec!["push", "--quiet", &self.remote];
+        args.extend(refspecs.iter().map(|refspec| refspec.as_str()));
+        let push = self.git(&args)?;
+
+        if push.status.success() {
+            self.fetch()
+        } else {
+            Err(ProtocolError::Connection)
+        }
+    }
+
+    fn list_refs(&self, prefix: &str) -> Result<Vec<String>, ProtocolError> {
+        let refs = self.git(&["for-each-ref", "--format=%(refname)", prefix])?;
+
+        Ok(String::from_utf8_lossy(&refs.stdout)
+            .lines()
+            .map(|name| name.to_owned())
+            .collect())
+    }
+}
+
+impl Mailbox for GitRefChannel {
+    fn session_state(&self) -> &RefCell<Option<Session>> {
+        &self.session
+    }
+
+    fn deadlines(&self) -> &Deadlines {
+        &self.deadlines
+    }
+
+    fn poll_interval(&self) -> time::Duration {
+        self.poll_interval
+    }
+
+    fn refresh(&self) -> Result<(), ProtocolError> {
+        self.fetch()
+    }
+
+    /// Signup claims are made unique by the caller, so that two parties never push the same
+    /// object, which the remote would accept twice
+    fn create(&self, id: &str, path: &str, data: &str) -> Result<bool, ProtocolError> {
+        let name = self.session_ref(id, path);
+        if self.resolve(&name)?.is_some() {
+            return Ok(false);
+        }
+
+        Ok(self.create_ref(&name, data)?.is_some())
+    }
+
+    fn read(&self, id: &str, path: &str) -> Result<Option<String>, ProtocolError> {
+        match self.resolve(&self.session_ref(id, path))? {
+            Some(oid) => self.read_blob(&oid).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn remove(&self, id: &str, path: &str) -> Result<(), ProtocolError> {
+        self.delete_refs(&[self.session_ref(id, path)])
+    }
+
+    fn any_below(&self, id: &str, dir: &str) -> Result<bool, ProtocolError> {
+        Ok(!self.list_refs(&self.session_ref(id, dir))?.is_empty())
+    }
+}
+
+impl Channel for GitRefChannel {
+    fn signup_keygen(&self) -> Result<u16, ProtocolError> {
+        self.signup_keygen_as(None)
+    }
+
+    fn signup_sign(&self) -> Result<u16, ProtocolError> {
+        self.fetch()?;
+        let (id, operation) = match self.current_session()? {
+            Some(session) => session,
+            None => return Err(ProtocolError::Full),
+        };
+
+        let threshold = match operation {
+            Operation::SignTag { threshold, .. } => threshold,
+            Operation::SignKey { threshold, .. } => threshold,
+            Operation::SignStatement { threshold, .. } => threshold,
+            _ => return Err(ProtocolError::Full),
+        };
+
+        self.signup(id, 1..=threshold + 1)
+    }
+
+    fn claim_keygen(&self, party: u16) -> Result<u16, ProtocolError> {
+        self.signup_keygen_as(Some(party))
+    }
+
+    fn signout(&self) -> Result<(), ProtocolError> {
+        self.signout_party()
+    }
+
+    fn broadcast(&self, party_num: u16, round: &str, data: String) -> Result<(), ProtocolError> {
+        self.post(&format!("{}/{}", round, party_num), &data)
+    }
+
+    fn send_p2p(
+        &self,
+        party_from: u16,
+        party_to: u16,
+        round: &str,
+        data: String,
+    ) -> Result<(), ProtocolError> {
+        self.post(&format!("{}/{}-{}", round, party_from, party_to), &data)
+    }
+
+    fn poll_for_broadcasts(
+        &self,
+        party_num: u16,
+        n: u16,
+        round: &str,
+    ) -> Result<Vec<String>, ProtocolError> {
+        self.collect_broadcasts(party_num, n, round)
+    }
+
+    fn poll_for_p2p(
+        &self,
+        party_num: u16,
+        n: u16,
+        round: &str,
+    ) -> Result<Vec<String>, ProtocolError> {
+        self.collect_p2p(party_num, n, round)
+    }
+
+    fn start_operation(&self, op: &Operation) -> Result<(), ProtocolError> {
+        self.fetch()?;
+        let data = serde_json::to_string(op).map_err(|_| ProtocolError::Connection)?;
+
+        // Unlike the server, which queues operations, this channel runs one at a time, and
+        // another operation still being in progress is not an error
+        if let Some(id) = self.create_ref(&self.operation_ref(), &data)? {
+            *self.session.borrow_mut() = Some(Session { id, party: None });
+        }
+
+        Ok(())
+    }
+
+    fn end_operation(&self, _op: &Operation) -> Result<(), ProtocolError> {
+        let id = match self.session_id() {
+            Ok(id) => id,
+            Err(_) => return Ok(()),
+        };
+
+        // Only remove the operation we have been part of, not one that has replaced it
+        let lease = format!("--force-with-lease={}:{}", self.operation_ref(), id);
+        let refspec = format!(":{}", self.operation_ref());
+        let push = self.git(&["push", "--quiet", &lease, &self.remote, &refspec])?;
+
+        if push.status.success() {
+            self.fetch()
+        } else {
+            Err(ProtocolError::Connection)
+        }
+    }
+
+    fn get_current_operation(&self) -> Result<Operation, ProtocolError> {
+        self.fetch()?;
+
+        match self.current_session()? {
+            Some((id, _)) if self.is_blamed(&id)? => Ok(Operation::Blame {}),
+            Some((_, operation)) => Ok(operation),
+            None => Ok(Operation::Idle),
+        }
+    }
+
+    /// Fetching is the expensive part, so the remote is only checked every `poll_interval`
+    fn await_operation(&self, _known: &Operation) -> Result<Operation, ProtocolError> {
+        thread::sleep(self.poll_interval);
+        self.get_current_operation()
+    }
+
+    fn session(&self) -> Option<String> {
+        self.session_id().ok()
+    }
+
+    fn blame(&self) -> Result<(), ProtocolError> {
+        self.blame_session()
+    }
+
+    fn clear(&self) -> Result<(), ProtocolError> {
+        self.fetch()?;
+
+        // This removes the messages of every past session right away, rather than once they
+        // expire like on the server, but it leaves any operation started in the meantime alone
+        let pending = match self.current_session()? {
+            Some((id, _)) => format!("refs/dit/{}/{}/", self.project, id),
+            None => self.operation_ref(),
+        };
+        let stale: Vec<String> = self
+            .list_refs(&format!("refs/dit/{}/", self.project))?
+            .into_iter()
+            .filter(|name| *name != self.operation_ref() && !name.starts_with(&pending))
+            .collect();
+
+        self.delete_refs(&stale)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::comm::test::exchange_round;
+    use crate::testing::temp_dir;
+
+    fn init(path: &PathBuf, bare: bool) {
+        let mut init = Command::new(GIT);
+        init.args(["init", "--quiet"]);
+        if bare {
+            init.arg("--bare");
+        }
+        assert!(init.arg(path).status().unwrap().success());
+    }
+
+    fn remote_refs(remote: &str) -> Vec<(String, String)> {
+        let output = Command::new(GIT)
+            .args(["ls-remote", remote, "refs/dit/*"])
+            .output()
+            .unwrap();
+
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| {
+                let (oid, name) = line.split_once('\t')?;
+                Some((name.to_owned(), oid.to_owned()))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn exchange_through_bare_remote() {
+        let root = temp_dir("gitref");
+        let remote = root.join("remote.git");
+        let (first, second) = (root.join("first"), root.join("second"));
+        init(&remote, true);
+        init(&first, false);
+        init(&second, false);
+
+        let remote = remote.to_string_lossy().into_owned();
+        let interval = time::Duration::from_millis(10);
+        let leader = GitRefChannel::new(first, remote.clone(), "test".into(), interval);
+        let participant = GitRefChannel::new(second, remote.clone(), "test".into(), interval);
+
+        let op = exchange_round(&leader, &participant);
+
+        // Everything lives in the remote, with the blob of the operation naming the session
+        let refs = remote_refs(&remote);
+        let session = participant.session().unwrap();
+        assert!(refs.contains(&("refs/dit/test/operation".to_owned(), session.clone())));
+        for name in ["round1/1", "round1/2", "round2/2-1", "signup/1", "signup/2"] {
+            let name = format!("refs/dit/test/{}/{}", session, name);
+            assert!(
+                refs.iter().any(|(existing, _)| *existing == name),
+                "{}",
+                name
+            );
+        }
+
+        leader.end_operation(&op).unwrap();
+        leader.clear().unwrap();
+        assert_eq!(
+            participant.get_current_operation().unwrap(),
+            Operation::Idle
+        );
+        assert!(remote_refs(&remote).is_empty());
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,660 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::{is_round_name, unique_suffix, Channel};
+use crate::errors::{CriticalError, ProtocolError, Result, UserError};
+use crate::utils::Operation;
+
+pub const ROUND_FORMAT: &str = "dit-round";
+pub const ROUND_VERSION: u8 = 1;
+/// Every chunk line starts with this, followed by the version of the format
+const CHUNK_PREFIX: &str = "DIT";
+/// Comfortably below the 2953 bytes a version 40 QR code holds at the lowest error correction
+pub const DEFAULT_CHUNK_SIZE: usize = 1200;
+
+/// Transport for participants that never touch a network: the protocol runs until it needs
+/// messages that have not been imported yet, at which point polling fails with
+/// `ProtocolError::Pending` and the round is carried over by `dit round export` and
+/// `dit round import`.
+///
+/// Everything lives under a single directory (`.dit/offline`):
+///  - `operation.json`, the operation this participant is part of
+///  - `messages/<round>/<from>[-<to>]`, our own messages and the ones imported so far
+///  - `stages/<stage>.json`, the results of stages that drew fresh randomness, so that
+///    rerunning the protocol on the next import sends exactly the same messages again
+///
+/// As there is no one to coordinate with, a participant signs up under the lowest party
+/// number none of the imported messages come from, unless told otherwise.
+pub struct OfflineChannel {
+    dir: PathBuf,
+    project: String,
+    index: Option<u16>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OfflineOperation {
+    pub session: String,
+    pub operation: Operation,
+    /// Whether we have started the operation, and are thus the ones producing the result
+    pub leader: bool,
+    pub party: Option<u16>,
+    pub finished: bool,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct RoundMessage {
+    pub round: String,
+    pub from: u16,
+    pub to: Option<u16>,
+    pub data: String,
+}
+
+/// Contents of an exported round, carrying everything needed to join the operation
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct RoundFile {
+    pub format: String,
+    pub version: u8,
+    pub project: String,
+    pub session: String,
+    pub operation: Operation,
+    pub from: Option<u16>,
+    pub messages: Vec<RoundMessage>,
+}
+
+impl RoundFile {
+    /// Split the file into lines of at most `size` characters of payload, each of which fits
+    /// into a QR code: `DIT1:<id>:<index>/<count>:<payload>`
+    pub fn to_chunks(&self, size: usize) -> Result<Vec<String>> {
+        let contents: Vec<char> = serde_json::to_string(self)?.chars().collect();
+        let pieces: Vec<String> = contents
+            .chunks(size.max(1))
+            .map(|piece| piece.iter().collect())
+            .collect();
+
+        let id = format!("{}.{}", self.session, self.from.unwrap_or(0));
+        Ok(pieces
+            .iter()
+            .enumerate()
+            .map(|(i, piece)| {
+                format!(
+                    "{}{}:{}:{}/{}:{}",
+                    CHUNK_PREFIX,
+                    ROUND_VERSION,
+                    id,
+                    i + 1,
+                    pieces.len(),
+                    piece
+                )
+            })
+            .collect())
+    }
+
+    /// Read either a whole exported file or its chunks, which may come in any order
+    pub fn parse(contents: &str) -> Result<RoundFile> {
+        let contents = contents.trim();
+        let file: RoundFile = if contents.starts_with('{') {
+            serde_json::from_str(contents)?
+        } else {
+            serde_json::from_str(&join_chunks(contents)?)?
+        };
+
+        if file.format != ROUND_FORMAT {
+            return Err(round_error("not a dit round file"));
+        }
+        if file.version != ROUND_VERSION {
+            return Err(round_error(&format!(
+                "unsupported version {} (expected {})",
+                file.version, ROUND_VERSION
+            )));
+        }
+
+        Ok(file)
+    }
+}
+
+fn round_error(reason: &str) -> CriticalError {
+    CriticalError::User(UserError::RoundFile(reason.to_owned()))
+}
+
+fn join_chunks(contents: &str) -> Result<String> {
+    let prefix = format!("{}{}:", CHUNK_PREFIX, ROUND_VERSION);
+    let mut id = None;
+    let mut pieces: Vec<Option<&str>> = Vec::new();
+    let lines: Vec<&str> = contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect();
+
+    for line in lines.iter() {
+        let fields: Vec<&str> = match line.strip_prefix(&prefix) {
+            Some(rest) => rest.splitn(3, ':').collect(),
+            None => return Err(round_error("unrecognized chunk")),
+        };
+        if fields.len() != 3 {
+            return Err(round_error("malformed chunk"));
+        }
+
+        if *id.get_or_insert(fields[0]) != fields[0] {
+            return Err(round_error("chunks belong to different exports"));
+        }
+
+        let position: Vec<usize> = fields[1]
+            .split('/')
+            .filter_map(|number| number.parse().ok())
+            .collect();
+        let (index, count) = match position[..] {
+            [index, count] if index >= 1 && index <= count => (index, count),
+            _ => return Err(round_error("malformed chunk index")),
+        };
+        // The count comes from the file, so it may not make us allocate more than it holds
+        if count > lines.len() {
+            return Err(round_error(&format!(
+                "only {} of {} chunks found",
+                lines.len(),
+                count
+            )));
+        }
+
+        if pieces.is_empty() {
+            pieces.resize(count, None);
+        } else if pieces.len() != count {
+            return Err(round_error("chunks disagree on their count"));
+        }
+        pieces[index - 1] = Some(fields[2]);
+    }
+
+    match pieces.iter().position(|piece| piece.is_none()) {
+        Some(missing) => Err(round_error(&format!("chunk {} is missing", missing + 1))),
+        None if pieces.is_empty() => Err(round_error("no chunks found")),
+        None => Ok(pieces.into_iter().flatten().collect()),
+    }
+}
+
+impl OfflineChannel {
+    pub fn new(dir: PathBuf, project: String) -> OfflineChannel {
+        OfflineChannel {
+            dir,
+            project,
+            index: None,
+        }
+    }
+
+    /// Sign up under the given party number instead of picking the lowest free one
+    pub fn with_index(mut self, index: Option<u16>) -> OfflineChannel {
+        self.index = index;
+        self
+    }
+
+    fn operation_file(&self) -> PathBuf {
+        self.dir.join("operation.json")
+    }
+
+    /// Where the message of `from` for `round` is kept, for plain round names only
+    fn message_path(&self, round: &str, from: u16, to: Option<u16>) -> Option<PathBuf> {
+        if !is_round_name(round) {
+            return None;
+        }
+        let name = match to {
+            Some(to) => format!("{}-{}", from, to),
+            None => from.to_string(),
+        };
+
+        Some(self.dir.join("messages").join(round).join(name))
+    }
+
+    pub fn pending(&self) -> Result<Option<OfflineOperation>> {
+        match fs::read_to_string(self.operation_file()) {
+            Ok(contents) => Ok(Some(serde_json::from_str(&contents)?)),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn write_pending(&self, pending: &OfflineOperation) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        write_file(&self.operation_file(), &serde_json::to_string(pending)?)
+    }
+
+    /// Forget about the previous operation, including its stored stages
+    fn reset(&self, pending: &OfflineOperation) -> Result<()> {
+        if self.dir.exists() {
+            fs::remove_dir_all(&self.dir)?;
+        }
+
+        self.write_pending(pending)
+    }
+
+    fn messages(&self) -> Result<Vec<RoundMessage>> {
+        let mut messages = Vec::new();
+        let rounds = match fs::read_dir(self.dir.join("messages")) {
+            Ok(rounds) => rounds,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(messages),
+            Err(err) => return Err(err.into()),
+        };
+
+        for round in rounds {
+            let round = round?;
+            for message in fs::read_dir(round.path())? {
+                let message = message?;
+                let name = message.file_name().to_string_lossy().into_owned();
+                let mut parties = name.splitn(2, '-').map(|party| party.parse::<u16>().ok());
+
+                // Anything that does not look like a message is a leftover temporary file
+                let (from, to) = match (parties.next().flatten(), parties.next()) {
+                    (Some(from), None) => (from, None),
+                    (Some(from), Some(Some(to))) => (from, Some(to)),
+                    _ => continue,
+                };
+
+                messages.push(RoundMessage {
+                    round: round.file_name().to_string_lossy().into_owned(),
+                    from,
+                    to,
+                    data: fs::read_to_string(message.path())?,
+                });
+            }
+        }
+
+        messages.sort_by(|a, b| (&a.round, a.from, a.to).cmp(&(&b.round, b.from, b.to)));
+        Ok(messages)
+    }
+
+    /// Collect every message of the pending operation, so that a round file can also be
+    /// passed on by a participant other than the one who wrote it
+    pub fn export(&self) -> Result<RoundFile> {
+        let pending = match self.pending()? {
+            Some(pending) => pending,
+            None => return Err(round_error("there is no offline operation to export")),
+        };
+
+        Ok(RoundFile {
+            format: ROUND_FORMAT.to_owned(),
+            version: ROUND_VERSION,
+            project: self.project.clone(),
+            session: pending.session,
+            operation: pending.operation,
+            from: pending.party,
+            messages: self.messages()?,
+        })
+    }
+
+    /// Whether importing `file` would start a new operation rather than advance ours
+    pub fn is_new_operation(&self, file: &RoundFile) -> Result<bool> {
+        match self.pending()? {
+            Some(pending) => Ok(pending.session != file.session),
+            None => Ok(true),
+        }
+    }
+
+    /// Store the messages of `file` that we have not seen yet, joining its operation if
+    /// it is not the one we are part of
+    pub fn import(&self, file: &RoundFile) -> Result<()> {
+        if file.project != self.project {
+            return Err(round_error(&format!(
+                "it belongs to project {}",
+                file.project
+            )));
+        }
+
+        if self.is_new_operation(file)? {
+            self.reset(&OfflineOperation {
+                session: file.session.clone(),
+                operation: file.operation.clone(),
+                leader: false,
+                party: None,
+                finished: false,
+            })?;
+        }
+        let party = self.pending()?.and_then(|pending| pending.party);
+
+        for message in file.messages.iter() {
+            let path = self
+                .message_path(&message.round, message.from, message.to)
+                .ok_or_else(|| round_error(&format!("invalid round {:?}", message.round)))?;
+
+            match fs::read_to_string(&path) {
+                Ok(ref existing) if *existing == message.data => {}
+                // Two different messages under our own number means someone else has
+                // picked it too, which is the one thing we cannot detect when signing up
+                Ok(_) if Some(message.from) == party => {
+                    return Err(CriticalError::User(UserError::IndexConflict(message.from)))
+                }
+                Ok(_) => {
+                    return Err(round_error(&format!(
+                        "party {} sent two different messages for {}",
+                        message.from, message.round
+                    )))
+                }
+                Err(ref err) if err.kind() == io::ErrorKind::NotFound => {
+                    if let Some(parent) = path.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+                    write_file(&path, &message.data)?;
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_message(&self, path: PathBuf, data: String) -> std::result::Result<(), ProtocolError> {
+        // Replaying the protocol sends every earlier message again
+        if path.exists() {
+            return Ok(());
+        }
+
+        path.parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .map_err(|_| ProtocolError::Connection)?;
+        write_file(&path, &data).map_err(|_| ProtocolError::Connection)
+    }
+
+    fn read_messages(
+        &self,
+        paths: Vec<PathBuf>,
+    ) -> std::result::Result<Vec<String>, ProtocolError> {
+        if !paths.iter().all(|path| path.exists()) {
+            return Err(ProtocolError::Pending);
+        }
+
+        paths
+            .iter()
+            .map(|path| fs::read_to_string(path).map_err(|_| ProtocolError::Connection))
+            .collect()
+    }
+}
+
+/// Write through a temporary file, so that an interrupted invocation does not leave half a
+/// message behind that a later one would treat as complete
+fn write_file(path: &Path, contents: &str) -> Result<()> {
+    let temp = path.with_file_name(format!(".tmp-{}", unique_suffix()));
+    fs::write(&temp, contents)?;
+    fs::rename(&temp, path)?;
+
+    Ok(())
+}
+
+impl Channel for OfflineChannel {
+    /// Key generation requires everyone to be online, so it never uses this transport
+    fn signup_keygen(&self) -> std::result::Result<u16, ProtocolError> {
+        Err(ProtocolError::Full)
+    }
+
+    fn signup_sign(&self) -> std::result::Result<u16, ProtocolError> {
+        let mut pending = match self.pending() {
+            Ok(Some(pending)) => pending,
+            _ => return Err(ProtocolError::Full),
+        };
+
+        if let Some(party) = pending.party {
+            return Ok(party);
+        }
+
+        let slots = match pending.operation {
+            Operation::SignTag { threshold, .. } => threshold + 1,
+            Operation::SignKey { threshold, .. } => threshold + 1,
+            Operation::SignStatement { threshold, .. } => threshold + 1,
+            _ => return Err(ProtocolError::Full),
+        };
+
+        let taken: BTreeSet<u16> = self
+            .messages()
+            .map_err(|_| ProtocolError::Connection)?
+            .iter()
+            .map(|message| message.from)
+            .collect();
+
+        let party = match self.index {
+            Some(index) if index >= 1 && index <= slots && !taken.contains(&index) => index,
+            Some(_) => return Err(ProtocolError::Full),
+            None => match (1..=slots).find(|index| !taken.contains(index)) {
+                Some(index) => index,
+                None => return Err(ProtocolError::Full),
+            },
+        };
+
+        pending.party = Some(party);
+        self.write_pending(&pending)
+            .map_err(|_| ProtocolError::Connection)?;
+
+        Ok(party)
+    }
+
+    fn signout(&self) -> std::result::Result<(), ProtocolError> {
+        Ok(())
+    }
+
+    fn broadcast(
+        &self,
+        party_num: u16,
+        round: &str,
+        data: String,
+    ) -> std::result::Result<(), ProtocolError> {
+        let path = self.message_path(round, party_num, None);
+        self.write_message(path.ok_or(ProtocolError::Connection)?, data)
+    }
+
+    fn send_p2p(
+        &self,
+        party_from: u16,
+        party_to: u16,
+        round: &str,
+        data: String,
+    ) -> std::result::Result<(), ProtocolError> {
+        let path = self.message_path(round, party_from, Some(party_to));
+        self.write_message(path.ok_or(ProtocolError::Connection)?, data)
+    }
+
+    fn poll_for_broadcasts(
+        &self,
+        party_num: u16,
+        n: u16,
+        round: &str,
+    ) -> std::result::Result<Vec<String>, ProtocolError> {
+        let paths = (1..=n)
+            .filter(|&i| i != party_num)
+            .map(|i| self.message_path(round, i, None))
+            .collect::<Option<_>>();
+
+        self.read_messages(paths.ok_or(ProtocolError::Connection)?)
+    }
+
+    fn poll_for_p2p(
+        &self,
+        party_num: u16,
+        n: u16,
+        round: &str,
+    ) -> std::result::Result<Vec<String>, ProtocolError> {
+        let paths = (1..=n)
+            .filter(|&i| i != party_num)
+            .map(|i| self.message_path(round, i, Some(party_num)))
+            .collect::<Option<_>>();
+
+        self.read_messages(paths.ok_or(ProtocolError::Connection)?)
+    }
+
+    fn start_operation(&self, op: &Operation) -> std::result::Result<(), ProtocolError> {
+        self.reset(&OfflineOperation {
+            session: unique_suffix(),
+            operation: op.clone(),
+            leader: true,
+            party: None,
+            finished: false,
+        })
+        .map_err(|_| ProtocolError::Connection)
+    }
+
+    /// The messages are kept around, as the others still need our last round, but the
+    /// stored stages must never be used again
+    fn end_operation(&self, _op: &Operation) -> std::result::Result<(), ProtocolError> {
+        let mut pending = match self.pending() {
+            Ok(Some(pending)) => pending,
+            _ => return Ok(()),
+        };
+
+        pending.finished = true;
+        self.write_pending(&pending)
+            .map_err(|_| ProtocolError::Connection)?;
+
+        match fs::remove_dir_all(self.dir.join("stages")) {
+            Err(ref err) if err.kind() != io::ErrorKind::NotFound => Err(ProtocolError::Connection),
+            _ => Ok(()),
+        }
+    }
+
+    fn get_current_operation(&self) -> std::result::Result<Operation, ProtocolError> {
+        match self.pending() {
+            Ok(Some(pending)) if !pending.finished => Ok(pending.operation),
+            Ok(_) => Ok(Operation::Idle),
+            Err(_) => Err(ProtocolError::Connection),
+        }
+    }
+
+    /// There is no one to notify until the next export
+    fn blame(&self) -> std::result::Result<(), ProtocolError> {
+        Ok(())
+    }
+
+    /// Messages are only removed once the next operation starts, as they may still need
+    /// to be exported
+    fn clear(&self) -> std::result::Result<(), ProtocolError> {
+        Ok(())
+    }
+
+    fn session(&self) -> Option<String> {
+        self.pending().ok().flatten().map(|pending| pending.session)
+    }
+
+    fn load_stage(&self, stage: &str) -> Option<String> {
+        fs::read_to_string(self.dir.join("stages").join(format!("{}.json", stage))).ok()
+    }
+
+    fn store_stage(&self, stage: &str, data: String) -> std::result::Result<(), ProtocolError> {
+        let stages = self.dir.join("stages");
+        fs::create_dir_all(&stages).map_err(|_| ProtocolError::Connection)?;
+
+        write_file(&stages.join(format!("{}.json", stage)), &data)
+            .map_err(|_| ProtocolError::Connection)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::temp_dir;
+
+    fn operation() -> Operation {
+        Operation::SignKey {
+            participants: 3,
+            threshold: 1,
+            leader: "leader".into(),
+            email: "leader@example.com".into(),
+            epoch: 0,
+        }
+    }
+
+    #[test]
+    fn round_trip_through_chunks() {
+        let root = temp_dir("offline");
+        let leader = OfflineChannel::new(root.join("leader"), "test".into());
+        let participant = OfflineChannel::new(root.join("participant"), "test".into());
+
+        let op = operation();
+        leader.start_operation(&op).unwrap();
+        assert_eq!(leader.signup_sign().unwrap(), 1);
+        leader
+            .broadcast(1, "round1", "from leader: ü".into())
+            .unwrap();
+        assert!(matches!(
+            leader.poll_for_broadcasts(1, 2, "round1"),
+            Err(ProtocolError::Pending)
+        ));
+
+        let chunks = leader.export().unwrap().to_chunks(16).unwrap();
+        assert!(chunks.len() > 1);
+        let mut shuffled = chunks.clone();
+        shuffled.reverse();
+        let file = RoundFile::parse(&shuffled.join("\n")).unwrap();
+
+        assert!(participant.is_new_operation(&file).unwrap());
+        participant.import(&file).unwrap();
+        assert_eq!(participant.get_current_operation().unwrap(), op);
+        assert_eq!(participant.signup_sign().unwrap(), 2);
+        participant
+            .broadcast(2, "round1", "from participant".into())
+            .unwrap();
+        assert_eq!(
+            participant.poll_for_broadcasts(2, 2, "round1").unwrap(),
+            vec!["from leader: ü".to_owned()]
+        );
+
+        let file =
+            RoundFile::parse(&serde_json::to_string(&participant.export().unwrap()).unwrap())
+                .unwrap();
+        assert!(!leader.is_new_operation(&file).unwrap());
+        leader.import(&file).unwrap();
+        assert_eq!(
+            leader.poll_for_broadcasts(1, 2, "round1").unwrap(),
+            vec!["from participant".to_owned()]
+        );
+
+        // A third participant that only saw the leader's export picks the same number
+        let late = OfflineChannel::new(root.join("late"), "test".into());
+        late.import(&RoundFile::parse(&chunks.join("\n")).unwrap())
+            .unwrap();
+        assert_eq!(late.signup_sign().unwrap(), 2);
+        late.broadcast(2, "round1", "from late".into()).unwrap();
+        assert!(matches!(
+            late.import(&file),
+            Err(CriticalError::User(UserError::IndexConflict(2)))
+        ));
+
+        leader.store_stage("stage", "secret".into()).unwrap();
+        assert_eq!(leader.load_stage("stage"), Some("secret".to_owned()));
+        leader.end_operation(&op).unwrap();
+        assert_eq!(leader.load_stage("stage"), None);
+        assert_eq!(leader.get_current_operation().unwrap(), Operation::Idle);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn rejects_forged_files() {
+        let root = temp_dir("forged");
+        let leader = OfflineChannel::new(root.join("leader"), "test".into());
+        let participant = OfflineChannel::new(root.join("participant"), "test".into());
+
+        leader.start_operation(&operation()).unwrap();
+        assert_eq!(leader.signup_sign().unwrap(), 1);
+        leader.broadcast(1, "round1", "from leader".into()).unwrap();
+
+        // Rounds name a directory of their own, and nothing outside of it
+        let mut file = leader.export().unwrap();
+        file.messages[0].round = "../../escaped".into();
+        assert!(matches!(
+            participant.import(&file),
+            Err(CriticalError::User(UserError::RoundFile(_)))
+        ));
+        assert!(!root.join("escaped").exists());
+
+        // A chunk claiming a huge count is turned down before anything gets allocated
+        let chunk = leader
+            .export()
+            .unwrap()
+            .to_chunks(1 << 20)
+            .unwrap()
+            .remove(0);
+        let forged = chunk.replacen(":1/1:", &format!(":1/{}:", usize::MAX), 1);
+        assert!(matches!(
+            RoundFile::parse(&forged),
+            Err(CriticalError::User(UserError::RoundFile(_)))
+        ));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
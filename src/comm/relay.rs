@@ -0,0 +1,549 @@
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Component, Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::Arc;
+use std::{fs, thread, time};
+
+use super::deadline::{self, Timeouts};
+use super::{is_round_name, Channel};
+use crate::errors::ProtocolError;
+use crate::utils::Operation;
+
+/// How often a client blocked on the relay checks whether the user has cancelled, in seconds
+const CANCEL_CHECK_INTERVAL: u64 = 1;
+
+/// A call of a `Channel` method, sent by `RelayChannel` as one line of JSON. The first
+/// request of every connection opens the project that all of the others refer to.
+#[derive(Serialize, Deserialize, Debug)]
+enum Request {
+    Open {
+        project: String,
+        timeouts: Timeouts,
+    },
+    SignupKeygen,
+    SignupSign,
+    ClaimKeygen(u16),
+    Signout,
+    Broadcast {
+        party_num: u16,
+        round: String,
+        data: String,
+    },
+    SendP2p {
+        party_from: u16,
+        party_to: u16,
+        round: String,
+        data: String,
+    },
+    PollForBroadcasts {
+        party_num: u16,
+        n: u16,
+        round: String,
+    },
+    PollForP2p {
+        party_num: u16,
+        n: u16,
+        round: String,
+    },
+    StartOperation(Operation),
+    EndOperation(Operation),
+    GetCurrentOperation,
+    AwaitOperation(Operation),
+    Session,
+    Blame,
+    Clear,
+}
+
+/// What a `Channel` method returned, sent back as one line of JSON wrapped in a `Result`
+#[derive(Serialize, Deserialize, Debug)]
+enum Reply {
+    Party(u16),
+    Done,
+    Messages(Vec<String>),
+    Operation(Operation),
+    Session(Option<String>),
+}
+
+/// Answer the requests read from `input` one line at a time, until it is closed, by calling
+/// the channel that `open` returns for the project of the connection
+pub fn serve<F, R, W>(open: F, input: R, mut output: W) -> io::Result<()>
+where
+    F: Fn(String, Timeouts) -> Box<dyn Channel>,
+    R: BufRead,
+    W: Write,
+{
+    let mut channel: Option<Box<dyn Channel>> = None;
+
+    for line in input.lines() {
+        let reply = match serde_json::from_str(&line?) {
+            Ok(Request::Open { project, timeouts }) if is_project_name(&project) => {
+                channel = Some(open(project, timeouts));
+                Ok(Reply::Done)
+            }
+            Ok(request) => match &channel {
+                Some(channel) => dispatch(channel.as_ref(), request),
+                None => Err(ProtocolError::Connection),
+            },
+            Err(_) => Err(ProtocolError::Connection),
+        };
+
+        serde_json::to_writer(&mut output, &reply)?;
+        output.write_all(b"\n")?;
+        output.flush()?;
+    }
+
+    Ok(())
+}
+
+/// Projects name a directory under the root of the relay, and may not point outside of it
+fn is_project_name(project: &str) -> bool {
+    let mut components = Path::new(project).components();
+
+    matches!(
+        (components.next(), components.next()),
+        (Some(Component::Normal(_)), None)
+    )
+}
+
+/// Serve every connection to the Unix socket at `path` on its own thread. A socket left
+/// behind by an earlier relay is replaced, but any other file at `path` is not.
+pub fn listen<F>(path: &Path, open: F) -> io::Result<()>
+where
+    F: Fn(String, Timeouts) -> Box<dyn Channel> + Send + Sync + 'static,
+{
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if metadata.file_type().is_socket() {
+            fs::remove_file(path)?;
+        }
+    }
+
+    let listener = UnixListener::bind(path)?;
+    let open = Arc::new(open);
+
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let open = Arc::clone(&open);
+
+        thread::spawn(move || {
+            let input = match stream.try_clone() {
+                Ok(input) => BufReader::new(input),
+                Err(_) => return,
+            };
+            let _ = serve(|project, timeouts| open(project, timeouts), input, stream);
+        });
+    }
+
+    Ok(())
+}
+
+/// Round of the requests that name one
+fn round_of(request: &Request) -> Option<&str> {
+    match request {
+        Request::Broadcast { round, .. }
+        | Request::SendP2p { round, .. }
+        | Request::PollForBroadcasts { round, .. }
+        | Request::PollForP2p { round, .. } => Some(round),
+        _ => None,
+    }
+}
+
+fn dispatch(channel: &dyn Channel, request: Request) -> Result<Reply, ProtocolError> {
+    // The channel behind the relay may make the round part of a path
+    if matches!(round_of(&request), Some(round) if !is_round_name(round)) {
+        return Err(ProtocolError::Connection);
+    }
+
+    match request {
+        Request::Open { .. } => Err(ProtocolError::Connection),
+        Request::SignupKeygen => channel.signup_keygen().map(Reply::Party),
+        Request::SignupSign => channel.signup_sign().map(Reply::Party),
+        Request::ClaimKeygen(party) => channel.claim_keygen(party).map(Reply::Party),
+        Request::Signout => channel.signout().map(|_| Reply::Done),
+        Request::Broadcast {
+            party_num,
+            round,
+            data,
+        } => channel
+            .broadcast(party_num, &round, data)
+            .map(|_| Reply::Done),
+        Request::SendP2p {
+            party_from,
+            party_to,
+            round,
+            data,
+        } => channel
+            .send_p2p(party_from, party_to, &round, data)
+            .map(|_| Reply::Done),
+        Request::PollForBroadcasts {
+            party_num,
+            n,
+            round,
+        } => channel
+            .poll_for_broadcasts(party_num, n, &round)
+            .map(Reply::Messages),
+        Request::PollForP2p {
+            party_num,
+            n,
+            round,
+        } => channel
+            .poll_for_p2p(party_num, n, &round)
+            .map(Reply::Messages),
+        Request::StartOperation(op) => channel.start_operation(&op).map(|_| Reply::Done),
+        Request::EndOperation(op) => channel.end_operation(&op).map(|_| Reply::Done),
+        Request::GetCurrentOperation => channel.get_current_operation().map(Reply::Operation),
+        Request::AwaitOperation(known) => channel.await_operation(&known).map(Reply::Operation),
+        Request::Session => Ok(Reply::Session(channel.session())),
+        Request::Blame => channel.blame().map(|_| Reply::Done),
+        Request::Clear => channel.clear().map(|_| Reply::Done),
+    }
+}
+
+/// Reaches a relay started with `dit relay`, either as a child process speaking over its
+/// standard input and output (typically through `ssh`), or over a Unix socket. The relay
+/// keeps the messages of all participants, and waits for them on their behalf.
+///
+/// The relay is only reached on the first call, like the HTTP server, so that an unreachable
+/// relay leaves `dit` working as plain git.
+pub struct RelayChannel {
+    endpoint: Endpoint,
+    project: String,
+    timeouts: Timeouts,
+    connection: RefCell<Option<Connection>>,
+}
+
+enum Endpoint {
+    Command(Vec<String>),
+    Socket(PathBuf),
+}
+
+struct Connection {
+    reader: Box<dyn BufRead>,
+    writer: Box<dyn Write>,
+    child: Option<Child>,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        if let Some(child) = &mut self.child {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+impl RelayChannel {
+    /// Talk to the relay started by running `command` (with its arguments)
+    pub fn command(command: Vec<String>, project: String, timeouts: Timeouts) -> RelayChannel {
+        RelayChannel::new(Endpoint::Command(command), project, timeouts)
+    }
+
+    /// Talk to the relay listening on the Unix socket at `path`
+    pub fn socket(path: PathBuf, project: String, timeouts: Timeouts) -> RelayChannel {
+        RelayChannel::new(Endpoint::Socket(path), project, timeouts)
+    }
+
+    fn new(endpoint: Endpoint, project: String, timeouts: Timeouts) -> RelayChannel {
+        RelayChannel {
+            endpoint,
+            project,
+            timeouts,
+            connection: RefCell::new(None),
+        }
+    }
+
+    fn connect(&self) -> io::Result<Connection> {
+        let mut connection = match &self.endpoint {
+            Endpoint::Command(command) => {
+                let (program, args) = command.split_first().ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidInput, "empty relay command")
+                })?;
+
+                let mut child = Command::new(program)
+                    .args(args)
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::piped())
+                    .spawn()?;
+
+                Connection {
+                    reader: Box::new(BufReader::new(child.stdout.take().unwrap())),
+                    writer: Box::new(child.stdin.take().unwrap()),
+                    child: Some(child),
+                }
+            }
+            Endpoint::Socket(path) => {
+                let stream = UnixStream::connect(path)?;
+                // Reads give up every now and then, to notice that the user has cancelled
+                stream.set_read_timeout(Some(time::Duration::from_secs(CANCEL_CHECK_INTERVAL)))?;
+
+                Connection {
+                    reader: Box::new(BufReader::new(stream.try_clone()?)),
+                    writer: Box::new(stream),
+                    child: None,
+                }
+            }
+        };
+
+        let open = Request::Open {
+            project: self.project.clone(),
+            timeouts: self.timeouts,
+        };
+        match connection.exchange(&open) {
+            Ok(Ok(_)) => Ok(connection),
+            _ => Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                "the relay did not accept the project",
+            )),
+        }
+    }
+
+    /// Send `request` and wait for the reply of the relay, connecting to it first if need be.
+    /// A connection that fails is dropped, and the next call starts a new one.
+    fn call(&self, request: Request) -> Result<Reply, ProtocolError> {
+        let mut connection = self.connection.borrow_mut();
+        if connection.is_none() {
+            *connection = Some(self.connect().map_err(|_| ProtocolError::Connection)?);
+        }
+
+        match connection.as_mut().unwrap().exchange(&request) {
+            Ok(reply) => reply,
+            Err(err) => {
+                *connection = None;
+                Err(err)
+            }
+        }
+    }
+
+    fn call_party(&self, request: Request) -> Result<u16, ProtocolError> {
+        match self.call(request)? {
+            Reply::Party(party) => Ok(party),
+            _ => Err(ProtocolError::Connection),
+        }
+    }
+
+    fn call_done(&self, request: Request) -> Result<(), ProtocolError> {
+        match self.call(request)? {
+            Reply::Done => Ok(()),
+            _ => Err(ProtocolError::Connection),
+        }
+    }
+
+    fn call_messages(&self, request: Request) -> Result<Vec<String>, ProtocolError> {
+        match self.call(request)? {
+            Reply::Messages(messages) => Ok(messages),
+            _ => Err(ProtocolError::Connection),
+        }
+    }
+
+    fn call_operation(&self, request: Request) -> Result<Operation, ProtocolError> {
+        match self.call(request)? {
+            Reply::Operation(op) => Ok(op),
+            _ => Err(ProtocolError::Connection),
+        }
+    }
+}
+
+impl Connection {
+    /// Write one request, and read the reply the relay answers it with. The outer error is
+    /// about the connection itself, and the inner one is what the relay's channel returned.
+    fn exchange(
+        &mut self,
+        request: &Request,
+    ) -> Result<Result<Reply, ProtocolError>, ProtocolError> {
+        let mut line = serde_json::to_vec(request).map_err(|_| ProtocolError::Connection)?;
+        line.push(b'\n');
+        self.writer
+            .write_all(&line)
+            .and_then(|_| self.writer.flush())
+            .map_err(|_| ProtocolError::Connection)?;
+
+        let mut reply = Vec::new();
+        loop {
+            // Whatever was read before a timeout stays in `reply`
+            match self.reader.read_until(b'\n', &mut reply) {
+                Ok(0) => return Err(ProtocolError::Connection),
+                Ok(_) => break,
+                Err(ref err)
+                    if err.kind() == io::ErrorKind::WouldBlock
+                        || err.kind() == io::ErrorKind::TimedOut
+                        || err.kind() == io::ErrorKind::Interrupted =>
+                {
+                    if deadline::is_cancelled() {
+                        return Err(ProtocolError::Cancelled);
+                    }
+                }
+                Err(_) => return Err(ProtocolError::Connection),
+            }
+        }
+
+        serde_json::from_slice(&reply).map_err(|_| ProtocolError::Connection)
+    }
+}
+
+impl Channel for RelayChannel {
+    fn signup_keygen(&self) -> Result<u16, ProtocolError> {
+        self.call_party(Request::SignupKeygen)
+    }
+
+    fn signup_sign(&self) -> Result<u16, ProtocolError> {
+        self.call_party(Request::SignupSign)
+    }
+
+    fn claim_keygen(&self, party: u16) -> Result<u16, ProtocolError> {
+        self.call_party(Request::ClaimKeygen(party))
+    }
+
+    fn signout(&self) -> Result<(), ProtocolError> {
+        self.call_done(Request::Signout)
+    }
+
+    fn broadcast(&self, party_num: u16, round: &str, data: String) -> Result<(), ProtocolError> {
+        self.call_done(Request::Broadcast {
+            party_num,
+            round: round.to_owned(),
+            data,
+        })
+    }
+
+    fn send_p2p(
+        &self,
+        party_from: u16,
+        party_to: u16,
+        round: &str,
+        data: String,
+    ) -> Result<(), ProtocolError> {
+        self.call_done(Request::SendP2p {
+            party_from,
+            party_to,
+            round: round.to_owned(),
+            data,
+        })
+    }
+
+    fn poll_for_broadcasts(
+        &self,
+        party_num: u16,
+        n: u16,
+        round: &str,
+    ) -> Result<Vec<String>, ProtocolError> {
+        self.call_messages(Request::PollForBroadcasts {
+            party_num,
+            n,
+            round: round.to_owned(),
+        })
+    }
+
+    fn poll_for_p2p(
+        &self,
+        party_num: u16,
+        n: u16,
+        round: &str,
+    ) -> Result<Vec<String>, ProtocolError> {
+        self.call_messages(Request::PollForP2p {
+            party_num,
+            n,
+            round: round.to_owned(),
+        })
+    }
+
+    fn start_operation(&self, op: &Operation) -> Result<(), ProtocolError> {
+        self.call_done(Request::StartOperation(op.clone()))
+    }
+
+    fn end_operation(&self, op: &Operation) -> Result<(), ProtocolError> {
+        self.call_done(Request::EndOperation(op.clone()))
+    }
+
+    fn get_current_operation(&self) -> Result<Operation, ProtocolError> {
+        self.call_operation(Request::GetCurrentOperation)
+    }
+
+    fn await_operation(&self, known: &Operation) -> Result<Operation, ProtocolError> {
+        self.call_operation(Request::AwaitOperation(known.clone()))
+    }
+
+    fn session(&self) -> Option<String> {
+        match self.call(Request::Session) {
+            Ok(Reply::Session(session)) => session,
+            _ => None,
+        }
+    }
+
+    fn blame(&self) -> Result<(), ProtocolError> {
+        self.call_done(Request::Blame)
+    }
+
+    fn clear(&self) -> Result<(), ProtocolError> {
+        self.call_done(Request::Clear)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::directory::DirectoryChannel;
+    use super::*;
+    use crate::testing::temp_dir;
+
+    #[test]
+    fn relays_rounds_between_participants() {
+        let root = temp_dir("relay");
+        let socket = root.join("relay.sock");
+
+        let messages = root.join("messages");
+        let open = move |project, timeouts| {
+            let channel =
+                DirectoryChannel::new(messages.clone(), project, time::Duration::from_millis(10))
+                    .with_timeouts(timeouts);
+            Box::new(channel) as Box<dyn Channel>
+        };
+        let listening = socket.clone();
+        thread::spawn(move || listen(&listening, open));
+        while !socket.exists() {
+            thread::sleep(time::Duration::from_millis(10));
+        }
+
+        let leader = RelayChannel::socket(socket.clone(), "test".into(), Timeouts::default());
+        let member = RelayChannel::socket(socket.clone(), "test".into(), Timeouts::default());
+        let outsider = RelayChannel::socket(socket, "../test".into(), Timeouts::default());
+
+        let op = Operation::KeyGen {
+            participants: 2,
+            leader: "leader".into(),
+            email: "leader@example.com".into(),
+            epoch: 0,
+        };
+        leader.start_operation(&op).unwrap();
+        assert_eq!(member.get_current_operation().unwrap(), op);
+        assert!(matches!(
+            outsider.get_current_operation(),
+            Err(ProtocolError::Connection)
+        ));
+        assert_eq!(leader.signup_keygen().unwrap(), 1);
+        assert_eq!(member.signup_keygen().unwrap(), 2);
+
+        leader.broadcast(1, "round1", "from leader".into()).unwrap();
+        member.send_p2p(2, 1, "round2", "to leader".into()).unwrap();
+        assert_eq!(
+            member.poll_for_broadcasts(2, 2, "round1").unwrap(),
+            vec!["from leader".to_owned()]
+        );
+        assert_eq!(
+            leader.poll_for_p2p(1, 2, "round2").unwrap(),
+            vec!["to leader".to_owned()]
+        );
+
+        // Errors of the channel behind the relay reach the client unchanged
+        assert!(matches!(member.signup_keygen(), Err(ProtocolError::Full)));
+        // Rounds name directories behind the relay, and may not point outside of them
+        assert!(matches!(
+            member.broadcast(2, "../../../escaped", "from member".into()),
+            Err(ProtocolError::Connection)
+        ));
+        assert!(!root.join("escaped").exists());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -1,5 +1,9 @@
 #![allow(non_snake_case)]
-use crate::comm::{HTTPChannel, PartyKeyPair};
+use crate::comm::{Channel, PartyKeyPair};
+use crate::encryption;
+use crate::errors::ProtocolError;
+use crate::signing::checkpoint;
+use crate::wire::{self, Session};
 
 use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2020::orchestrate::{
     keygen_stage1, keygen_stage2, keygen_stage3, keygen_stage4, KeyGenStage1Input,
@@ -15,59 +19,32 @@ use curv::elliptic::curves::secp256_k1::{FE, GE};
 use paillier::EncryptionKey;
 use zk_paillier::zkproofs::DLogStatement;
 
-pub fn distributed_keygen(
-    channel: &HTTPChannel,
+pub fn distributed_keygen<C: Channel + ?Sized>(
+    channel: &C,
     participants: u16,
     threshold: u16,
-) -> Result<PartyKeyPair, ()> {
+) -> Result<PartyKeyPair, ProtocolError> {
     // TODO Get rid of this
     let params = Parameters {
         threshold,
         share_count: participants,
     };
 
-    let party_num_int = channel.signup_keygen().unwrap();
+    let party_num_int = channel.signup_keygen()?;
+    let session = Session::join(channel, party_num_int, params.share_count, "dkg")?;
 
     let input_stage1 = KeyGenStage1Input {
         index: (party_num_int - 1) as usize,
     };
 
-    let res_stage1 = keygen_stage1(&input_stage1);
-
-    channel
-        .broadcast(
-            party_num_int,
-            "dkg-round1",
-            serde_json::to_string(&res_stage1.bc_com1_l).unwrap(),
-        )
-        .unwrap();
-
-    let round1_ans_vec =
-        channel.poll_for_broadcasts(party_num_int, params.share_count, "dkg-round1");
-
-    let mut bc1_vec = round1_ans_vec
-        .iter()
-        .map(|m| serde_json::from_str::<KeyGenBroadcastMessage1>(m).unwrap())
-        .collect::<Vec<_>>();
+    let res_stage1 = checkpoint(channel, "dkg-stage1", || Ok(keygen_stage1(&input_stage1)))?;
 
+    let mut bc1_vec: Vec<KeyGenBroadcastMessage1> =
+        session.broadcast("dkg-round1", &res_stage1.bc_com1_l)?;
     bc1_vec.insert(party_num_int as usize - 1, res_stage1.bc_com1_l);
 
-    channel
-        .broadcast(
-            party_num_int,
-            "dkg-round2",
-            serde_json::to_string(&res_stage1.decom1_l).unwrap(),
-        )
-        .unwrap();
-
-    let round2_ans_vec =
-        channel.poll_for_broadcasts(party_num_int, params.share_count, "dkg-round2");
-
-    let mut decom1_vec = round2_ans_vec
-        .iter()
-        .map(|m| serde_json::from_str::<KeyGenDecommitMessage1>(m).unwrap())
-        .collect::<Vec<_>>();
-
+    let mut decom1_vec: Vec<KeyGenDecommitMessage1> =
+        session.broadcast("dkg-round2", &res_stage1.decom1_l)?;
     decom1_vec.insert(party_num_int as usize - 1, res_stage1.decom1_l);
 
     let input_stage2 = KeyGenStage2Input {
@@ -78,7 +55,9 @@ pub fn distributed_keygen(
         bc1_vec_s: bc1_vec.clone(),
     };
 
-    let res_stage2 = keygen_stage2(&input_stage2).expect("keygen stage 2 failed.");
+    let res_stage2 = checkpoint(channel, "dkg-stage2", || {
+        keygen_stage2(&input_stage2).map_err(|_| channel.abort())
+    })?;
 
     let mut point_vec: Vec<GE> = Vec::new();
     for i in 1..=params.share_count {
@@ -89,20 +68,25 @@ pub fn distributed_keygen(
     let (head, tail) = point_vec.split_at(1);
     let y_sum = tail.iter().fold(head[0], |acc, x| acc + x);
 
+    // The shares are encrypted under keys agreed on with the round 1 keys of the recipients,
+    // as anyone observing the channel could otherwise reconstruct the private key
+    let u_i = res_stage1.party_keys_l.u_i;
     for (k, i) in (1..=params.share_count).enumerate() {
         if i != party_num_int {
-            channel
-                .sendp2p(
-                    party_num_int,
-                    i,
-                    "dkg-round3",
-                    serde_json::to_string(&res_stage2.secret_shares_s[k]).unwrap(),
-                )
-                .unwrap();
+            let key = encryption::shared_key(u_i, decom1_vec[k].y_i);
+            session.send_p2p(
+                i,
+                "dkg-round3",
+                encryption::seal(
+                    &key,
+                    &encryption::context("dkg-round3", party_num_int, i),
+                    &wire::to_payload(&res_stage2.secret_shares_s[k]),
+                ),
+            )?;
         }
     }
     // get shares from other parties.
-    let round3_ans_vec = channel.poll_for_p2p(party_num_int, params.share_count, "dkg-round3");
+    let round3_ans_vec = session.poll_for_p2p("dkg-round3")?;
 
     // decrypt shares from other parties.
     let mut j = 0;
@@ -111,34 +95,22 @@ pub fn distributed_keygen(
         if i == party_num_int {
             party_shares.push(res_stage2.secret_shares_s[(i - 1) as usize]);
         } else {
-            party_shares.push(serde_json::from_str(&round3_ans_vec[j]).unwrap());
+            let key = encryption::shared_key(u_i, decom1_vec[(i - 1) as usize].y_i);
+            let context = encryption::context("dkg-round3", i, party_num_int);
+            let share = encryption::open(&key, &context, &round3_ans_vec[j]).ok_or_else(|| {
+                let _ = channel.blame();
+                ProtocolError::Decryption(i, "dkg-round3".to_owned())
+            })?;
+
+            party_shares.push(session.read(i, "dkg-round3", &share)?);
             j += 1;
         }
     }
 
-    channel
-        .broadcast(
-            party_num_int,
-            "dkg-round4",
-            serde_json::to_string(&res_stage2.vss_scheme_s).unwrap(),
-        )
-        .unwrap();
-
     //get vss_scheme for others.
-    let round4_ans_vec =
-        channel.poll_for_broadcasts(party_num_int, params.share_count, "dkg-round4");
-
-    let mut j = 0;
-    let mut vss_scheme_vec: Vec<VerifiableSS<GE>> = Vec::new();
-    for i in 1..=params.share_count {
-        if i == party_num_int {
-            vss_scheme_vec.push(res_stage2.vss_scheme_s.clone());
-        } else {
-            let vss_scheme_j: VerifiableSS<GE> = serde_json::from_str(&round4_ans_vec[j]).unwrap();
-            vss_scheme_vec.push(vss_scheme_j);
-            j += 1;
-        }
-    }
+    let mut vss_scheme_vec: Vec<VerifiableSS<GE>> =
+        session.broadcast("dkg-round4", &res_stage2.vss_scheme_s)?;
+    vss_scheme_vec.insert(party_num_int as usize - 1, res_stage2.vss_scheme_s.clone());
     let input_stage3 = KeyGenStage3Input {
         party_keys_s: res_stage1.party_keys_l.clone(),
         vss_scheme_vec_s: vss_scheme_vec.clone(),
@@ -147,30 +119,11 @@ pub fn distributed_keygen(
         index_s: (party_num_int - 1) as usize,
         params_s: params.clone(),
     };
-    let res_stage3 = keygen_stage3(&input_stage3).expect("stage 3 keygen failed.");
+    let res_stage3 = keygen_stage3(&input_stage3).map_err(|_| channel.abort())?;
     // round 5: send dlog proof
-    channel
-        .broadcast(
-            party_num_int,
-            "dkg-round5",
-            serde_json::to_string(&res_stage3.dlog_proof_s).unwrap(),
-        )
-        .unwrap();
-
-    let round5_ans_vec =
-        channel.poll_for_broadcasts(party_num_int, params.share_count, "dkg-round5");
-
-    let mut j = 0;
-    let mut dlog_proof_vec: Vec<DLogProof<GE>> = Vec::new();
-    for i in 1..=params.share_count {
-        if i == party_num_int {
-            dlog_proof_vec.push(res_stage3.dlog_proof_s.clone());
-        } else {
-            let dlog_proof_j: DLogProof<GE> = serde_json::from_str(&round5_ans_vec[j]).unwrap();
-            dlog_proof_vec.push(dlog_proof_j);
-            j += 1;
-        }
-    }
+    let mut dlog_proof_vec: Vec<DLogProof<GE>> =
+        session.broadcast("dkg-round5", &res_stage3.dlog_proof_s)?;
+    dlog_proof_vec.insert(party_num_int as usize - 1, res_stage3.dlog_proof_s.clone());
 
     let input_stage4 = KeyGenStage4Input {
         params_s: params.clone(),
@@ -178,7 +131,7 @@ pub fn distributed_keygen(
         y_vec_s: point_vec.clone(),
     };
 
-    let _ = keygen_stage4(&input_stage4).expect("keygen stage4 failed.");
+    let _ = keygen_stage4(&input_stage4).map_err(|_| channel.abort())?;
 
     let paillier_key_vec = (0..params.share_count)
         .map(|i| bc1_vec[i as usize].e.clone())
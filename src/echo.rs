@@ -0,0 +1,84 @@
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+
+use crate::comm::Channel;
+use crate::errors::ProtocolError;
+use crate::utils::to_hex;
+
+/// Broadcast `data` for the given round and collect the broadcasts of the other `n - 1`
+/// parties, ordered by party number, like `Channel::broadcast` followed by
+/// `Channel::poll_for_broadcasts`.
+///
+/// GG20 assumes a reliable broadcast, which a channel can break by handing different parties
+/// different messages for the same round. Before the messages are returned, every party
+/// therefore broadcasts a digest of the full set it ended up with, and the round only
+/// succeeds if all of the digests agree.
+pub fn broadcast<C: Channel + ?Sized>(
+    channel: &C,
+    party_num: u16,
+    n: u16,
+    round: &str,
+    data: String,
+) -> Result<Vec<String>, ProtocolError> {
+    channel.broadcast(party_num, round, data.clone())?;
+    let received = channel.poll_for_broadcasts(party_num, n, round)?;
+
+    let mut messages = received.clone();
+    messages.insert(party_num as usize - 1, data);
+    let own = digest(round, &messages);
+
+    let echo_round = format!("{}-echo", round);
+    channel.broadcast(party_num, &echo_round, own.clone())?;
+    let echoes = channel.poll_for_broadcasts(party_num, n, &echo_round)?;
+
+    let disagreeing = (1..=n)
+        .filter(|&i| i != party_num)
+        .zip(echoes.iter())
+        .filter(|(_, echo)| **echo != own)
+        .map(|(i, _)| i)
+        .collect::<Vec<_>>();
+
+    if disagreeing.is_empty() {
+        Ok(received)
+    } else {
+        let _ = channel.blame();
+        Err(ProtocolError::Equivocation(round.to_owned(), disagreeing))
+    }
+}
+
+/// Digest of the messages of a round, ordered by party number. Each message is prefixed by
+/// its length, so that the boundaries between them are part of the digest.
+pub fn digest(round: &str, messages: &[String]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.input(round.as_bytes());
+    for message in messages {
+        hasher.input(&(message.len() as u64).to_be_bytes());
+        hasher.input(message.as_bytes());
+    }
+
+    let mut hash = [0u8; 32];
+    hasher.result(&mut hash);
+    to_hex(&hash)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn digest_covers_the_whole_round() {
+        let messages = vec!["ab".to_owned(), "c".to_owned()];
+        let own = digest("sign-round1", &messages);
+
+        assert_eq!(own, digest("sign-round1", &messages));
+        assert_ne!(own, digest("sign-round4", &messages));
+        assert_ne!(
+            own,
+            digest("sign-round1", &["a".to_owned(), "bc".to_owned()])
+        );
+        assert_ne!(
+            own,
+            digest("sign-round1", &["c".to_owned(), "ab".to_owned()])
+        );
+    }
+}
@@ -0,0 +1,102 @@
+use crypto::aead::{AeadDecryptor, AeadEncryptor};
+use crypto::aes::KeySize;
+use crypto::aes_gcm::AesGcm;
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use curv::elliptic::curves::secp256_k1::{FE, GE};
+use curv::elliptic::curves::traits::ECPoint;
+use rand::rngs::OsRng;
+use rand::RngCore;
+
+const KEY_CONTEXT: &[u8] = b"dit-p2p-v1";
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+/// Key shared between the holder of `secret` and the holder of the secret behind `public`,
+/// obtained by ECDH over the parties' keys (`u_i` and `g^u_j`, respectively)
+pub fn shared_key(secret: FE, public: GE) -> [u8; 32] {
+    let point = public * secret;
+
+    let mut hasher = Sha256::new();
+    hasher.input(KEY_CONTEXT);
+    hasher.input(&point.pk_to_key_slice());
+
+    let mut key = [0u8; 32];
+    hasher.result(&mut key);
+    key
+}
+
+/// Authenticated data binding a message to its round and direction, so that the channel
+/// cannot replay it elsewhere, or reflect it back to its sender
+pub fn context(round: &str, party_from: u16, party_to: u16) -> String {
+    format!("{}:{}-{}", round, party_from, party_to)
+}
+
+/// Encrypt a point-to-point message, which the channel only ever sees as
+/// `nonce | ciphertext | tag`
+pub fn seal(key: &[u8; 32], context: &str, plaintext: &[u8]) -> Vec<u8> {
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+
+    let mut ciphertext = vec![0u8; plaintext.len()];
+    let mut tag = [0u8; TAG_LEN];
+    AesGcm::new(KeySize::KeySize256, key, &nonce, context.as_bytes()).encrypt(
+        plaintext,
+        &mut ciphertext,
+        &mut tag,
+    );
+
+    let mut sealed = nonce.to_vec();
+    sealed.extend_from_slice(&ciphertext);
+    sealed.extend_from_slice(&tag);
+    sealed
+}
+
+/// Decrypt a message produced by `seal`, or `None` if it was not sealed with the same key
+/// and context
+pub fn open(key: &[u8; 32], context: &str, sealed: &[u8]) -> Option<Vec<u8>> {
+    if sealed.len() < NONCE_LEN + TAG_LEN {
+        return None;
+    }
+    let (nonce, rest) = sealed.split_at(NONCE_LEN);
+    let (ciphertext, tag) = rest.split_at(rest.len() - TAG_LEN);
+
+    let mut plaintext = vec![0u8; ciphertext.len()];
+    let authentic = AesGcm::new(KeySize::KeySize256, key, nonce, context.as_bytes()).decrypt(
+        ciphertext,
+        &mut plaintext,
+        tag,
+    );
+
+    if authentic {
+        Some(plaintext)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn seal_and_open() {
+        let key = [7u8; 32];
+        let sealed = seal(&key, &context("dkg-round3", 1, 2), b"share");
+
+        assert_eq!(
+            open(&key, &context("dkg-round3", 1, 2), &sealed),
+            Some(b"share".to_vec())
+        );
+        assert_eq!(
+            open(&key, &context("dkg-round3", 1, 2), &sealed[..20]),
+            None
+        );
+        // Reflected back to the sender, or opened with someone else's key
+        assert_eq!(open(&key, &context("dkg-round3", 2, 1), &sealed), None);
+        assert_eq!(
+            open(&[8u8; 32], &context("dkg-round3", 1, 2), &sealed),
+            None
+        );
+    }
+}
@@ -2,12 +2,15 @@ use std::convert::From;
 use std::error::Error;
 use std::fmt;
 use std::io;
+use std::path::PathBuf;
 use std::string;
 use std::time;
 
 use toml::de::Error as TOMLError;
 use serde::{Deserialize, Serialize};
 
+use crate::wire;
+
 #[derive(Debug)]
 pub struct CommandError {
     command: String,
@@ -44,12 +47,48 @@ impl Error for CommandError {
 #[derive(Debug)]
 pub enum UserError {
     TagMessage,
+    ExistingHook(PathBuf),
+    MissingChannel,
+    RoundFile(String),
+    IndexConflict(u16),
+    InvalidIdentity(PathBuf),
+    KeyFile(PathBuf),
+    ClientKeyWithoutCertificate,
 }
 
 impl fmt::Display for UserError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             UserError::TagMessage => write!(f, "No tag message was provided"),
+            UserError::ExistingHook(path) => write!(
+                f,
+                "{} already exists and was not installed by dit (use -f to overwrite it)",
+                path.display()
+            ),
+            UserError::MissingChannel => write!(
+                f,
+                "The config needs either a [server] or a [channel] section"
+            ),
+            UserError::RoundFile(reason) => write!(f, "Could not import round file: {}", reason),
+            UserError::IndexConflict(index) => write!(
+                f,
+                "Another participant has signed up as party {} (pick a free one with --index)",
+                index
+            ),
+            UserError::InvalidIdentity(path) => write!(
+                f,
+                "{} does not hold a valid identity key",
+                path.display()
+            ),
+            UserError::KeyFile(path) => write!(
+                f,
+                "{} holds an armored block that could not be read",
+                path.display()
+            ),
+            UserError::ClientKeyWithoutCertificate => write!(
+                f,
+                "The TLS config sets client_key, but not the client_certificate it belongs to"
+            ),
         }
     }
 }
@@ -67,6 +106,7 @@ pub enum CriticalError {
     Command(CommandError),
     User(UserError),
     Clock(time::SystemTimeError),
+    Protocol(ProtocolError),
 }
 
 pub type Result<T> = std::result::Result<T, CriticalError>;
@@ -114,6 +154,7 @@ impl fmt::Display for CriticalError {
             CriticalError::Command(ref err) => write!(f, "[Command]\t{}", err),
             CriticalError::User(ref err) => write!(f, "[User]\t{}", err),
             CriticalError::Clock(ref err) => write!(f, "[Clock]\t{}", err),
+            CriticalError::Protocol(ref err) => write!(f, "[Protocol]\t{}", err),
         }
     }
 }
@@ -154,6 +195,12 @@ impl From<CommandError> for CriticalError {
     }
 }
 
+impl From<ProtocolError> for CriticalError {
+    fn from(protocol_error: ProtocolError) -> Self {
+        CriticalError::Protocol(protocol_error)
+    }
+}
+
 impl From<TOMLError> for CriticalError {
     fn from(toml_error: TOMLError) -> Self {
         CriticalError::TOML(toml_error)
@@ -174,16 +221,193 @@ impl Error for CriticalError {
             CriticalError::Command(ref err) => Some(err),
             CriticalError::User(ref err) => Some(err),
             CriticalError::Clock(ref err) => Some(err),
+            CriticalError::Protocol(ref err) => Some(err),
         }
     }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum ProtocolError {
-    Timeout,
+    Timeout(String, Vec<u16>),
+    Cancelled,
     Connection,
     Blame,
     Full,
+    Pending,
+    Decryption(u16, String),
+    Authentication(u16, String),
+    Equivocation(String, Vec<u16>),
+    Malformed(u16, String),
+    Version(Vec<u16>),
+    ServerVersion(Option<(u8, u8)>),
+    Unauthorized,
+    Unlisted,
+    Expired,
+    Refused(Refusal),
+    /// The transparency log of the server does not vouch for what was signed
+    Log(String),
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::Timeout(round, parties) => write!(
+                f,
+                "Timed out in {} waiting for the messages of parties {}",
+                round,
+                parties
+                    .iter()
+                    .map(|party| party.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            ProtocolError::Cancelled => write!(f, "The operation was cancelled"),
+            ProtocolError::Connection => write!(f, "Could not reach the channel"),
+            ProtocolError::Blame => write!(f, "The protocol has been aborted"),
+            ProtocolError::Full => write!(f, "All of the participant slots are taken"),
+            ProtocolError::Pending => write!(f, "Waiting for the messages of the other participants"),
+            ProtocolError::Decryption(party, round) => write!(
+                f,
+                "The message of party {} for {} could not be decrypted",
+                party, round
+            ),
+            ProtocolError::Authentication(party, round) => write!(
+                f,
+                "Rejected the message of party {} for {}: missing or invalid signature",
+                party, round
+            ),
+            ProtocolError::Equivocation(round, parties) => write!(
+                f,
+                "The channel equivocated in {}: parties {} received different broadcasts than this one",
+                round,
+                parties
+                    .iter()
+                    .map(|party| party.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            ProtocolError::Malformed(party, round) => write!(
+                f,
+                "The message of party {} for {} could not be read",
+                party, round
+            ),
+            ProtocolError::Version(parties) => write!(
+                f,
+                "Parties {} run another version of dit than this one (wire version {})",
+                parties
+                    .iter()
+                    .map(|party| party.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                wire::VERSION
+            ),
+            ProtocolError::ServerVersion(Some((min, max))) => write!(
+                f,
+                "The server speaks wire versions {} to {}, and this build only {}",
+                min,
+                max,
+                wire::VERSION
+            ),
+            ProtocolError::ServerVersion(None) => write!(
+                f,
+                "The server predates versioning of the wire format, and has to be upgraded"
+            ),
+            ProtocolError::Unauthorized => write!(
+                f,
+                "The server does not know this participant, check the token or client certificate in the config"
+            ),
+            ProtocolError::Expired => write!(
+                f,
+                "The server aborted the operation, as it stalled for longer than the server allows"
+            ),
+            ProtocolError::Unlisted => write!(
+                f,
+                "The identity key of this participant is not in the roster of the config (see `dit identity`)"
+            ),
+            ProtocolError::Refused(refusal) => {
+                write!(f, "The server refused the request: {}", refusal)
+            }
+            ProtocolError::Log(reason) => {
+                write!(f, "The transparency log of the server does not check out: {}", reason)
+            }
+        }
+    }
+}
+
+impl Error for ProtocolError {}
+
+impl From<Refusal> for ProtocolError {
+    fn from(refusal: Refusal) -> Self {
+        match refusal {
+            Refusal::Unauthorized => ProtocolError::Unauthorized,
+            Refusal::Unlisted => ProtocolError::Unlisted,
+            Refusal::Full => ProtocolError::Full,
+            // Treated like the server being down
+            Refusal::Unavailable => ProtocolError::Connection,
+            refusal => ProtocolError::Refused(refusal),
+        }
+    }
+}
+
+/// Why `dit-server` turned down a request, which it sends as the body of the response along
+/// with the matching status code
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Refusal {
+    /// The request could not be read, or one of its fields is out of bounds
+    Invalid(String),
+    TooLarge,
+    Unauthorized,
+    UnknownProject,
+    UnknownSession,
+    /// Nothing is stored under the key
+    Missing,
+    /// Another message is already stored under the key, which is never replaced
+    Conflict,
+    /// The operation of the session has ended, and takes neither signups nor messages
+    Ended,
+    /// Signing up for a different kind of operation than the one of the session
+    WrongOperation,
+    Full,
+    /// Giving up a party number once every party has signed up, and the others may have moved on
+    Started,
+    Unlisted,
+    /// The server could not keep the change, and did not make it
+    Unavailable,
+}
+
+impl Refusal {
+    /// HTTP status code the server answers with
+    pub fn status(&self) -> u16 {
+        match self {
+            Refusal::Invalid(_) => 422,
+            Refusal::TooLarge => 413,
+            Refusal::Unauthorized | Refusal::Unlisted => 403,
+            Refusal::UnknownProject | Refusal::UnknownSession | Refusal::Missing => 404,
+            Refusal::Ended => 410,
+            Refusal::Conflict | Refusal::WrongOperation | Refusal::Full | Refusal::Started => 409,
+            Refusal::Unavailable => 503,
+        }
+    }
+}
+
+impl fmt::Display for Refusal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Refusal::Invalid(reason) => write!(f, "invalid request ({})", reason),
+            Refusal::TooLarge => write!(f, "the request is larger than the server accepts"),
+            Refusal::Unauthorized => write!(f, "unknown client"),
+            Refusal::UnknownProject => write!(f, "no such project"),
+            Refusal::UnknownSession => write!(f, "no such session, it may have expired"),
+            Refusal::Missing => write!(f, "no message under that key"),
+            Refusal::Conflict => write!(f, "a different message is already stored under that key"),
+            Refusal::Ended => write!(f, "the operation has already ended"),
+            Refusal::WrongOperation => write!(f, "the session runs a different kind of operation"),
+            Refusal::Full => write!(f, "all of the participant slots are taken"),
+            Refusal::Started => write!(f, "the operation is already under way"),
+            Refusal::Unlisted => write!(f, "the identity is not in the roster of the operation"),
+            Refusal::Unavailable => write!(f, "the server could not record the change"),
+        }
+    }
 }
 
 pub fn unwrap_or_exit<T>(wrapped: Result<T>) -> T {
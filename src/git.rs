@@ -9,6 +9,7 @@ use crate::utils::Tag;
 use std::collections::HashMap;
 
 const GIT: &str = "git";
+const GPG: &str = "gpg";
 const TAG_MSG: &'static str = "
 # Write a message for tag:
 #   {}
@@ -73,6 +74,22 @@ pub fn get_repo_root() -> Result<String> {
     parse_cmd_output(&repo_root.stdout)
 }
 
+/// Location of the hooks directory, taking `core.hooksPath` into account
+pub fn get_hooks_dir() -> Result<PathBuf> {
+    let mut hooks_cmd = Command::new(GIT);
+    hooks_cmd.args(&["rev-parse", "--git-path", "hooks"]);
+
+    let hooks = hooks_cmd.output()?;
+    if !hooks.stdout.is_empty() {
+        Ok(PathBuf::from(parse_cmd_output(&hooks.stdout)?))
+    } else {
+        let command = format!("{:?}", hooks_cmd);
+        let error = parse_cmd_output(&hooks.stderr)?;
+
+        Err(CommandError::new(command, error).into())
+    }
+}
+
 pub fn get_commit_hash(commit: &str) -> Result<String> {
     let mut commit_cmd = Command::new(GIT);
     commit_cmd.args(&["rev-parse", commit]);
@@ -115,6 +132,38 @@ pub fn get_current_timezone() -> Result<String> {
     Ok(String::from("+0000"))
 }
 
+/// Format the given epoch as an RFC 3339 UTC timestamp, as expected by the in-toto
+/// specification. Relies on GNU `date` for the calendar arithmetic.
+pub fn get_rfc3339_timestamp(epoch: u64) -> Result<String> {
+    let mut date_cmd = Command::new("date");
+    date_cmd.args(&["-u", "-d", &format!("@{}", epoch), "+%Y-%m-%dT%H:%M:%SZ"]);
+
+    let date = date_cmd.output()?;
+    if !date.stdout.is_empty() {
+        parse_cmd_output(&date.stdout)
+    } else {
+        let command = format!("{:?}", date_cmd);
+        let error = parse_cmd_output(&date.stderr)?;
+
+        Err(CommandError::new(command, error).into())
+    }
+}
+
+pub fn get_remote_url(remote: &str) -> Result<String> {
+    let mut url_cmd = Command::new(GIT);
+    url_cmd.args(&["config", "--get", &format!("remote.{}.url", remote)]);
+
+    let url = url_cmd.output()?;
+    if !url.stdout.is_empty() {
+        parse_cmd_output(&url.stdout)
+    } else {
+        let command = format!("{:?}", url_cmd);
+        let error = parse_cmd_output(&url.stderr)?;
+
+        Err(CommandError::new(command, error).into())
+    }
+}
+
 pub fn get_git_tag_message(tag: &str, env: &GitEnv) -> Result<String> {
     let editor = env.git_config.get("editor")
                 .expect("Git does not expose an `editor` variable!");
@@ -202,6 +251,44 @@ pub fn create_git_tag(tag_name: &str, tag_body: &str, env: &GitEnv) -> Result<()
     }
 }
 
+/// Import OpenPGP certificates into the keyring of gpg(1), which `git verify-tag` checks
+/// signatures against
+pub fn import_keys(packets: &[u8]) -> Result<()> {
+    let mut gpg_child = Command::new(GPG)
+        .args(&["--batch", "--import"])
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    gpg_child.stdin.take().unwrap().write_all(packets)?;
+    let output = gpg_child.wait_with_output()?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        let command = "gpg --batch --import".to_owned();
+        let error = parse_cmd_output(&output.stderr)?;
+
+        Err(CommandError::new(command, error).into())
+    }
+}
+
+/// Contents of the annotated tag `tag`, with its signature if it has one
+pub fn get_tag_object(tag: &str) -> Result<String> {
+    let mut tag_cmd = Command::new(GIT);
+    tag_cmd.args(&["cat-file", "tag", tag]);
+
+    let output = tag_cmd.output()?;
+    if output.status.success() {
+        Ok(String::from_utf8(output.stdout)?)
+    } else {
+        let command = format!("{:?}", tag_cmd);
+        let error = parse_cmd_output(&output.stderr)?;
+
+        Err(CommandError::new(command, error).into())
+    }
+}
+
 pub fn git_owning_subcommand(subcommand: &str, args: &[&str]) -> Result<()> {
     let mut git_child = Command::new(GIT);
     git_child.stdin(Stdio::inherit()).stdout(Stdio::inherit());
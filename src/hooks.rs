@@ -0,0 +1,225 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::errors::{CriticalError, Result, UserError};
+
+/// First line after the shebang of every hook we write, so that re-running the installer
+/// can tell our hooks apart from ones the user wrote themselves
+pub const HOOK_MARKER: &str = "# Installed by dit";
+pub const DEFAULT_RELEASE_PATTERN: &str = "v*";
+
+/// Shared verification routine: a release tag is accepted only if GnuPG reports a good
+/// signature made by the project key. Lightweight tags fail `verify-tag` outright.
+const CHECK_TAG: &str = r#"
+KEYID="@KEYID@"
+PATTERN="@PATTERN@"
+ZERO="0000000000000000000000000000000000000000"
+
+check_tag() {
+    ref="$1"
+    sha="$2"
+
+    case "$ref" in
+    refs/tags/*) ;;
+    *) return 0 ;;
+    esac
+
+    # Deleting a tag does not need a signature
+    [ "$sha" = "$ZERO" ] && return 0
+
+    tag="${ref#refs/tags/}"
+    case "$tag" in
+    $PATTERN) ;;
+    *) return 0 ;;
+    esac
+
+    if git verify-tag --raw "$sha" 2>&1 | grep -q "^\[GNUPG:\] GOODSIG $KEYID "; then
+        return 0
+    fi
+
+    echo "dit: refusing $tag, it does not carry a valid signature by the project key $KEYID" >&2
+    echo "dit: release tags have to be created with \`dit start-tag\`" >&2
+    return 1
+}
+"#;
+
+const PRE_PUSH: &str = r#"
+status=0
+while read local_ref local_sha remote_ref remote_sha
+do
+    check_tag "$local_ref" "$local_sha" || status=1
+done
+exit $status
+"#;
+
+const PRE_RECEIVE: &str = r#"
+status=0
+while read old_sha new_sha ref
+do
+    check_tag "$ref" "$new_sha" || status=1
+done
+exit $status
+"#;
+
+const REMINDER: &str = r#"
+# Only a reminder, this should never get in the way of the checkout or merge
+if command -v dit > /dev/null 2>&1; then
+    dit pending
+fi
+exit 0
+"#;
+
+fn tag_check_hook(body: &str, keyid: &[u8], pattern: &str) -> String {
+    let keyid: String = keyid.iter().map(|b| format!("{:02X}", b)).collect();
+    let check = CHECK_TAG
+        .replace("@KEYID@", &keyid)
+        .replace("@PATTERN@", pattern);
+
+    format!("#!/bin/sh\n{}\n{}{}", HOOK_MARKER, check, body)
+}
+
+fn reminder_hook() -> String {
+    format!("#!/bin/sh\n{}\n{}", HOOK_MARKER, REMINDER)
+}
+
+/// Install the enforcement hooks into `hooks_dir`.
+///
+/// Regular repositories get a `pre-push` hook that verifies release tags before they leave
+/// the machine, and `post-merge`/`post-checkout` hooks that point out pending operations.
+/// Bare (mirror) repositories only receive pushes, so they get the `pre-receive` check instead.
+///
+/// # Warning
+/// Hooks not written by `dit` are left untouched unless `force` is set.
+pub fn install_hooks<P: AsRef<Path>>(
+    hooks_dir: P,
+    keyid: &[u8],
+    pattern: &str,
+    bare: bool,
+    force: bool,
+) -> Result<Vec<PathBuf>> {
+    let hooks = if bare {
+        vec![("pre-receive", tag_check_hook(PRE_RECEIVE, keyid, pattern))]
+    } else {
+        vec![
+            ("pre-push", tag_check_hook(PRE_PUSH, keyid, pattern)),
+            ("post-merge", reminder_hook()),
+            ("post-checkout", reminder_hook()),
+        ]
+    };
+
+    fs::create_dir_all(&hooks_dir)?;
+
+    // Check everything before writing anything, so we never end up with half the hooks
+    let paths: Vec<PathBuf> = hooks
+        .iter()
+        .map(|(name, _)| hooks_dir.as_ref().join(name))
+        .collect();
+    if !force {
+        for path in paths.iter() {
+            if let Ok(existing) = fs::read_to_string(path) {
+                if !existing.contains(HOOK_MARKER) {
+                    return Err(CriticalError::User(UserError::ExistingHook(path.clone())));
+                }
+            }
+        }
+    }
+
+    for (path, (_, contents)) in paths.iter().zip(hooks.iter()) {
+        fs::write(path, contents)?;
+        make_executable(path)?;
+    }
+
+    Ok(paths)
+}
+
+#[cfg(unix)]
+fn make_executable(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    Ok(fs::set_permissions(
+        path,
+        fs::Permissions::from_mode(0o755),
+    )?)
+}
+
+#[cfg(not(unix))]
+fn make_executable(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::temp_dir;
+
+    const KEYID: [u8; 8] = [0xDE, 0xAD, 0xBE, 0xEF, 0x01, 0x23, 0x45, 0x67];
+
+    #[test]
+    fn marks_the_hooks_it_writes() {
+        let dir = temp_dir("hooks-marker");
+        let paths = install_hooks(&dir, &KEYID, DEFAULT_RELEASE_PATTERN, false, false).unwrap();
+        assert_eq!(paths.len(), 3);
+
+        let pre_push = fs::read_to_string(dir.join("pre-push")).unwrap();
+        assert!(pre_push.starts_with(&format!("#!/bin/sh\n{}\n", HOOK_MARKER)));
+        assert!(pre_push.contains("KEYID=\"DEADBEEF01234567\""));
+        assert!(pre_push.contains("PATTERN=\"v*\""));
+
+        // Our own hooks get replaced on the next run
+        install_hooks(&dir, &KEYID, "release-*", false, false).unwrap();
+        let pre_push = fs::read_to_string(dir.join("pre-push")).unwrap();
+        assert!(pre_push.contains("PATTERN=\"release-*\""));
+
+        let bare = temp_dir("hooks-bare");
+        let paths = install_hooks(&bare, &KEYID, DEFAULT_RELEASE_PATTERN, true, false).unwrap();
+        assert_eq!(paths, vec![bare.join("pre-receive")]);
+
+        fs::remove_dir_all(&dir).unwrap();
+        fs::remove_dir_all(&bare).unwrap();
+    }
+
+    #[test]
+    fn leaves_foreign_hooks_alone() {
+        let dir = temp_dir("hooks-foreign");
+        fs::write(dir.join("post-merge"), "#!/bin/sh\nmake\n").unwrap();
+
+        match install_hooks(&dir, &KEYID, DEFAULT_RELEASE_PATTERN, false, false) {
+            Err(CriticalError::User(UserError::ExistingHook(path))) => {
+                assert_eq!(path, dir.join("post-merge"))
+            }
+            other => panic!("Overwrote a foreign hook: {:?}", other.map(|_| ())),
+        }
+        // Nothing gets written when one of the hooks is in the way
+        assert!(!dir.join("pre-push").exists());
+        assert_eq!(
+            fs::read_to_string(dir.join("post-merge")).unwrap(),
+            "#!/bin/sh\nmake\n"
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn force_replaces_foreign_hooks() {
+        let dir = temp_dir("hooks-force");
+        fs::write(dir.join("post-merge"), "#!/bin/sh\nmake\n").unwrap();
+
+        install_hooks(&dir, &KEYID, DEFAULT_RELEASE_PATTERN, false, true).unwrap();
+        assert!(fs::read_to_string(dir.join("post-merge"))
+            .unwrap()
+            .contains(HOOK_MARKER));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let mode = fs::metadata(dir.join("pre-push"))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o755);
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,116 @@
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use rand::rngs::OsRng;
+use secp256k1::{Message, PublicKey, SecretKey, Signature};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::errors::{CriticalError, Result, UserError};
+use crate::utils::{from_hex, to_hex};
+
+/// Long-term key a participant signs all of their protocol messages with, so that the others
+/// (and not the channel) can tell who sent them. Unlike the key shares, it exists before the
+/// first key generation, and its public half is what identifies a participant in the roster.
+pub struct Identity {
+    secret: SecretKey,
+    public: PublicKey,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredIdentity {
+    secret: String,
+}
+
+impl Identity {
+    /// Key that only lives as long as the process, for servers without storage
+    pub fn generate() -> Identity {
+        let secret = SecretKey::random(&mut OsRng);
+        Identity {
+            public: PublicKey::from_secret_key(&secret),
+            secret,
+        }
+    }
+
+    /// Read the identity stored at `path`, generating a new one on first use
+    pub fn load_or_create(path: &Path) -> Result<Identity> {
+        let secret = match fs::read_to_string(path) {
+            Ok(contents) => {
+                let stored: StoredIdentity = serde_json::from_str(&contents)?;
+                from_hex(&stored.secret)
+                    .and_then(|secret| SecretKey::parse_slice(&secret).ok())
+                    .ok_or_else(|| {
+                        CriticalError::User(UserError::InvalidIdentity(path.to_path_buf()))
+                    })?
+            }
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => {
+                let secret = SecretKey::random(&mut OsRng);
+                let stored = StoredIdentity {
+                    secret: to_hex(&secret.serialize()),
+                };
+
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                create_secret_file(path)?.write_all(serde_json::to_string(&stored)?.as_bytes())?;
+
+                secret
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok(Identity {
+            public: PublicKey::from_secret_key(&secret),
+            secret,
+        })
+    }
+
+    /// The compressed public key, as listed in the roster
+    pub fn public_key(&self) -> String {
+        to_hex(&self.public.serialize_compressed())
+    }
+
+    pub fn sign(&self, data: &[u8]) -> String {
+        let (signature, _) = secp256k1::sign(&digest(data), &self.secret);
+        to_hex(&signature.serialize())
+    }
+}
+
+/// Check that `signature` was made over `data` by the holder of `public_key`
+pub fn verify(public_key: &str, data: &[u8], signature: &str) -> bool {
+    let public = from_hex(public_key).and_then(|key| PublicKey::parse_slice(&key, None).ok());
+    let signature = from_hex(signature).and_then(|sig| Signature::parse_slice(&sig).ok());
+
+    match (public, signature) {
+        (Some(public), Some(signature)) => secp256k1::verify(&digest(data), &signature, &public),
+        _ => false,
+    }
+}
+
+fn digest(data: &[u8]) -> Message {
+    let mut hasher = Sha256::new();
+    hasher.input(data);
+
+    let mut hash = [0u8; 32];
+    hasher.result(&mut hash);
+    Message::parse(&hash)
+}
+
+/// Create the file of a new secret, which only its owner may read from the start. An
+/// identity created by someone else in the meantime is left alone.
+#[cfg(unix)]
+pub(crate) fn create_secret_file(path: &Path) -> io::Result<fs::File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn create_secret_file(path: &Path) -> io::Result<fs::File> {
+    fs::OpenOptions::new().write(true).create_new(true).open(path)
+}
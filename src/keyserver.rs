@@ -0,0 +1,835 @@
+use std::collections::BTreeMap;
+
+use crypto::digest::Digest;
+use crypto::sha1::Sha1;
+use crypto::sha2::{Sha224, Sha256, Sha384, Sha512};
+use secp256k1::{Message, PublicKey, PublicKeyFormat, Signature};
+
+use dit::provenance::sha256_hex;
+use dit::utils::to_hex;
+
+const SIGNATURE: u8 = 2;
+const PUBLIC_KEY: u8 = 6;
+const TRUST: u8 = 12;
+const USER_ID: u8 = 13;
+const PUBLIC_SUBKEY: u8 = 14;
+const USER_ATTRIBUTE: u8 = 17;
+
+const SUBKEY_BINDING: u8 = 0x18;
+const DIRECT_KEY: u8 = 0x1F;
+const KEY_REVOCATION: u8 = 0x20;
+const SUBKEY_REVOCATION: u8 = 0x28;
+const CERTIFICATION_REVOCATION: u8 = 0x30;
+
+const ISSUER: u8 = 16;
+const ISSUER_FINGERPRINT: u8 = 33;
+
+const ECDSA: u8 = 19;
+const SECP256K1: &[u8] = &[0x2B, 0x81, 0x04, 0x00, 0x0A];
+
+/// Certifications of each user ID by keys other than the primary one, which the server cannot
+/// verify without those keys, and so only keeps so many of
+const MAX_CERTIFICATIONS: usize = 32;
+
+/// One OpenPGP packet, kept as it was published, header included
+#[derive(Clone, Debug, PartialEq)]
+struct Packet {
+    tag: u8,
+    bytes: Vec<u8>,
+    /// Offset of the body, past the header
+    body: usize,
+}
+
+impl Packet {
+    fn body(&self) -> &[u8] {
+        &self.bytes[self.body..]
+    }
+
+    fn signature_type(&self) -> Option<u8> {
+        let body = self.body();
+        match body.first()? {
+            3 => body.get(2).copied(),
+            _ => body.get(1).copied(),
+        }
+    }
+
+    /// Fingerprint of a version 4 key (Section 12.2 of RFC 4880)
+    fn fingerprint(&self) -> Result<[u8; 20], String> {
+        let body = self.body();
+        if body.first() != Some(&4) {
+            return Err("only version 4 keys are supported".to_owned());
+        }
+
+        let mut hasher = Sha1::new();
+        hasher.input(&[0x99]);
+        hasher.input(&(body.len() as u16).to_be_bytes());
+        hasher.input(body);
+
+        let mut fingerprint = [0u8; 20];
+        hasher.result(&mut fingerprint);
+        Ok(fingerprint)
+    }
+
+    /// Whether the signature names the key with `fingerprint` as its issuer, either by
+    /// fingerprint or by key ID
+    fn issued_by(&self, fingerprint: &[u8; 20]) -> bool {
+        let keyid = &fingerprint[12..];
+        let body = self.body();
+
+        match body.first() {
+            Some(3) => body.get(7..15) == Some(keyid),
+            Some(4) => subpackets(body).iter().any(|(kind, data)| match *kind {
+                ISSUER => *data == keyid,
+                ISSUER_FINGERPRINT => data.get(1..) == Some(&fingerprint[..]),
+                _ => false,
+            }),
+            _ => false,
+        }
+    }
+
+    /// Point of a version 4 ECDSA key over secp256k1, the only kind of key dit generates
+    fn ecdsa_point(&self) -> Option<&[u8]> {
+        let body = self.body();
+        if body.first() != Some(&4) || body.get(5) != Some(&ECDSA) {
+            return None;
+        }
+
+        let oid = *body.get(6)? as usize;
+        if body.get(7..7 + oid)? != SECP256K1 {
+            return None;
+        }
+        let (point, _) = mpi(body.get(7 + oid..)?)?;
+        Some(point)
+    }
+
+    /// The key, user ID or attribute as signatures over it hash it, with a header giving the
+    /// length of its body (Section 5.2.4 of RFC 4880)
+    fn hashed(&self) -> Vec<u8> {
+        let body = self.body();
+        let mut hashed = match self.tag {
+            PUBLIC_KEY | PUBLIC_SUBKEY => {
+                let mut header = vec![0x99];
+                header.extend(&(body.len() as u16).to_be_bytes());
+                header
+            }
+            tag => {
+                let mut header = vec![if tag == USER_ID { 0xB4 } else { 0xD1 }];
+                header.extend(&(body.len() as u32).to_be_bytes());
+                header
+            }
+        };
+        hashed.extend(body);
+        hashed
+    }
+
+    /// Whether the version 4 ECDSA signature was made by `key` over `signed`
+    fn verifies(&self, key: &PublicKey, signed: &[&[u8]]) -> bool {
+        verify(self.body(), key, signed).unwrap_or(false)
+    }
+}
+
+/// Order of the group of secp256k1, which the integers of its signatures stay below
+const ORDER: [u8; 32] = [
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFE,
+    0xBA, 0xAE, 0xDC, 0xE6, 0xAF, 0x48, 0xA0, 0x3B, 0xBF, 0xD2, 0x5E, 0x8C, 0xD0, 0x36, 0x41, 0x41,
+];
+
+/// Check the signature with packet body `body` against `key`. It covers `signed`, followed by
+/// the hashed part of the signature and its trailer (Section 5.2.4 of RFC 4880), and `None`
+/// stands for signatures that cannot be read.
+fn verify(body: &[u8], key: &PublicKey, signed: &[&[u8]]) -> Option<bool> {
+    if body.first() != Some(&4) || body.get(2) != Some(&ECDSA) {
+        return None;
+    }
+
+    let length =
+        |at: usize| Some(u16::from_be_bytes([*body.get(at)?, *body.get(at + 1)?]) as usize);
+    let hashed = body.get(..6 + length(4)?)?;
+    let rest = body.get(hashed.len() + 2 + length(hashed.len())?..)?;
+    let (r, rest) = mpi(rest.get(2..)?)?;
+    let (s, _) = mpi(rest)?;
+
+    let mut trailer = hashed.to_vec();
+    trailer.extend(&[0x04, 0xFF]);
+    trailer.extend(&(hashed.len() as u32).to_be_bytes());
+    let mut parts = signed.to_vec();
+    parts.push(&trailer);
+    let digest = digest(body[3], &parts)?;
+
+    // Digests longer than the order are cut to its size, and shorter ones taken as they are
+    let mut message = [0u8; 32];
+    let size = digest.len().min(32);
+    message[32 - size..].copy_from_slice(&digest[..size]);
+
+    let mut signature = [0u8; 64];
+    for (integer, at) in [(r, 0), (s, 32)] {
+        let start = integer
+            .iter()
+            .position(|&b| b != 0)
+            .unwrap_or(integer.len());
+        let integer = &integer[start..];
+        if integer.len() > 32 {
+            return None;
+        }
+        signature[at + 32 - integer.len()..at + 32].copy_from_slice(integer);
+        if signature[at..at + 32] >= ORDER[..] {
+            return None;
+        }
+    }
+
+    Some(secp256k1::verify(
+        &Message::parse(&message),
+        &Signature::parse(&signature),
+        key,
+    ))
+}
+
+/// Digest of `parts` with the hash algorithm numbered `algorithm` (Section 9.4 of RFC 4880),
+/// for the SHA-2 ones
+fn digest(algorithm: u8, parts: &[&[u8]]) -> Option<Vec<u8>> {
+    let mut hasher: Box<dyn Digest> = match algorithm {
+        8 => Box::new(Sha256::new()),
+        9 => Box::new(Sha384::new()),
+        10 => Box::new(Sha512::new()),
+        11 => Box::new(Sha224::new()),
+        _ => return None,
+    };
+    for part in parts {
+        hasher.input(part);
+    }
+
+    let mut digest = vec![0; hasher.output_bytes()];
+    hasher.result(&mut digest);
+    Some(digest)
+}
+
+/// Split the multiprecision integer at the start of `buffer` from what follows it (Section 3.2
+/// of RFC 4880)
+fn mpi(buffer: &[u8]) -> Option<(&[u8], &[u8])> {
+    let bits = u16::from_be_bytes([*buffer.first()?, *buffer.get(1)?]) as usize;
+    let end = 2 + bits.div_ceil(8);
+    Some((buffer.get(2..end)?, buffer.get(end..)?))
+}
+
+/// Split `buffer` into packets, with either old or new format headers (Section 4.2 of
+/// RFC 4880). Partial body lengths only appear in messages, never in certificates.
+fn packets(buffer: &[u8]) -> Result<Vec<Packet>, String> {
+    let mut packets = Vec::new();
+    let mut offset = 0;
+
+    while offset < buffer.len() {
+        let rest = &buffer[offset..];
+        let truncated = || "a packet header is cut short".to_owned();
+        let byte = |at: usize| rest.get(at).map(|&b| b as usize).ok_or_else(truncated);
+        let length = |at: usize, size: usize| {
+            let bytes = rest.get(at..at + size).ok_or_else(truncated)?;
+            Ok::<_, String>(bytes.iter().fold(0, |len, &b| (len << 8) | b as usize))
+        };
+
+        let header = rest[0];
+        if header & 0x80 == 0 {
+            return Err("the data is not made of OpenPGP packets".to_owned());
+        }
+
+        let (tag, body, len) = if header & 0x40 != 0 {
+            match byte(1)? {
+                first @ 0..=191 => (header & 0x3F, 2, first),
+                first @ 192..=223 => (header & 0x3F, 3, ((first - 192) << 8) + byte(2)? + 192),
+                255 => (header & 0x3F, 6, length(2, 4)?),
+                _ => return Err("partial body lengths are not supported".to_owned()),
+            }
+        } else {
+            let tag = (header >> 2) & 0x0F;
+            match header & 0x03 {
+                0 => (tag, 2, byte(1)?),
+                1 => (tag, 3, length(1, 2)?),
+                2 => (tag, 5, length(1, 4)?),
+                _ => (tag, 1, rest.len() - 1),
+            }
+        };
+
+        let bytes = rest
+            .get(..body + len)
+            .ok_or_else(|| "a packet is cut short".to_owned())?;
+        packets.push(Packet {
+            tag,
+            bytes: bytes.to_vec(),
+            body,
+        });
+        offset += body + len;
+    }
+
+    Ok(packets)
+}
+
+/// Hashed and unhashed subpackets of a version 4 signature, as their types and contents
+fn subpackets(body: &[u8]) -> Vec<(u8, &[u8])> {
+    let mut subpackets = Vec::new();
+    let mut offset = 4;
+
+    // Both areas start with their length in two bytes
+    for _ in 0..2 {
+        let area = match body.get(offset..offset + 2) {
+            Some(size) => {
+                let size = u16::from_be_bytes([size[0], size[1]]) as usize;
+                offset += 2 + size;
+                body.get(offset - size..offset).unwrap_or(&[])
+            }
+            None => break,
+        };
+
+        let mut at = 0;
+        while at < area.len() {
+            let (header, len) = match area[at] {
+                first @ 0..=191 => (1, first as usize),
+                first @ 192..=254 => match area.get(at + 1) {
+                    Some(&second) => (2, ((first as usize - 192) << 8) + second as usize + 192),
+                    None => break,
+                },
+                255 => match area.get(at + 1..at + 5) {
+                    Some(size) => (
+                        5,
+                        u32::from_be_bytes([size[0], size[1], size[2], size[3]]) as usize,
+                    ),
+                    None => break,
+                },
+            };
+
+            match area.get(at + header..at + header + len) {
+                Some(data) if !data.is_empty() => subpackets.push((data[0] & 0x7F, &data[1..])),
+                _ => break,
+            }
+            at += header + len;
+        }
+    }
+
+    subpackets
+}
+
+/// Transferable public key (Section 11.1 of RFC 4880): the primary key along with the
+/// signatures on it, its user IDs and its subkeys
+#[derive(Clone, Debug)]
+pub struct Certificate {
+    primary: Packet,
+    fingerprint: [u8; 20],
+    key: PublicKey,
+    /// Revocations and direct-key signatures of the primary key
+    signatures: Vec<Packet>,
+    /// User IDs and attributes, each with its certifications
+    users: Vec<(Packet, Vec<Packet>)>,
+    subkeys: Vec<(Packet, Vec<Packet>)>,
+}
+
+impl Certificate {
+    fn new(primary: Packet) -> Result<Certificate, String> {
+        let key = primary
+            .ecdsa_point()
+            .and_then(|point| PublicKey::parse_slice(point, Some(PublicKeyFormat::Full)).ok())
+            .ok_or_else(|| "only the ECDSA keys over secp256k1 of dit are supported".to_owned())?;
+
+        Ok(Certificate {
+            fingerprint: primary.fingerprint()?,
+            key,
+            primary,
+            signatures: Vec::new(),
+            users: Vec::new(),
+            subkeys: Vec::new(),
+        })
+    }
+
+    /// Fingerprint of the primary key, in upper case hex as keyservers print it
+    pub fn fingerprint(&self) -> String {
+        to_hex(&self.fingerprint).to_uppercase()
+    }
+
+    /// Digest of the public key, which the transparency log of the project records when
+    /// generating it
+    pub fn key_digest(&self) -> String {
+        sha256_hex(&self.key.serialize())
+    }
+
+    pub fn is_revoked(&self) -> bool {
+        self.signatures
+            .iter()
+            .any(|signature| signature.signature_type() == Some(KEY_REVOCATION))
+    }
+
+    /// Creation time of the primary key, in seconds since the epoch
+    fn created(&self) -> u32 {
+        match self.primary.body().get(1..5) {
+            Some(time) => u32::from_be_bytes([time[0], time[1], time[2], time[3]]),
+            None => 0,
+        }
+    }
+
+    fn algorithm(&self) -> u8 {
+        self.primary.body().get(5).copied().unwrap_or(0)
+    }
+
+    /// User IDs, leaving out the attributes (such as photos)
+    fn user_ids(&self) -> impl Iterator<Item = &[u8]> {
+        self.users
+            .iter()
+            .filter(|(user, _)| user.tag == USER_ID)
+            .map(|(user, _)| user.body())
+    }
+
+    /// Whether the certificate is the one `search` asks for in an HKP request: a key ID or
+    /// fingerprint with a `0x` prefix, or else part of a user ID, regardless of case
+    pub fn matches(&self, search: &str) -> bool {
+        let hex = search
+            .strip_prefix("0x")
+            .or_else(|| search.strip_prefix("0X"));
+        if let Some(hex) = hex {
+            return hex.len() >= 8 && self.fingerprint().ends_with(&hex.to_uppercase());
+        }
+
+        let search = search.to_lowercase();
+        self.user_ids().any(|uid| {
+            String::from_utf8_lossy(uid)
+                .to_lowercase()
+                .contains(&search)
+        })
+    }
+
+    /// Add the signatures, user IDs and subkeys of `other` that this certificate is missing
+    fn merge(&mut self, other: Certificate) {
+        merge_signatures(&mut self.signatures, other.signatures);
+        merge_components(&mut self.users, other.users);
+        merge_components(&mut self.subkeys, other.subkeys);
+    }
+
+    /// Drop the signatures that do not verify against the primary key, and the user IDs,
+    /// attributes and subkeys left without a self-signature binding them to it. Certifications
+    /// by other keys are kept as long as they do not claim to be by the primary key, up to
+    /// `MAX_CERTIFICATIONS` for each user ID.
+    fn keep_verified(&mut self) {
+        let key = self.primary.hashed();
+        let fingerprint = self.fingerprint;
+        let public = &self.key;
+
+        self.signatures.retain(|signature| {
+            matches!(
+                signature.signature_type(),
+                Some(KEY_REVOCATION) | Some(DIRECT_KEY)
+            ) && signature.verifies(public, &[&key])
+        });
+
+        let users = std::mem::take(&mut self.users);
+        self.users = users
+            .into_iter()
+            .filter_map(|(user, mut signatures)| {
+                let hashed = user.hashed();
+                let (mut bound, mut others) = (false, 0);
+                signatures.retain(|signature| {
+                    let kind = signature.signature_type();
+                    let certification = matches!(kind, Some(0x10..=0x13));
+                    if !certification && kind != Some(CERTIFICATION_REVOCATION) {
+                        return false;
+                    }
+                    if signature.verifies(public, &[&key, &hashed]) {
+                        bound |= certification;
+                        return true;
+                    }
+
+                    let kept = certification
+                        && !signature.issued_by(&fingerprint)
+                        && others < MAX_CERTIFICATIONS;
+                    others += kept as usize;
+                    kept
+                });
+                Some((user, signatures)).filter(|_| bound)
+            })
+            .collect();
+
+        let subkeys = std::mem::take(&mut self.subkeys);
+        self.subkeys = subkeys
+            .into_iter()
+            .filter_map(|(subkey, mut signatures)| {
+                let hashed = subkey.hashed();
+                signatures.retain(|signature| {
+                    matches!(
+                        signature.signature_type(),
+                        Some(SUBKEY_BINDING) | Some(SUBKEY_REVOCATION)
+                    ) && signature.verifies(public, &[&key, &hashed])
+                });
+                let bound = signatures
+                    .iter()
+                    .any(|signature| signature.signature_type() == Some(SUBKEY_BINDING));
+                Some((subkey, signatures)).filter(|_| bound)
+            })
+            .collect();
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buffer = self.primary.bytes.clone();
+        for signature in self.signatures.iter() {
+            buffer.extend(&signature.bytes);
+        }
+        for (component, signatures) in self.users.iter().chain(self.subkeys.iter()) {
+            buffer.extend(&component.bytes);
+            for signature in signatures {
+                buffer.extend(&signature.bytes);
+            }
+        }
+
+        buffer
+    }
+
+    /// Lines of the machine-readable index of HKP for the certificate
+    fn index(&self) -> String {
+        let flags = if self.is_revoked() { "r" } else { "" };
+        let mut index = format!(
+            "pub:{}:{}::{}::{}\n",
+            self.fingerprint(),
+            self.algorithm(),
+            self.created(),
+            flags
+        );
+        for uid in self.user_ids() {
+            index.push_str(&format!("uid:{}:::{}\n", escape(uid), flags));
+        }
+
+        index
+    }
+}
+
+fn merge_signatures(signatures: &mut Vec<Packet>, others: Vec<Packet>) {
+    for signature in others {
+        if !signatures.contains(&signature) {
+            signatures.push(signature);
+        }
+    }
+}
+
+fn merge_components(
+    components: &mut Vec<(Packet, Vec<Packet>)>,
+    others: Vec<(Packet, Vec<Packet>)>,
+) {
+    for (component, signatures) in others {
+        match components.iter_mut().find(|(known, _)| *known == component) {
+            Some((_, known)) => merge_signatures(known, signatures),
+            None => components.push((component, signatures)),
+        }
+    }
+}
+
+/// Escape the characters of user IDs the index cannot hold as they are
+fn escape(uid: &[u8]) -> String {
+    uid.iter()
+        .map(|&b| match b {
+            b':' | b'%' => format!("%{:02X}", b),
+            0x20..=0x7E => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Certificates published to a project, by fingerprint. Along with whole certificates,
+/// signatures can be published on their own when they are revocations or direct-key
+/// signatures of a key the project already has. Only what verifies against the primary key
+/// of a certificate is kept, besides a few certifications of its user IDs by other keys.
+#[derive(Clone, Debug, Default)]
+pub struct Keyring(BTreeMap<String, Certificate>);
+
+impl Keyring {
+    /// The certificates as they would be after publishing the OpenPGP packets in `buffer`,
+    /// leaving the keyring as it is. Anything that is not part of a certificate the keyring
+    /// has or would have is refused, and signatures that do not verify are dropped, or refused
+    /// when published on their own.
+    pub fn updates(&self, buffer: &[u8]) -> Result<Vec<Certificate>, String> {
+        let mut certificates: Vec<Certificate> = Vec::new();
+        let mut loose = Vec::new();
+
+        for packet in packets(buffer)? {
+            let current = certificates.last_mut();
+            match (packet.tag, current) {
+                (PUBLIC_KEY, _) => certificates.push(Certificate::new(packet)?),
+                (SIGNATURE, None) => loose.push(packet),
+                (SIGNATURE, Some(certificate)) => {
+                    let signatures = match certificate.subkeys.last_mut() {
+                        Some((_, signatures)) => signatures,
+                        None => match certificate.users.last_mut() {
+                            Some((_, signatures)) => signatures,
+                            None => &mut certificate.signatures,
+                        },
+                    };
+                    signatures.push(packet);
+                }
+                (USER_ID, Some(certificate)) | (USER_ATTRIBUTE, Some(certificate))
+                    if certificate.subkeys.is_empty() =>
+                {
+                    certificate.users.push((packet, Vec::new()))
+                }
+                (PUBLIC_SUBKEY, Some(certificate)) => {
+                    packet.fingerprint()?;
+                    certificate.subkeys.push((packet, Vec::new()))
+                }
+                // Trust packets are local to the keyring they were exported from
+                (TRUST, _) => {}
+                (tag, _) => return Err(format!("unexpected packet of type {}", tag)),
+            }
+        }
+
+        let mut updated: BTreeMap<String, Certificate> = BTreeMap::new();
+        for certificate in certificates {
+            let fingerprint = certificate.fingerprint();
+            let known = updated
+                .get(&fingerprint)
+                .or_else(|| self.0.get(&fingerprint))
+                .cloned();
+            let mut merged = match known {
+                Some(mut known) => {
+                    known.merge(certificate);
+                    known
+                }
+                None => certificate,
+            };
+            merged.keep_verified();
+            updated.insert(fingerprint, merged);
+        }
+
+        for signature in loose {
+            match signature.signature_type() {
+                Some(KEY_REVOCATION) | Some(DIRECT_KEY) => {}
+                _ => return Err(
+                    "only revocations and direct-key signatures can be published without their key"
+                        .to_owned(),
+                ),
+            }
+
+            let issuer = updated
+                .values()
+                .chain(self.0.values())
+                .find(|certificate| signature.issued_by(&certificate.fingerprint))
+                .cloned()
+                .ok_or_else(|| "the signature is not issued by a key of the project".to_owned())?;
+
+            let mut certificate = updated.remove(&issuer.fingerprint()).unwrap_or(issuer);
+            merge_signatures(&mut certificate.signatures, vec![signature.clone()]);
+            certificate.keep_verified();
+            if !certificate.signatures.contains(&signature) {
+                return Err("the signature does not verify against the key it names".to_owned());
+            }
+            updated.insert(certificate.fingerprint(), certificate);
+        }
+
+        if updated.is_empty() {
+            return Err("there are no keys or signatures to publish".to_owned());
+        }
+
+        Ok(updated.into_values().collect())
+    }
+
+    /// Revocations of the primary keys among `certificates` that the keyring does not have as
+    /// revoked yet, one for each key along with its fingerprint. Signatures can be encoded
+    /// anew without changing what they sign, so any further revocation of a key that already
+    /// is revoked is left out.
+    pub fn new_revocations(&self, certificates: &[Certificate]) -> Vec<(String, Vec<u8>)> {
+        let mut revocations = Vec::new();
+        for certificate in certificates {
+            let known = self.0.get(&certificate.fingerprint());
+            if matches!(known, Some(known) if known.is_revoked()) {
+                continue;
+            }
+
+            let revocation = certificate
+                .signatures
+                .iter()
+                .find(|signature| signature.signature_type() == Some(KEY_REVOCATION));
+            if let Some(revocation) = revocation {
+                revocations.push((certificate.fingerprint(), revocation.bytes.clone()));
+            }
+        }
+
+        revocations
+    }
+
+    pub fn insert(&mut self, certificates: Vec<Certificate>) {
+        for certificate in certificates {
+            self.0.insert(certificate.fingerprint(), certificate);
+        }
+    }
+
+    /// Add `certificate`, merging it with the one with the same fingerprint if there is any
+    pub fn merge(&mut self, certificate: Certificate) {
+        match self.0.get_mut(&certificate.fingerprint()) {
+            Some(known) => known.merge(certificate),
+            None => self.insert(vec![certificate]),
+        }
+    }
+
+    pub fn certificates(&self) -> impl Iterator<Item = &Certificate> {
+        self.0.values()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Every certificate, one after the other, as `op=get` of HKP hands them out
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.certificates()
+            .flat_map(|certificate| certificate.to_bytes())
+            .collect()
+    }
+
+    /// The machine-readable listing of `op=index` of HKP
+    pub fn index(&self) -> String {
+        let mut index = format!("info:1:{}\n", self.0.len());
+        for certificate in self.certificates() {
+            index.push_str(&certificate.index());
+        }
+
+        index
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Key over secp256k1 as `gpg --quick-gen-key` creates it, with a single user ID
+    const PROJECT_KEY: &str = "\
+        mE8EatV49xMFK4EEAAoCAwROwoXHalRO6inG+DZFTvqwn8mAIRnYiKCTze2O+JMcPml/jHxiODgyM/VnpSTy\
+        UaoCQUTU30RZLUfREVj0QtmptB1Qcm9qZWN0IDxwcm9qZWN0QGV4YW1wbGUuY29tPoiQBBMTCAA4FiEElL3U\
+        sgnK77kvj8VmotxgVaP+K9YFAmrVePcCGwMFCwkIBwIGFQoJCAsCBBYCAwECHgECF4AACgkQotxgVaP+K9ao\
+        QgEApOJX2ezgtKWu0xJwX15dAxVH6jqcanJVfD0cWy2ZOi0BALGlcY74z6oHgSDqH0xIQA+pXgSRnnFu8Kmv\
+        UWlNiwd8";
+
+    /// The same key, with a certification by another key, a second user ID and a subkey
+    const CERTIFIED: &str = "\
+        mE8EatV49xMFK4EEAAoCAwROwoXHalRO6inG+DZFTvqwn8mAIRnYiKCTze2O+JMcPml/jHxiODgyM/VnpSTy\
+        UaoCQUTU30RZLUfREVj0QtmptB1Qcm9qZWN0IDxwcm9qZWN0QGV4YW1wbGUuY29tPoiQBBMTCAA4FiEElL3U\
+        sgnK77kvj8VmotxgVaP+K9YFAmrVePcCGwMFCwkIBwIGFQoJCAsCBBYCAwECHgECF4AACgkQotxgVaP+K9ao\
+        QgEApOJX2ezgtKWu0xJwX15dAxVH6jqcanJVfD0cWy2ZOi0BALGlcY74z6oHgSDqH0xIQA+pXgSRnnFu8Kmv\
+        UWlNiwd8iHUEEBYIAB0WIQS9aZ1UTPC/Z7jpUkSk1HPyQoUWeQUCatV5KwAKCRCk1HPyQoUWeUozAP9WUEev\
+        572XxCubDyH5jk90nXBCZWKqByMbQSykIfBR0QEAzioRuqrcYPhp3RCinRGICguS0E8poyn5td/z7iExpAq0\
+        JVByb2plY3QgUmVsZWFzZSA8cmVsZWFzZUBleGFtcGxlLmNvbT6IkAQTEwgAOBYhBJS91LIJyu+5L4/FZqLc\
+        YFWj/ivWBQJq1XkrAhsDBQsJCAcCBhUKCQgLAgQWAgMBAh4BAheAAAoJEKLcYFWj/ivW0bsA/iQIU8lOyyXm\
+        FYeRibDc5AqgltsYdzfIPsW8orvK6AI2AQDUw7mvToVBG2REQcTRR7xOHLT5k24l/RHABy7KXgMd0LhTBGrV\
+        eSsSBSuBBAAKAgME7Qh9527GZCoj+CC+U72QVh6fxCwkoV/BlqH3aw8nbKNzFOSliaOLH8FSBzh7JZtqUNxK\
+        dAT2c5LzLITBC0TOPwMBCAeIdwQYEwgAIBYhBJS91LIJyu+5L4/FZqLcYFWj/ivWBQJq1XkrAhsMAAoJEKLc\
+        YFWj/ivWi88A+KeJ7tAgspcn03mlOZti9GxfIBTZ84QHd+4SyMDdJmwBALjk1duAn85vKOVhBXhUU0EbCnXD\
+        AsapGMGTYcPsdwFZ";
+
+    /// Revocation of the key by itself
+    const REVOCATION: &str = "\
+        iHgEIBMIACAWIQSUvdSyCcrvuS+PxWai3GBVo/4r1gUCatV49wIdAAAKCRCi3GBVo/4r1gAYAQCpZfoOojVz\
+        8uCBW0KsDmka4kH0LNpBAHZ0vW4Z/TRwdwD7B78BLgGPPGhYrpgMLM5C/SCvY4frBxqzmyPOg6QMico=";
+
+    /// Key over Ed25519, which made the certification
+    const OTHER_KEY: &str = "\
+        mDMEatV5KxYJKwYBBAHaRw8BAQdA0OYLvyZHI+BXTY3+ze7erMpxerH2HVIFdr7mLaylonK0GUFsaWNlIDxh\
+        bGljZUBleGFtcGxlLmNvbT6IkAQTFggAOBYhBL1pnVRM8L9nuOlSRKTUc/JChRZ5BQJq1XkrAhsDBQsJCAcC\
+        BhUKCQgLAgQWAgMBAh4BAheAAAoJEKTUc/JChRZ5lJ4BAOVTV8XE+BWjCW59bJssk6vWOLOl4dUQI/1hwUhv\
+        yvhPAP0bBJninB0X/Jo7X08IPEReepkQW1Xdhaall3CnKOPoBQ==";
+
+    fn decode(data: &str) -> Vec<u8> {
+        base64::decode(data).unwrap()
+    }
+
+    /// Packet with a new format header, for bodies of less than 192 bytes
+    fn packet(tag: u8, body: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0xC0 | tag, body.len() as u8];
+        packet.extend(body);
+        packet
+    }
+
+    /// The one certificate publishing `buffer` updates
+    fn updated(keyring: &Keyring, buffer: &[u8]) -> Certificate {
+        let mut updated = keyring.updates(buffer).unwrap();
+        assert_eq!(updated.len(), 1);
+        updated.remove(0)
+    }
+
+    #[test]
+    fn merges_what_verifies() {
+        let mut keyring = Keyring::default();
+        let key = updated(&keyring, &decode(PROJECT_KEY));
+        assert_eq!(
+            key.fingerprint(),
+            "94BDD4B209CAEFB92F8FC566A2DC6055A3FE2BD6"
+        );
+        assert_eq!(key.to_bytes(), decode(PROJECT_KEY));
+        keyring.insert(vec![key]);
+
+        // The certification by the other key is kept, even though it cannot be verified
+        let merged = updated(&keyring, &decode(CERTIFIED));
+        assert_eq!(merged.user_ids().count(), 2);
+        assert_eq!(merged.users[0].1.len(), 2);
+        assert_eq!(merged.subkeys.len(), 1);
+        assert_eq!(merged.to_bytes(), decode(CERTIFIED));
+        keyring.insert(vec![merged]);
+
+        let again = updated(&keyring, &decode(PROJECT_KEY));
+        assert_eq!(again.to_bytes(), decode(CERTIFIED));
+    }
+
+    #[test]
+    fn drops_forged_components() {
+        let packets = packets(&decode(CERTIFIED)).unwrap();
+        let self_signature = &packets[2].bytes;
+        let mut binding = packets[7].bytes.clone();
+        *binding.last_mut().unwrap() ^= 1;
+
+        let mut forged = decode(PROJECT_KEY);
+        forged.extend(packet(USER_ID, b"Mallory <mallory@example.com>"));
+        forged.extend(self_signature);
+        forged.extend(&packets[6].bytes);
+        forged.extend(binding);
+
+        let certificate = updated(&Keyring::default(), &forged);
+        assert_eq!(certificate.to_bytes(), decode(PROJECT_KEY));
+    }
+
+    #[test]
+    fn rejects_forged_revocation() {
+        let mut keyring = Keyring::default();
+        keyring.insert(vec![updated(&keyring, &decode(PROJECT_KEY))]);
+
+        let mut forged = decode(REVOCATION);
+        *forged.last_mut().unwrap() ^= 1;
+        assert!(keyring.updates(&forged).is_err());
+
+        // Nor is it taken along with the key
+        let packets = packets(&decode(PROJECT_KEY)).unwrap();
+        let mut certificate = packets[0].bytes.clone();
+        certificate.extend(&forged);
+        certificate.extend(&packets[1].bytes);
+        certificate.extend(&packets[2].bytes);
+        let unrevoked = updated(&keyring, &certificate);
+        assert!(!unrevoked.is_revoked());
+        assert!(keyring.new_revocations(&[unrevoked]).is_empty());
+
+        let revoked = updated(&keyring, &decode(REVOCATION));
+        assert!(revoked.is_revoked());
+        let fingerprint = revoked.fingerprint();
+        let revocations = keyring.new_revocations(std::slice::from_ref(&revoked));
+        assert_eq!(revocations, vec![(fingerprint, decode(REVOCATION))]);
+        keyring.insert(vec![revoked]);
+
+        // Leaving out the unhashed subpackets makes another revocation out of the same one
+        let signature = &super::packets(&decode(REVOCATION)).unwrap()[0];
+        let body = signature.body();
+        let hashed = 6 + u16::from_be_bytes([body[4], body[5]]) as usize;
+        let unhashed = u16::from_be_bytes([body[hashed], body[hashed + 1]]) as usize;
+        let mut copy = body[..hashed].to_vec();
+        copy.extend(&[0, 0]);
+        copy.extend(&body[hashed + 2 + unhashed..]);
+        let again = updated(&keyring, &packet(SIGNATURE, &copy));
+        assert_eq!(again.signatures.len(), 2);
+        assert!(keyring.new_revocations(&[again]).is_empty());
+    }
+
+    #[test]
+    fn takes_only_keys_of_dit() {
+        assert!(Keyring::default().updates(&decode(OTHER_KEY)).is_err());
+
+        let key = updated(&Keyring::default(), &decode(PROJECT_KEY));
+        let primary = &packets(&decode(PROJECT_KEY)).unwrap()[0];
+        let point = primary.ecdsa_point().unwrap();
+        assert_eq!(point.len(), 65);
+        assert_eq!(key.key_digest(), sha256_hex(point));
+    }
+}
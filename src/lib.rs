@@ -7,3 +7,12 @@ pub mod errors;
 pub mod dkg;
 pub mod signing;
 pub mod comm;
+pub mod echo;
+pub mod encryption;
+pub mod identity;
+pub mod provenance;
+pub mod transparency;
+pub mod hooks;
+pub mod wire;
+#[cfg(test)]
+mod testing;
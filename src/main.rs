@@ -1,20 +1,28 @@
 use std::thread::sleep;
 
 use dit::app;
-use dit::comm::HTTPChannel;
+use dit::comm;
 
 use dit::config;
 use dit::errors;
 use dit::errors::Result;
 use dit::utils as utl;
 
+const JOIN_OR_START: &str =
+    "Participate in one of the pending operations (`n` to start a new one)?";
+
 fn main() -> Result<()> {
-    let app = app::build_app();
+    let matches = app::build_app().get_matches();
+
+    // The relay usually runs on a host without the repository, and talks over stdout
+    if let ("relay", Some(relay_matches)) = matches.subcommand() {
+        return app::relay(relay_matches);
+    }
 
     let git_dir = match dit::git::get_repo_root() {
         Ok(dir) => dir,
         Err(_) => {
-            match app.get_matches().subcommand() {
+            match matches.subcommand() {
                 (git_command, args) => app::git_passthrough(git_command, args)?,
             }
             return Ok(());
@@ -32,14 +40,19 @@ fn main() -> Result<()> {
         );
         sleep(utl::USER_SLEEP);
 
-        match app.get_matches().subcommand() {
+        match matches.subcommand() {
             (git_command, args) => app::git_passthrough(git_command, args)?,
         }
 
         return Ok(());
     } else {
-        println!("{}Config file present, working in dit mode", utl::DIT_LOG);
-        sleep(utl::USER_SLEEP);
+        // Hooks ask for pending operations on every checkout, and should not hold it up
+        let quiet = matches.subcommand_name() == Some("pending");
+
+        if !quiet {
+            println!("{}Config file present, working in dit mode", utl::DIT_LOG);
+            sleep(utl::USER_SLEEP);
+        }
 
         // In this case, we actually need to consider whether it's possible to use
         // dit-specific features. For that, we need to double check how to setup
@@ -47,50 +60,92 @@ fn main() -> Result<()> {
         // are required, to not slow execution too much.
         let config = config.unwrap();
 
-        let project = config.project.clone();
+        // The first Ctrl-C lets the running operation notify the others before stopping
+        ctrlc::set_handler(|| {
+            if comm::deadline::is_cancelled() {
+                std::process::exit(130);
+            }
+            eprintln!(
+                "{}Cancelling the operation, press Ctrl-C again to quit right away",
+                utl::DIT_LOG
+            );
+            comm::deadline::cancel();
+        })
+        .expect("Could not install the Ctrl-C handler");
 
-        // TODO Move this to an enum
-        let mut channel = HTTPChannel::new(
-            format!("http://{}:{}", config.server.address, config.server.port),
-            project,
-        );
+        let channel = comm::from_config(&config, git_dir.into())?;
 
         let mut reachable: bool = false;
-        let mut pending_operation = dit::utils::Operation::Idle;
+        let mut pending_operations = Vec::new();
 
-        match channel.get_current_operation() {
-            Ok(op) => {
+        match channel.list_operations() {
+            Ok(ops) => {
                 reachable = true;
-                pending_operation = op;
+                pending_operations = ops;
             }
-            Err(_) => {
-                println!(
-                    "{}No connection to server, working in compatibility mode",
-                    utl::DIT_LOG
-                );
-                sleep(utl::USER_SLEEP);
+            Err(err) => {
+                if !quiet {
+                    // Neither mismatched builds nor unknown participants are going to fix
+                    // themselves by retrying
+                    if matches!(
+                        err,
+                        errors::ProtocolError::ServerVersion(_)
+                            | errors::ProtocolError::Unauthorized
+                    ) {
+                        println!("{}{}", utl::DIT_LOG, err);
+                    }
+                    println!(
+                        "{}No connection to server, working in compatibility mode",
+                        utl::DIT_LOG
+                    );
+                    sleep(utl::USER_SLEEP);
+                }
             }
         };
 
-        match app.get_matches().subcommand() {
+        match matches.subcommand() {
+            ("start-tag", Some(tag_matches)) if tag_matches.is_present("offline") => {
+                let gitenv = dit::git::GitEnv::new();
+                let offline = app::offline_protocol_channel(&config, &gitenv, None)?;
+
+                println!("Initiating offline tagging");
+                let result = app::leader_tag(&*offline, &config, Some(tag_matches), &gitenv);
+                if app::await_round(result)? {
+                    println!("Finished tagging!");
+                }
+            }
+            ("start-attestation", Some(attest_matches)) if attest_matches.is_present("offline") => {
+                let gitenv = dit::git::GitEnv::new();
+                let offline = app::offline_protocol_channel(&config, &gitenv, None)?;
+
+                println!("Initiating offline provenance signing");
+                let result = app::leader_attest(&*offline, &config, Some(attest_matches), &gitenv);
+                if app::await_round(result)? {
+                    println!("Finished signing the provenance statement!");
+                }
+            }
+            ("round", Some(round_matches)) => {
+                let gitenv = dit::git::GitEnv::new();
+
+                match round_matches.subcommand() {
+                    ("export", export_matches) => {
+                        app::round_export(export_matches, &gitenv, &config)?
+                    }
+                    ("import", import_matches) => {
+                        app::round_import(import_matches, &gitenv, &config)?
+                    }
+                    _ => unreachable!(),
+                }
+            }
             ("keygen", keygen_matches) => {
                 if reachable == true {
                     let gitenv = dit::git::GitEnv::new();
 
-                    if pending_operation == dit::utils::Operation::Idle {
-                        dit::app::leader_keygen(&mut channel, &config, keygen_matches, &gitenv)?;
-                    } else {
-                        println!("{}", pending_operation);
-                        let choice = dit::utils::get_user_choice(
-                            "Participate in the pending operation?",
-                            &["y", "n"],
-                        )?;
-
-                        if choice == 0 {
-                            dit::app::participant_keygen(&mut channel, &gitenv, &config)?;
-                            println!("{}Key generation is complete, the key should be under the `.dit` folder", utl::DIT_LOG);
-                            sleep(utl::USER_SLEEP);
+                    match app::choose_operation(&*channel, &pending_operations, JOIN_OR_START)? {
+                        None => {
+                            dit::app::leader_keygen(&*channel, &config, keygen_matches, &gitenv)?
                         }
+                        Some(op) => app::participate(&*channel, &op, &gitenv, &config)?,
                     }
                 }
             }
@@ -98,64 +153,95 @@ fn main() -> Result<()> {
                 if reachable == true {
                     let gitenv = dit::git::GitEnv::new();
 
-                    if pending_operation == dit::utils::Operation::Idle {
-                        println!("Initiating tagging");
-
-                        app::leader_tag(&mut channel, &config, tag_matches, &gitenv)?;
-
-                        println!("Finished tagging!");
-                        println!("To make sure the other participants can see the tag, don't forget to push it");
-                    } else {
-                        println!("{}", pending_operation);
-
-                        if errors::unwrap_or_exit(dit::utils::get_user_choice(
-                            "Participate in the pending operation?",
-                            &["y", "n"],
-                        )) == 0
-                        {
-                            app::participant_tag(
-                                &mut channel,
-                                &pending_operation,
-                                &gitenv,
-                                &config,
-                            )?;
-                            println!("{}Tagging is done.", utl::DIT_LOG);
-                            sleep(utl::USER_SLEEP);
+                    match app::choose_operation(&*channel, &pending_operations, JOIN_OR_START)? {
+                        None => {
+                            println!("Initiating tagging");
+
+                            app::leader_tag(&*channel, &config, tag_matches, &gitenv)?;
+
+                            println!("Finished tagging!");
+                            println!("To make sure the other participants can see the tag, don't forget to push it");
                         }
+                        Some(op) => app::participate(&*channel, &op, &gitenv, &config)?,
                     }
                 }
             }
-            (other, args) => {
+            ("start-attestation", attest_matches) => {
                 if reachable == true {
-                    if pending_operation != dit::utils::Operation::Idle {
-                        println!("{}{}", utl::DIT_LOG, pending_operation);
-                        if errors::unwrap_or_exit(dit::utils::get_user_choice(
-                            "Participate in the pending operation?",
-                            &["y", "n"],
-                        )) == 0
-                        {
-                            let gitenv = dit::git::GitEnv::new();
-
-                            // TODO Could probably remove the config and just get the vars
-                            // from the op
-                            match pending_operation {
-                                dit::utils::Operation::KeyGen { .. } => {
-                                    app::participant_keygen(&mut channel, &gitenv, &config)?;
-                                    sleep(utl::USER_SLEEP);
-                                }
-                                dit::utils::Operation::SignTag { .. } => {
-                                    app::participant_tag(
-                                        &mut channel,
-                                        &pending_operation,
-                                        &gitenv,
-                                        &config,
-                                    )?;
-                                    sleep(utl::USER_SLEEP);
-                                }
-                                dit::utils::Operation::Blame {} => unimplemented!(),
-                                _ => unreachable!(),
-                            };
+                    let gitenv = dit::git::GitEnv::new();
+
+                    match app::choose_operation(&*channel, &pending_operations, JOIN_OR_START)? {
+                        None => {
+                            println!("Initiating provenance signing");
+
+                            app::leader_attest(&*channel, &config, attest_matches, &gitenv)?;
+
+                            println!("Finished signing the provenance statement!");
                         }
+                        Some(op) => app::participate(&*channel, &op, &gitenv, &config)?,
+                    }
+                }
+            }
+            ("install-hooks", hook_matches) => {
+                let gitenv = dit::git::GitEnv::new();
+                app::install_hooks(hook_matches, &gitenv)?;
+            }
+            ("identity", _) => {
+                let gitenv = dit::git::GitEnv::new();
+                app::show_identity(&gitenv, &config)?;
+            }
+            ("publish-key", publish_matches) => {
+                let gitenv = dit::git::GitEnv::new();
+                app::publish_keys(&*channel, publish_matches, &gitenv)?;
+            }
+            ("verify-tag", args) => {
+                // The server adds the certifications and revocations published since the
+                // key was generated, which verifying goes without when it is down, unlike
+                // the check against the transparency log
+                if reachable == true {
+                    let gitenv = dit::git::GitEnv::new();
+                    match app::fetch_project_key(&*channel, &gitenv) {
+                        Ok(true) => {
+                            println!("{}Imported the project key from the server", utl::DIT_LOG)
+                        }
+                        Ok(false) => {}
+                        Err(err) => {
+                            println!("{}Could not import the project key: {}", utl::DIT_LOG, err)
+                        }
+                    }
+
+                    // A tag that is missing from the transparency log was not released
+                    app::check_logged(&*channel, args, &gitenv, &config)?;
+                } else if config.checks_log() {
+                    // Without the log, there is no telling whether the tags were released
+                    let reason = "the server is unreachable, so the tags are unverified";
+                    return Err(errors::ProtocolError::Log(reason.to_owned()).into());
+                }
+                app::git_passthrough("verify-tag", args)?;
+            }
+            ("pending", _) => {
+                if reachable == true && !pending_operations.is_empty() {
+                    for (_, op) in pending_operations.iter() {
+                        println!("{}{}", utl::DIT_LOG, op);
+                    }
+                    println!(
+                        "{}Run `dit` to participate in a pending operation",
+                        utl::DIT_LOG
+                    );
+                }
+            }
+            (other, args) => {
+                if reachable == true {
+                    let prompt = "Participate in one of the pending operations (`n` for none)?";
+                    let choice = errors::unwrap_or_exit(app::choose_operation(
+                        &*channel,
+                        &pending_operations,
+                        prompt,
+                    ));
+
+                    if let Some(op) = choice {
+                        let gitenv = dit::git::GitEnv::new();
+                        app::participate(&*channel, &op, &gitenv, &config)?;
                     }
                 }
                 app::git_passthrough(other, args)?;
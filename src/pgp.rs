@@ -123,6 +123,104 @@ pub fn armor_binary_output(buffer: &[u8]) -> String {
     armor
 }
 
+/// Format OpenPGP certificates as an ASCII-armored public key block, in lines of 64 characters
+/// (Section 6.3), which is how keyservers hand them out
+pub fn armor_public_key(buffer: &[u8]) -> String {
+    let mut armor = String::from("-----BEGIN PGP PUBLIC KEY BLOCK-----\n\n");
+
+    let radix64 = binary_to_radix64(buffer);
+    for line in radix64.chunks(64) {
+        armor.push_str(&String::from_utf8_lossy(line));
+        armor.push('\n');
+    }
+    let crc = binary_to_radix64(&compute_crc(buffer).to_be_bytes()[1..]);
+    armor.push('=');
+    armor.push_str(&String::from_utf8_lossy(&crc));
+
+    armor.push_str("\n-----END PGP PUBLIC KEY BLOCK-----\n");
+
+    armor
+}
+
+/// Recover the binary OpenPGP message from the first ASCII-armored block of `text`, skipping
+/// its armor headers. Blocks with a checksum that does not match their contents are rejected.
+pub fn dearmor(text: &str) -> Option<Vec<u8>> {
+    let mut lines = text
+        .lines()
+        .map(str::trim_end)
+        .skip_while(|line| !line.starts_with("-----BEGIN PGP "));
+    lines.next()?;
+
+    // Armor headers run up to the first blank line
+    let mut lines = lines.skip_while(|line| !line.is_empty()).skip(1);
+
+    let mut encoded = String::new();
+    let mut crc = None;
+    loop {
+        let line = lines.next()?;
+        if line.starts_with("-----END PGP ") {
+            break;
+        }
+        match line.strip_prefix('=') {
+            Some(checksum) => crc = Some(checksum.to_owned()),
+            None => encoded.push_str(line),
+        }
+    }
+
+    let buffer = base64::decode(&encoded).ok()?;
+    if let Some(crc) = crc {
+        let expected = base64::decode(&crc).ok()?;
+        if expected != compute_crc(&buffer).to_be_bytes()[1..] {
+            return None;
+        }
+    }
+
+    Some(buffer)
+}
+
+/// Data a version 4 signature packet was computed over, given the `signed` data: the data
+/// itself, followed by the hashed part of the signature and its trailer (Section 5.2.4). For
+/// the signatures of dit, it is what the participants handed to the signing protocol.
+pub fn signed_data(signed: &[u8], signature: &[u8]) -> Option<Vec<u8>> {
+    let header = *signature.first()?;
+    if header & 0x80 == 0 {
+        return None;
+    }
+
+    let (tag, body) = if header & 0x40 == 0 {
+        let length_bytes = match header & 0x03 {
+            0 => 1,
+            1 => 2,
+            2 => 4,
+            _ => return None,
+        };
+        (
+            (header >> PACKET_TAG_OFFSET) & 0x0F,
+            signature.get(1 + length_bytes..)?,
+        )
+    } else {
+        let offset = match *signature.get(1)? {
+            0..=191 => 2,
+            192..=223 => 3,
+            255 => 6,
+            _ => return None,
+        };
+        (header & 0x3F, signature.get(offset..)?)
+    };
+    if tag != PacketHeader::Signature as u8 || body.first() != Some(&4) {
+        return None;
+    }
+
+    let hashed_len = u16::from_be_bytes([*body.get(4)?, *body.get(5)?]) as usize;
+    let hashed = body.get(..6 + hashed_len)?;
+
+    let mut data = signed.to_vec();
+    data.extend(hashed);
+    data.extend(&[0x04, 0xFF]);
+    data.extend(&(hashed.len() as u32).to_be_bytes());
+    Some(data)
+}
+
 /// Byte used to represent the type of the first packet in the packet header.
 #[repr(u8)]
 enum PacketHeader {
@@ -1128,4 +1226,37 @@ mod test {
             )
         );
     }
+
+    #[test]
+    fn public_key_armor_round_trip() {
+        let buffer: Vec<u8> = (0..=255).collect();
+        let armored = armor_public_key(&buffer);
+
+        assert!(armored.starts_with("-----BEGIN PGP PUBLIC KEY BLOCK-----\n\n"));
+        assert!(armored.lines().all(|line| line.len() <= 64));
+        assert_eq!(Some(buffer.clone()), dearmor(&armored));
+
+        // Armor headers are skipped, and a checksum that does not match is rejected
+        let with_headers = armored.replacen("\n\n", "\nComment: dit\n\n", 1);
+        assert_eq!(Some(buffer), dearmor(&with_headers));
+        let tampered = armored.replacen("AAEC", "AAED", 1);
+        assert_eq!(None, dearmor(&tampered));
+    }
+
+    #[test]
+    fn signed_data_of_signature() {
+        let payload = b"object 0123\ntype commit\ntag v1.0\n\nRelease\n".to_vec();
+
+        let mut message = Message::new();
+        message.new_signature(Duration::from_secs(1_600_000_000));
+        let mut hashable = payload.clone();
+        hashable.extend(message.get_hashable());
+
+        let signature = SignatureData::ECDSA(vec![0x01; 32], vec![0x02; 32]);
+        message.finalize_signature(&[0xAB, 0xCD], vec![0x03; 8], signature);
+        let packet = message.get_formatted_message();
+
+        assert_eq!(Some(hashable), signed_data(&payload, &packet));
+        assert_eq!(None, signed_data(&payload, &packet[..4]));
+    }
 }
@@ -0,0 +1,272 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use crate::errors::Result;
+use crate::pgp::{binary_to_radix64, SignatureData};
+use crate::utils::to_hex;
+
+pub const STATEMENT_TYPE: &str = "https://in-toto.io/Statement/v1";
+pub const PREDICATE_TYPE: &str = "https://slsa.dev/provenance/v1";
+pub const PAYLOAD_TYPE: &str = "application/vnd.in-toto+json";
+pub const BUILD_TYPE: &str = "https://github.com/luzap/dit/release@v1";
+
+/// An in-toto Statement binding a set of release artifacts to the SLSA provenance
+/// predicate describing how (and from what) they were produced.
+///
+/// The field order of the structures below is significant: the statement is serialized
+/// once by the leader and shipped to the participants as part of the operation, but every
+/// participant reserializes it to compute the bytes that get signed.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct Statement {
+    #[serde(rename = "_type")]
+    pub statement_type: String,
+    pub subject: Vec<Subject>,
+    #[serde(rename = "predicateType")]
+    pub predicate_type: String,
+    pub predicate: Provenance,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct Subject {
+    pub name: String,
+    pub digest: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Provenance {
+    pub build_definition: BuildDefinition,
+    pub run_details: RunDetails,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BuildDefinition {
+    pub build_type: String,
+    pub external_parameters: ExternalParameters,
+    pub resolved_dependencies: Vec<ResourceDescriptor>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct ExternalParameters {
+    pub source: ResourceDescriptor,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct ResourceDescriptor {
+    pub uri: String,
+    pub digest: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct RunDetails {
+    pub builder: Builder,
+    pub metadata: BuildMetadata,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct Builder {
+    pub id: String,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BuildMetadata {
+    pub invocation_id: String,
+    pub started_on: String,
+}
+
+impl Statement {
+    /// Build a statement for the given artifacts, all of which must be readable from the
+    /// current working directory.
+    pub fn new<P: AsRef<Path>>(
+        artifacts: &[P],
+        source_uri: String,
+        commit: String,
+        builder: String,
+        invocation_id: String,
+        started_on: String,
+    ) -> Result<Statement> {
+        let mut subject = Vec::with_capacity(artifacts.len());
+        for artifact in artifacts.iter() {
+            let contents = fs::read(artifact)?;
+            let mut digest = BTreeMap::new();
+            digest.insert("sha256".to_owned(), sha256_hex(&contents));
+
+            subject.push(Subject {
+                name: artifact.as_ref().to_string_lossy().into_owned(),
+                digest,
+            });
+        }
+
+        let mut commit_digest = BTreeMap::new();
+        commit_digest.insert("gitCommit".to_owned(), commit);
+
+        let source = ResourceDescriptor {
+            uri: source_uri,
+            digest: commit_digest,
+        };
+
+        Ok(Statement {
+            statement_type: STATEMENT_TYPE.to_owned(),
+            subject,
+            predicate_type: PREDICATE_TYPE.to_owned(),
+            predicate: Provenance {
+                build_definition: BuildDefinition {
+                    build_type: BUILD_TYPE.to_owned(),
+                    external_parameters: ExternalParameters {
+                        source: source.clone(),
+                    },
+                    resolved_dependencies: vec![source],
+                },
+                run_details: RunDetails {
+                    builder: Builder { id: builder },
+                    metadata: BuildMetadata {
+                        invocation_id,
+                        started_on,
+                    },
+                },
+            },
+        })
+    }
+
+    pub fn to_payload(&self) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(self)?)
+    }
+
+    /// The bytes that the participants collectively sign
+    pub fn get_hashable(&self) -> Result<Vec<u8>> {
+        Ok(pre_auth_encoding(PAYLOAD_TYPE, &self.to_payload()?))
+    }
+}
+
+/// A DSSE envelope, as described in the [specification](https://github.com/secure-systems-lab/dsse/blob/master/envelope.md)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Envelope {
+    pub payload_type: String,
+    pub payload: String,
+    pub signatures: Vec<EnvelopeSignature>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EnvelopeSignature {
+    pub keyid: String,
+    pub sig: String,
+}
+
+impl Envelope {
+    pub fn new(statement: &Statement, keyid: &[u8], signature: SignatureData) -> Result<Envelope> {
+        let payload = statement.to_payload()?;
+
+        let sig = match signature {
+            SignatureData::ECDSA(r, s) => encode_der_signature(&r, &s),
+        };
+
+        Ok(Envelope {
+            payload_type: PAYLOAD_TYPE.to_owned(),
+            payload: String::from_utf8(binary_to_radix64(&payload))?,
+            signatures: vec![EnvelopeSignature {
+                keyid: to_hex(keyid),
+                sig: String::from_utf8(binary_to_radix64(&sig))?,
+            }],
+        })
+    }
+
+    pub fn write_to_file<P: AsRef<Path>>(&self, file_path: P) -> Result<()> {
+        let mut contents = serde_json::to_string(self)?;
+        contents.push('\n');
+
+        Ok(fs::write(file_path, contents)?)
+    }
+}
+
+/// DSSE pre-authentication encoding, which is what actually gets signed instead of the
+/// bare payload: `"DSSEv1" SP LEN(type) SP type SP LEN(body) SP body`
+pub fn pre_auth_encoding(payload_type: &str, payload: &[u8]) -> Vec<u8> {
+    let mut encoded = format!(
+        "DSSEv1 {} {} {} ",
+        payload_type.len(),
+        payload_type,
+        payload.len()
+    )
+    .into_bytes();
+    encoded.extend_from_slice(payload);
+    encoded
+}
+
+/// Encode a raw `(r, s)` pair as an ASN.1 DER `Ecdsa-Sig-Value`, which is what most DSSE
+/// verifiers expect of ECDSA signatures.
+pub fn encode_der_signature(r: &[u8], s: &[u8]) -> Vec<u8> {
+    let r = der_integer(r);
+    let s = der_integer(s);
+
+    let mut sequence = Vec::with_capacity(r.len() + s.len() + 2);
+    sequence.push(0x30);
+    sequence.push((r.len() + s.len()) as u8);
+    sequence.extend(r);
+    sequence.extend(s);
+    sequence
+}
+
+fn der_integer(value: &[u8]) -> Vec<u8> {
+    // Leading zeros are dropped, down to a single zero byte for a value of zero
+    let value = match value.iter().position(|&b| b != 0) {
+        Some(start) => &value[start..],
+        None => &[0],
+    };
+
+    let mut integer = Vec::with_capacity(value.len() + 3);
+    integer.push(0x02);
+    // Integers are signed, so a set high bit needs a leading zero to remain positive
+    if value[0] & 0x80 != 0 {
+        integer.push((value.len() + 1) as u8);
+        integer.push(0x00);
+    } else {
+        integer.push(value.len() as u8);
+    }
+    integer.extend_from_slice(value);
+    integer
+}
+
+pub fn sha256_hex(buffer: &[u8]) -> String {
+    use crypto::digest::Digest;
+    use crypto::sha2::Sha256;
+
+    let mut hasher = Sha256::new();
+    hasher.input(buffer);
+    hasher.result_str()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pae_encoding() {
+        // Test vector from the DSSE protocol description
+        assert_eq!(
+            pre_auth_encoding("http://example.com/HelloWorld", b"hello world"),
+            b"DSSEv1 29 http://example.com/HelloWorld 11 hello world".to_vec()
+        );
+    }
+
+    #[test]
+    fn der_signature_padding() {
+        let r = [0x00, 0x81, 0x02];
+        let s = [0x7f, 0x01];
+
+        assert_eq!(
+            encode_der_signature(&r, &s),
+            vec![0x30, 0x09, 0x02, 0x03, 0x00, 0x81, 0x02, 0x02, 0x02, 0x7f, 0x01]
+        );
+    }
+
+    #[test]
+    fn der_integer_of_zero() {
+        assert_eq!(der_integer(&[]), vec![0x02, 0x01, 0x00]);
+        assert_eq!(der_integer(&[0x00, 0x00]), vec![0x02, 0x01, 0x00]);
+    }
+}
@@ -1,246 +1,2688 @@
-#![feature(proc_macro_hygiene, decl_macro)]
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex, RwLock};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use std::collections::HashMap;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::{Arc, RwLock};
-
-use rocket::{post, routes, State};
-use rocket_contrib::json::Json;
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use rocket::data::{Limits, ToByteUnit};
+use rocket::fairing::AdHoc;
+use rocket::figment::Figment;
+use rocket::http::{ContentType, Status};
+use rocket::mtls::Certificate;
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::{self, Responder};
+use rocket::serde::json::Json;
+use rocket::tokio::sync::Notify;
+use rocket::tokio::time::{interval, timeout_at, Instant};
+use rocket::{catch, catchers, get, post, routes, State};
+use serde::{Deserialize, Serialize};
 
 // TODO Move these to a separate crate
-use dit::comm::{Entry, Index, Key, PartySignup};
-use dit::utils::Operation;
+use dit::comm::{
+    AbortReason, Batch, Entry, Hello, Index, Key, OperationStart, OperationWait, PartySignup,
+    Queued, Signup, Stage,
+};
+use dit::errors::Refusal;
+use dit::identity::Identity;
+use dit::pgp::armor_public_key;
+use dit::provenance::sha256_hex;
+use dit::transparency::{
+    to_hash, EntryKind, LogAppend, LogEntry, ProofRequest, SignedTreeHead, Tree,
+};
+use dit::utils::{to_hex, Operation};
+use dit::wire;
+
+mod admin;
+mod keyserver;
+mod store;
+#[cfg(test)]
+mod testing;
+
+use admin::{ProjectSummary, SessionReport};
+use keyserver::Keyring;
+use store::{Event, Log, Memory, Store};
 
 // TODO Now we need to send the project name with every message
-// TODO Start handling server errors
 
-#[post("/start-operation", format = "json", data = "<request>")]
-fn start_operation(
-    db: State<RwLock<HashMap<String, Project>>>,
-    request: Json<(String, Operation)>,
-) {
-    // TODO Remove this
-    let (project_name, new_operation) = request.into_inner();
-
-    let (operation, exists) = {
-        let read_db = db.read().unwrap();
-        match read_db.get(&project_name) {
-            Some(project) => ((*project).operation.clone(), true),
-            None => (Arc::new(Operation::Idle), false),
+/// Upper bound on how long a long-polling request is held, in milliseconds
+const MAX_WAIT_MS: u64 = 25_000;
+
+/// Longest project names, sessions and message keys the server takes, in bytes
+const MAX_NAME_LEN: usize = 256;
+
+/// Most messages a client can ask for in one `/get-batch` request
+const MAX_BATCH_KEYS: usize = 1024;
+
+/// Largest request body the server reads, in MiB, unless set with `limits.json` in
+/// `Rocket.toml`. The messages of key generation carry proofs, and easily exceed the default
+/// of Rocket.
+const DEFAULT_JSON_LIMIT_MIB: u64 = 16;
+
+/// How long the messages of an operation are kept after it ends, in seconds, unless set with
+/// `session_retention` in `Rocket.toml`
+const DEFAULT_RETENTION_SECS: u64 = 24 * 60 * 60;
+
+/// How long operations may stall before they are aborted, in seconds, unless set with
+/// `signup_timeout` and `round_timeout` in `Rocket.toml`
+const DEFAULT_SIGNUP_TIMEOUT_SECS: u64 = 24 * 60 * 60;
+const DEFAULT_ROUND_TIMEOUT_SECS: u64 = 60 * 60;
+
+/// How often stalled operations are looked for, in seconds
+const EXPIRY_INTERVAL_SECS: u64 = 10;
+
+/// Projects by name
+type Projects = RwLock<HashMap<String, Project>>;
+
+/// How long archived sessions are kept around before they expire
+struct Retention(Duration);
+
+impl Retention {
+    fn from_config(figment: &Figment) -> Retention {
+        let secs = figment
+            .extract_inner("session_retention")
+            .unwrap_or(DEFAULT_RETENTION_SECS);
+
+        Retention(Duration::from_secs(secs))
+    }
+}
+
+/// How long operations may go without moving along before they are aborted and ended: those
+/// still waiting for participants to sign up for `signups`, and those running (or aborted, and
+/// waiting for their leader to end them) for `rounds`. A limit of 0 in the config turns it off.
+struct Expiry {
+    signups: Option<Duration>,
+    rounds: Option<Duration>,
+}
+
+impl Expiry {
+    fn from_config(figment: &Figment) -> Expiry {
+        let limit = |key: &str, default: u64| {
+            let secs = figment.extract_inner(key).unwrap_or(default);
+            Some(secs).filter(|&secs| secs > 0).map(Duration::from_secs)
+        };
+
+        Expiry {
+            signups: limit("signup_timeout", DEFAULT_SIGNUP_TIMEOUT_SECS),
+            rounds: limit("round_timeout", DEFAULT_ROUND_TIMEOUT_SECS),
         }
-    };
+    }
+
+    /// Expire the operations that stalled, and wake up the participants waiting on them, who
+    /// find them aborted
+    fn expire(&self, db: &Projects, storage: &Storage, changes: &Changes) {
+        let mut write_db = db.write().unwrap();
+        let stalled: Vec<(String, String)> = write_db
+            .iter()
+            .flat_map(|(name, project)| {
+                project
+                    .pending()
+                    .filter(move |id| project.sessions[*id].stalled(self))
+                    .map(move |id| (name.clone(), id.clone()))
+            })
+            .collect();
+        if stalled.is_empty() {
+            return;
+        }
+
+        for (project, session) in stalled {
+            let event = Event::Expire {
+                project,
+                session,
+                at: SystemTime::now(),
+            };
+            // The others may still expire, and this one is tried again next time
+            if let Err(refusal) = storage.commit(&mut write_db, event) {
+                eprintln!("Could not expire an operation: {}", refusal);
+            }
+        }
+        drop(write_db);
+        changes.notify();
+    }
+}
+
+/// Store of the events making up the state, which every change goes through. With
+/// `storage_path` set in `Rocket.toml`, they are appended to that file, and otherwise the state
+/// only lives in memory.
+struct Storage {
+    store: Box<dyn Store>,
+    audit: Audit,
+}
+
+impl Storage {
+    fn from_config(figment: &Figment) -> Storage {
+        let store: Box<dyn Store> = match figment.extract_inner::<PathBuf>("storage_path") {
+            Ok(path) => Box::new(Log::open(path).expect("Could not open the storage")),
+            Err(_) => Box::new(Memory),
+        };
+
+        Storage {
+            store,
+            audit: Audit::from_config(figment),
+        }
+    }
+
+    /// Record `event` in the audit log, then in the store. A change the store failed to keep
+    /// is still in the audit log, but it is refused and never applied, while one missing from
+    /// the audit log would be gone from it for good.
+    fn record(&self, event: &Event) -> Result<(), Refusal> {
+        let recorded = self.audit.log(event).and_then(|()| self.store.record(event));
+        recorded.map_err(|err| {
+            eprintln!("Could not record a change: {}", err);
+            Refusal::Unavailable
+        })
+    }
+
+    /// Record `event` before applying it, so that a restart cannot lose a change that clients
+    /// have already seen
+    fn commit(&self, projects: &mut HashMap<String, Project>, event: Event) -> Result<(), Refusal> {
+        self.record(&event)?;
+        apply(projects, event);
+        Ok(())
+    }
+
+    /// Restore the state recorded by earlier runs of the server, and compact the store so
+    /// that it holds neither expired sessions nor anything a crash left behind
+    fn restore(&self, retention: Duration) -> HashMap<String, Project> {
+        let mut projects = HashMap::new();
+        for event in self.store.load().expect("Could not read the storage") {
+            apply(&mut projects, event);
+        }
+
+        for project in projects.values_mut() {
+            project.expire(retention);
+        }
+        self.store
+            .compact(&snapshot(&projects))
+            .expect("Could not compact the storage");
+
+        projects
+    }
+}
+
+/// Key the server signs the heads of the transparency logs with, which clients pin on first
+/// use. It is kept at `log_key_path` in `Rocket.toml`, or else next to the storage, and servers
+/// that keep nothing sign with a new key on every start.
+struct LogKey(Identity);
+
+impl LogKey {
+    fn from_config(figment: &Figment) -> LogKey {
+        let path = figment.extract_inner::<PathBuf>("log_key_path").ok().or_else(|| {
+            figment
+                .extract_inner::<PathBuf>("storage_path")
+                .ok()
+                .map(|path| path.with_extension("key"))
+        });
+
+        match path {
+            Some(path) => LogKey(
+                Identity::load_or_create(&path).expect("Could not read the key of the log"),
+            ),
+            None => LogKey(Identity::generate()),
+        }
+    }
+}
+
+/// Trail of every change to the operations, along with the identity of the client that made
+/// it. Unlike the store, it is never compacted. It is appended to the file at `audit_path` in
+/// `Rocket.toml`, or printed without one.
+struct Audit(Option<Mutex<File>>);
+
+#[derive(Serialize)]
+struct AuditEntry<'a> {
+    at: SystemTime,
+    event: &'a Event,
+}
+
+impl Audit {
+    fn from_config(figment: &Figment) -> Audit {
+        let file = figment.extract_inner::<PathBuf>("audit_path").ok().map(|path| {
+            let file = OpenOptions::new().create(true).append(true).open(path);
+            Mutex::new(file.expect("Could not open the audit log"))
+        });
+
+        Audit(file)
+    }
+
+    fn log(&self, event: &Event) -> io::Result<()> {
+        // Messages are not changes to the state of an operation
+        if let Event::Set { .. } = event {
+            return Ok(());
+        }
+
+        let entry = AuditEntry {
+            at: SystemTime::now(),
+            event,
+        };
+        let line = serde_json::to_string(&entry).expect("Events are always serializable");
+
+        match &self.0 {
+            Some(file) => writeln!(file.lock().unwrap(), "{}", line),
+            None => {
+                println!("{}", line);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Signalled on every change to the operations or messages of any project, so that
+/// long-polling requests can sleep until there is something new to look at
+struct Changes {
+    changed: Notify,
+}
+
+impl Changes {
+    fn notify(&self) {
+        self.changed.notify_waiters();
+    }
+
+    /// Evaluate `check` after every change, until it produces a value or `wait` has passed
+    async fn wait_for<T, F: Fn() -> Option<T>>(&self, wait: Duration, check: F) -> Option<T> {
+        let deadline = Instant::now() + wait;
+
+        loop {
+            // Changes are signalled to every waiter created before them, including this one if
+            // something changes while `check` runs
+            let changed = self.changed.notified();
+            if let Some(value) = check() {
+                return Some(value);
+            }
+
+            if timeout_at(deadline, changed).await.is_err() {
+                return None;
+            }
+        }
+    }
+}
+
+/// Clients allowed to use the server, identified by the SHA-256 fingerprints of their TLS
+/// client certificates or by bearer tokens, and mapped to their identity keys. These are listed
+/// under `clients` in `Rocket.toml`, and without any, the server is open to everyone who can
+/// reach it. Tokens are kept as their SHA-256 digests, so that looking one up takes no longer
+/// for a guess that gets more of it right.
+struct Clients {
+    certificates: HashMap<String, String>,
+    tokens: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct ClientConfig {
+    certificate: Option<String>,
+    token: Option<String>,
+    identity: String,
+}
+
+impl Clients {
+    fn from_config(figment: &Figment) -> Clients {
+        let clients: Vec<ClientConfig> = figment.extract_inner("clients").unwrap_or_default();
+
+        let mut certificates = HashMap::new();
+        let mut tokens = HashMap::new();
+        for client in clients {
+            if let Some(certificate) = client.certificate {
+                certificates.insert(normalize_fingerprint(&certificate), client.identity.clone());
+            }
+            if let Some(token) = client.token {
+                tokens.insert(sha256_hex(token.as_bytes()), client.identity);
+            }
+        }
+
+        Clients {
+            certificates,
+            tokens,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.certificates.is_empty() && self.tokens.is_empty()
+    }
+
+    fn identity(&self, certificate: &[u8]) -> Option<&String> {
+        let mut hasher = Sha256::new();
+        hasher.input(certificate);
+
+        let mut fingerprint = [0u8; 32];
+        hasher.result(&mut fingerprint);
+        self.certificates.get(&to_hex(&fingerprint))
+    }
+
+    fn bearer(&self, token: &str) -> Option<&String> {
+        self.tokens.get(&sha256_hex(token.as_bytes()))
+    }
+}
+
+/// Accept both plain hex and the colon-separated form printed by `openssl x509 -fingerprint`
+fn normalize_fingerprint(fingerprint: &str) -> String {
+    fingerprint.replace(':', "").to_lowercase()
+}
+
+/// Request guard only letting through clients that present the token or certificate of one
+/// of the `Clients`, when there are any, along with their identity key
+struct KnownClient(Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for KnownClient {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        let clients = match request.rocket().state::<Clients>() {
+            Some(clients) if !clients.is_empty() => clients,
+            _ => return request::Outcome::Success(KnownClient(None)),
+        };
+
+        let token = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|header| header.strip_prefix("Bearer "))
+            .and_then(|token| clients.bearer(token.trim()));
+        if let Some(identity) = token {
+            return request::Outcome::Success(KnownClient(Some(identity.clone())));
+        }
+
+        let certificate = request.guard::<Certificate<'_>>().await.succeeded();
+        match certificate.and_then(|certificate| clients.identity(certificate.as_bytes())) {
+            Some(identity) => request::Outcome::Success(KnownClient(Some(identity.clone()))),
+            None => request::Outcome::Error((Status::Forbidden, ())),
+        }
+    }
+}
+
+/// Digest of the token operators present to use the admin API, set with `admin_token` in
+/// `Rocket.toml`. Without one, the admin API is closed.
+struct AdminToken(Option<String>);
+
+impl AdminToken {
+    fn from_config(figment: &Figment) -> AdminToken {
+        let token = figment.extract_inner::<String>("admin_token").ok();
+        AdminToken(token.map(|token| sha256_hex(token.as_bytes())))
+    }
+}
+
+/// Request guard only letting through the bearer of the `AdminToken`
+struct Admin;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Admin {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        let expected = request
+            .rocket()
+            .state::<AdminToken>()
+            .and_then(|token| token.0.as_ref());
+        let token = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|header| header.strip_prefix("Bearer "))
+            .map(|token| sha256_hex(token.trim().as_bytes()));
+
+        // Like those of clients, the token is compared by its digest, so that how long the
+        // comparison takes says nothing about how much of a guess is right
+        match (expected, token) {
+            (Some(expected), Some(token)) if *expected == token => request::Outcome::Success(Admin),
+            _ => request::Outcome::Error((Status::Forbidden, ())),
+        }
+    }
+}
+
+/// Refusal of a request, answered with the status code of its reason and the reason itself as
+/// the body, which the client turns back into a `ProtocolError`
+struct Refused(Refusal);
+
+impl From<Refusal> for Refused {
+    fn from(refusal: Refusal) -> Self {
+        Refused(refusal)
+    }
+}
+
+impl<'r> Responder<'r, 'static> for Refused {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let status = Status::from_code(self.0.status()).unwrap_or(Status::BadRequest);
+        (status, Json(self.0)).respond_to(request)
+    }
+}
+
+/// Answer of a route that can turn the request down
+type Reply<T> = Result<Json<T>, Refused>;
+
+/// Refuse project names, sessions and keys that are empty, longer than `MAX_NAME_LEN` or not
+/// printable, naming the offending `field`
+fn check_name(field: &str, value: &str) -> Result<(), Refusal> {
+    if value.is_empty() || value.len() > MAX_NAME_LEN || value.chars().any(char::is_control) {
+        return Err(Refusal::Invalid(format!(
+            "{} has to be 1 to {} printable bytes",
+            field, MAX_NAME_LEN
+        )));
+    }
 
-    let new_operation = match *operation {
-        Operation::Idle => Arc::new(new_operation),
-        _ => operation,
+    Ok(())
+}
+
+/// Refuse digests that are not SHA-256 hashes in hex
+fn check_digest(digest: &str) -> Result<(), Refusal> {
+    if to_hash(digest).is_none() {
+        let reason = "the digest has to be a SHA-256 hash in hex".to_owned();
+        return Err(Refusal::Invalid(reason));
+    }
+
+    Ok(())
+}
+
+/// Refuse operations that cannot run: anything but key generation and signing, thresholds
+/// the participants cannot meet, and rosters that do not list every participant once
+fn check_start(start: &OperationStart) -> Result<(), Refusal> {
+    let invalid = |reason: &str| Err(Refusal::Invalid(reason.to_owned()));
+
+    let (participants, threshold) = match start.operation {
+        Operation::KeyGen { participants, .. } => (participants, 0),
+        Operation::SignTag {
+            participants,
+            threshold,
+            ..
+        }
+        | Operation::SignKey {
+            participants,
+            threshold,
+            ..
+        }
+        | Operation::SignStatement {
+            participants,
+            threshold,
+            ..
+        } => (participants, threshold),
+        Operation::Idle | Operation::Blame {} => {
+            return invalid("only key generations and signings can be started")
+        }
     };
+    if participants == 0 || threshold >= participants {
+        return invalid("the threshold has to be below the number of participants");
+    }
 
-    if exists != true {
-        let mut write_db = db.write().unwrap();
-        write_db.insert(
-            project_name.clone(),
-            Project {
-                operation: new_operation,
-                participants: AtomicUsize::new(0),
-                cache: RwLock::new(HashMap::new()),
-            },
-        );
+    if let Some(after) = &start.after {
+        check_name("the previous session", after)?;
+    }
+    for member in start.roster.iter() {
+        check_name("a roster entry", member)?;
+    }
+    if start.roster.iter().collect::<HashSet<_>>().len() != start.roster.len() {
+        return invalid("the roster lists a member twice");
+    }
+    if let Operation::KeyGen { .. } = start.operation {
+        if !start.roster.is_empty() && start.roster.len() != participants as usize {
+            return invalid("the roster has to list every participant of the key generation");
+        }
+    }
+
+    Ok(())
+}
+
+/// The session `id` of the project, whether it is still pending or archived
+fn find_session<'a>(
+    projects: &'a HashMap<String, Project>,
+    project: &str,
+    id: &str,
+) -> Result<&'a Session, Refusal> {
+    projects
+        .get(project)
+        .ok_or(Refusal::UnknownProject)?
+        .sessions
+        .get(id)
+        .ok_or(Refusal::UnknownSession)
+}
+
+/// The session `id` of the project, as long as its operation has not ended
+fn pending_mut<'a>(
+    projects: &'a mut HashMap<String, Project>,
+    project: &str,
+    id: &str,
+) -> Result<&'a mut Session, Refusal> {
+    let project = projects.get_mut(project).ok_or(Refusal::UnknownProject)?;
+    if !project.sessions.contains_key(id) {
+        return Err(Refusal::UnknownSession);
+    }
+
+    project.running_mut(id).ok_or(Refusal::Ended)
+}
+
+/// The version of the wire format the server speaks, as long as it is within the range the
+/// client sends, or otherwise that version as a range. Unlike the other routes, this one
+/// answers the same way in every version, so that any client can make sense of it.
+#[post("/hello", format = "json", data = "<request>")]
+fn hello(_client: KnownClient, request: Json<(String, Hello)>) -> Json<Result<u8, Hello>> {
+    let (_, client) = request.into_inner();
+
+    if (client.min..=client.max).contains(&wire::VERSION) {
+        Json(Ok(wire::VERSION))
     } else {
+        Json(Err(Hello {
+            min: wire::VERSION,
+            max: wire::VERSION,
+        }))
+    }
+}
+
+/// Queue `operation` under a new session, returning the ID of the session. Any number of
+/// operations can be pending at once, and participants choose which one to join.
+#[post("/start-operation", format = "json", data = "<request>")]
+fn start_operation(
+    client: KnownClient,
+    db: &State<Arc<Projects>>,
+    storage: &State<Arc<Storage>>,
+    retention: &State<Retention>,
+    changes: &State<Arc<Changes>>,
+    request: Json<(String, OperationStart)>,
+) -> Reply<String> {
+    let (project_name, start) = request.into_inner();
+    check_name("the project", &project_name)?;
+    check_start(&start)?;
+    let session = to_hex(&rand::random::<[u8; 16]>());
+
+    {
         let mut write_db = db.write().unwrap();
-        match write_db.get_mut(&project_name) {
-            Some(project) => project.operation = new_operation,
-            None => unreachable!(),
+        if let Some(project) = write_db.get_mut(&project_name) {
+            project.expire(retention.0);
         }
+
+        let event = Event::Start {
+            project: project_name,
+            session: session.clone(),
+            operation: start.operation,
+            after: start.after,
+            by: client.0,
+            roster: start.roster,
+        };
+        storage.commit(&mut write_db, event)?;
+    }
+    changes.notify();
+
+    Ok(Json(session))
+}
+
+/// Operations waiting for participants, oldest first. Projects only come to be with their
+/// first operation, so there are none for unknown projects.
+#[post("/operations", format = "json", data = "<request>")]
+fn operations(
+    _client: KnownClient,
+    db: &State<Arc<Projects>>,
+    request: Json<(String, usize)>,
+) -> Reply<Vec<Queued>> {
+    let (project_name, _) = request.into_inner();
+    check_name("the project", &project_name)?;
+
+    let queued = match db.read().unwrap().get(&project_name) {
+        Some(project) => project
+            .pending()
+            .filter_map(|id| project.resolve(Some(id)))
+            .collect(),
+        None => vec![],
     };
+
+    Ok(Json(queued))
 }
 
-#[post("/get-operation", format = "json", data = "<request>")]
-fn get_operation(
-    db: State<RwLock<HashMap<String, Project>>>,
+/// Operations that have ended, oldest first, which are kept until their sessions expire
+#[post("/history", format = "json", data = "<request>")]
+fn history(
+    _client: KnownClient,
+    db: &State<Arc<Projects>>,
     request: Json<(String, usize)>,
-) -> Json<Operation> {
+) -> Reply<Vec<Queued>> {
     let (project_name, _) = request.into_inner();
+    check_name("the project", &project_name)?;
+
+    let ended = match db.read().unwrap().get(&project_name) {
+        Some(project) => project
+            .order
+            .iter()
+            .filter(|id| project.running(id).is_none())
+            .map(|id| project.sessions[id].queued(id))
+            .collect(),
+        None => vec![],
+    };
+
+    Ok(Json(ended))
+}
+
+/// The operation of the given session (or the oldest one, without any), which is `None` once
+/// it has ended and nothing was started in its place
+#[post("/get-operation", format = "json", data = "<request>")]
+fn get_operation(
+    _client: KnownClient,
+    db: &State<Arc<Projects>>,
+    request: Json<(String, Option<String>)>,
+) -> Reply<Option<Queued>> {
+    let (project_name, session) = request.into_inner();
+    check_name("the project", &project_name)?;
+    if let Some(session) = &session {
+        check_name("the session", session)?;
+    }
 
     let read_db = db.read().unwrap();
+    let queued = read_db
+        .get(&project_name)
+        .and_then(|project| project.resolve(session.as_ref()));
+
+    Ok(Json(queued))
+}
+
+/// Long-polling version of `/get-operation`, answering as soon as the operation differs from
+/// the one the client already knows about
+#[post("/await-operation", format = "json", data = "<request>")]
+async fn await_operation(
+    _client: KnownClient,
+    db: &State<Arc<Projects>>,
+    changes: &State<Arc<Changes>>,
+    request: Json<(String, OperationWait)>,
+) -> Reply<Option<Queued>> {
+    let (project_name, wait) = request.into_inner();
+    check_name("the project", &project_name)?;
+    if let Some(session) = &wait.session {
+        check_name("the session", session)?;
+    }
 
-    // TODO How should we handle the case of the missing operation?
-    // Probably want more error states here
-    let operation = match read_db.get(&project_name) {
-        Some(op) => (*op.operation).clone(),
-        None => Operation::Idle,
+    let current = || {
+        db.read()
+            .unwrap()
+            .get(&project_name)
+            .and_then(|project| project.resolve(wait.session.as_ref()))
     };
 
-    Json(operation)
+    let changed = changes
+        .wait_for(Duration::from_millis(wait.wait_ms.min(MAX_WAIT_MS)), || {
+            let queued = current();
+            let operation = queued.as_ref().map(|queued| &queued.operation);
+
+            if operation.unwrap_or(&Operation::Idle) != &wait.known {
+                Some(queued)
+            } else {
+                None
+            }
+        })
+        .await;
+
+    Ok(Json(changed.unwrap_or_else(current)))
 }
 
-// TODO Maybe we should be checking how many participants there are?
-// Figure out how to best share that state between threads
-// Think about the best way of handling the number of participants
+/// Only the client that started the operation ends it right away. When the server has
+/// `Clients`, its parties and roster members merely vote to end it, and a majority of them has
+/// to agree, while anyone else is turned away. Ending an operation that has already ended
+/// changes nothing.
 #[post("/end-operation", format = "json", data = "<request>")]
-fn end_operation(db: State<RwLock<HashMap<String, Project>>>, request: Json<(String, Operation)>) {
-    let (project_name, _) = request.into_inner();
+fn end_operation(
+    client: KnownClient,
+    db: &State<Arc<Projects>>,
+    storage: &State<Arc<Storage>>,
+    retention: &State<Retention>,
+    changes: &State<Arc<Changes>>,
+    request: Json<(String, String)>,
+) -> Reply<()> {
+    let (project_name, session) = request.into_inner();
+    check_name("the project", &project_name)?;
+    check_name("the session", &session)?;
+
+    {
+        let mut write_db = db.write().unwrap();
+        let project = write_db
+            .get_mut(&project_name)
+            .ok_or(Refusal::UnknownProject)?;
+        project.expire(retention.0);
+        let running = match project.sessions.get(&session) {
+            Some(session) => session.ended.is_none(),
+            None => return Err(Refusal::UnknownSession.into()),
+        };
+        if running {
+            project.sessions[&session].votes(client.0.as_ref())?;
+        }
+
+        if running {
+            let event = Event::End {
+                project: project_name,
+                session,
+                at: SystemTime::now(),
+                by: client.0,
+            };
+            storage.commit(&mut write_db, event)?;
+        }
+    }
+    changes.notify();
+
+    Ok(Json(()))
+}
+
+/// Any participant can abort the operation of their session, which the others notice the next
+/// time they check the operation. When the server has `Clients`, the client that started the
+/// operation and those that signed up for it abort it on their own, while the other roster
+/// members vote to abort it, and anyone else is turned away. Ending the aborted operation is
+/// still left to the leader, or to its expiry.
+#[post("/blame", format = "json", data = "<request>")]
+fn blame(
+    client: KnownClient,
+    db: &State<Arc<Projects>>,
+    storage: &State<Arc<Storage>>,
+    changes: &State<Arc<Changes>>,
+    request: Json<(String, String)>,
+) -> Reply<()> {
+    let (project_name, session) = request.into_inner();
+    check_name("the project", &project_name)?;
+    check_name("the session", &session)?;
+
+    {
+        let mut write_db = db.write().unwrap();
+        // There is nothing left to abort once the operation has ended
+        match pending_mut(&mut write_db, &project_name, &session) {
+            Ok(pending) => {
+                pending.votes(client.0.as_ref())?;
+                let event = Event::Blame {
+                    project: project_name,
+                    session,
+                    by: client.0,
+                };
+                storage.commit(&mut write_db, event)?;
+            }
+            Err(Refusal::Ended) => {}
+            Err(refusal) => return Err(refusal.into()),
+        }
+    }
+    changes.notify();
 
-    // Reset project operation
-    db.write()
-        .unwrap()
-        .get_mut(&project_name)
-        .unwrap()
-        .operation = Arc::new(Operation::Idle);
-    // Reset participant number
-    db.write()
-        .unwrap()
-        .get_mut(&project_name)
-        .unwrap()
-        .participants
-        .store(0, Ordering::SeqCst);
+    Ok(Json(()))
 }
 
+/// Drop the expired sessions of the project. The messages of past operations are kept until
+/// then, and those of pending ones are never touched.
 #[post("/clear", format = "json", data = "<request>")]
-fn clear(db: State<RwLock<HashMap<String, Project>>>, request: Json<(String, usize)>) {
+fn clear(
+    _client: KnownClient,
+    db: &State<Arc<Projects>>,
+    retention: &State<Retention>,
+    request: Json<(String, usize)>,
+) -> Reply<()> {
     let (project_name, _) = request.into_inner();
-    db.write()
-        .unwrap()
-        .get_mut(&project_name)
-        .unwrap()
-        .cache
-        .write()
-        .unwrap()
-        .clear()
+    check_name("the project", &project_name)?;
+
+    if let Some(project) = db.write().unwrap().get_mut(&project_name) {
+        project.expire(retention.0);
+    }
+
+    Ok(Json(()))
 }
 
 #[post("/get", format = "json", data = "<request>")]
 fn get(
-    db_mtx: State<RwLock<HashMap<String, Project>>>,
+    _client: KnownClient,
+    db_mtx: &State<Arc<Projects>>,
     request: Json<(String, Index)>,
-) -> Json<Result<Entry, ()>> {
+) -> Reply<Entry> {
     let (project_name, index) = request.into_inner();
+    check_name("the project", &project_name)?;
+    check_name("the session", &index.session)?;
+    check_name("the key", &index.key)?;
 
-    // TODO I don't like holding the lock for so long but it seems necessary
     let hm = db_mtx.read().unwrap();
-    let project = hm.get(&project_name).unwrap();
+    let value = find_session(&hm, &project_name, &index.session)?
+        .cache
+        .get(&index.key)
+        .ok_or(Refusal::Missing)?;
 
-    let read_db = project.cache.read().unwrap();
+    Ok(Json(Entry {
+        value: value.clone(),
+        session: index.session,
+        key: index.key,
+    }))
+}
 
-    match read_db.get(&index.key) {
-        Some(v) => {
-            let entry = Entry {
-                key: index.key,
-                value: v.clone().to_string(),
-            };
-            Json(Ok(entry))
-        }
-        None => Json(Err(())),
+/// Messages stored under the keys of the batch in its session, in order, held until all of
+/// them have arrived so that clients need neither one request per message nor a polling loop
+#[post("/get-batch", format = "json", data = "<request>")]
+async fn get_batch(
+    _client: KnownClient,
+    db_mtx: &State<Arc<Projects>>,
+    changes: &State<Arc<Changes>>,
+    request: Json<(String, Batch)>,
+) -> Reply<Vec<Option<String>>> {
+    let (project_name, batch) = request.into_inner();
+    check_name("the project", &project_name)?;
+    check_name("the session", &batch.session)?;
+    if batch.keys.len() > MAX_BATCH_KEYS {
+        let reason = format!("batches hold at most {} keys", MAX_BATCH_KEYS);
+        return Err(Refusal::Invalid(reason).into());
     }
+    for key in batch.keys.iter() {
+        check_name("the key", key)?;
+    }
+
+    let lookup = || -> Result<Vec<Option<String>>, Refusal> {
+        let db = db_mtx.read().unwrap();
+        let session = find_session(&db, &project_name, &batch.session)?;
+        Ok(batch
+            .keys
+            .iter()
+            .map(|key| session.cache.get(key).cloned())
+            .collect())
+    };
+
+    // Sessions that expire while the request is held are refused right away
+    let complete = changes
+        .wait_for(Duration::from_millis(batch.wait_ms.min(MAX_WAIT_MS)), || {
+            match lookup() {
+                Ok(found) if found.iter().any(Option::is_none) => None,
+                found => Some(found),
+            }
+        })
+        .await;
+
+    Ok(Json(complete.unwrap_or_else(lookup)?))
 }
 
 #[post("/set", format = "json", data = "<request>")]
 fn set(
-    db_mtx: State<RwLock<HashMap<String, Project>>>,
+    _client: KnownClient,
+    db_mtx: &State<Arc<Projects>>,
+    storage: &State<Arc<Storage>>,
+    changes: &State<Arc<Changes>>,
     request: Json<(String, Entry)>,
-) -> Json<Result<(), ()>> {
+) -> Reply<()> {
     let (project_name, entry) = request.into_inner();
+    check_name("the project", &project_name)?;
+    check_name("the session", &entry.session)?;
+    check_name("the key", &entry.key)?;
 
-    let hm = db_mtx.write().unwrap();
-    let project = hm.get(&project_name).unwrap();
-    let mut project_cache = project.cache.write().unwrap();
+    {
+        let mut hm = db_mtx.write().unwrap();
+        // Archived sessions are read-only, so only pending operations take new messages
+        let session = pending_mut(&mut hm, &project_name, &entry.session)?;
+        if !session.takes(&entry.key, &entry.value)? {
+            return Ok(Json(()));
+        }
+
+        let event = Event::Set {
+            project: project_name,
+            session: entry.session,
+            key: entry.key,
+            value: entry.value,
+        };
+        storage.commit(&mut hm, event)?;
+    }
+    changes.notify();
 
-    project_cache.insert(entry.key.clone(), entry.value.clone());
-    Json(Ok(()))
+    Ok(Json(()))
 }
 
 #[post("/signupkeygen", format = "json", data = "<request>")]
 fn signup_keygen(
-    db_mtx: State<RwLock<HashMap<String, Project>>>,
-    request: Json<(String, String)>,
-) -> Json<Result<PartySignup, ()>> {
-    let (project_name, _) = request.into_inner();
+    client: KnownClient,
+    db_mtx: &State<Arc<Projects>>,
+    storage: &State<Arc<Storage>>,
+    request: Json<(String, Signup)>,
+) -> Reply<PartySignup> {
+    let keygen = |op: &Operation| matches!(op, Operation::KeyGen { .. });
+    Ok(Json(signup(
+        client,
+        db_mtx,
+        storage,
+        request.into_inner(),
+        keygen,
+    )?))
+}
 
-    let hm = db_mtx.read().unwrap();
-    let project = hm.get(&project_name).unwrap();
+#[post("/signupsign", format = "json", data = "<request>")]
+fn signup_sign(
+    client: KnownClient,
+    db_mtx: &State<Arc<Projects>>,
+    storage: &State<Arc<Storage>>,
+    request: Json<(String, Signup)>,
+) -> Reply<PartySignup> {
+    let signing = |op: &Operation| {
+        matches!(
+            op,
+            Operation::SignTag { .. } | Operation::SignKey { .. } | Operation::SignStatement { .. }
+        )
+    };
+    Ok(Json(signup(
+        client,
+        db_mtx,
+        storage,
+        request.into_inner(),
+        signing,
+    )?))
+}
 
-    let op = &project.operation;
-    let parties = match **op {
-        Operation::KeyGen { participants, .. } => participants,
-        _ => return Json(Err(())),
-    } as usize;
-    let participants = &project.participants;
+/// Hand out a party number in the session of the signup, as long as `accepts` its operation.
+/// The lock is held throughout, so that no two clients ever get the same number.
+fn signup<F: Fn(&Operation) -> bool>(
+    client: KnownClient,
+    db_mtx: &Projects,
+    storage: &Storage,
+    (project_name, signup): (String, Signup),
+    accepts: F,
+) -> Result<PartySignup, Refusal> {
+    check_name("the project", &project_name)?;
+    check_name("the session", &signup.session)?;
 
-    let res = if participants.load(Ordering::SeqCst) < parties {
-        let index = participants.fetch_add(1, Ordering::SeqCst) + 1;
+    let mut hm = db_mtx.write().unwrap();
+    let session = pending_mut(&mut hm, &project_name, &signup.session)?;
+    if !accepts(&session.current()) {
+        return Err(Refusal::WrongOperation);
+    }
 
-        Ok(PartySignup {
-            number: index as u16,
-        })
-    } else {
-        Err(())
-    };
+    let number = session.assign(signup.party, client.0.as_ref())?;
+    if !session.parties.contains_key(&number) {
+        let event = Event::Signup {
+            project: project_name,
+            session: signup.session.clone(),
+            party: Some(number),
+            by: client.0,
+        };
+        storage.commit(&mut hm, event)?;
+    }
 
-    Json(res)
+    Ok(PartySignup {
+        number,
+        session: signup.session,
+    })
 }
 
-#[post("/signupsign", format = "json", data = "<request>")]
-fn signup_sign(db_mtx: State<RwLock<HashMap<String, Project>>>, request: Json<(String, String)>) -> Json<Result<PartySignup, ()>> {
-    let (project_name, _) = request.into_inner();
-
-    let hm = db_mtx.read().unwrap();
-    let project = hm.get(&project_name).unwrap();
+/// Give up the party number of the signup, which goes to whoever signs up next, along with
+/// the messages already sent under it. This only works until every party has signed up.
+#[post("/signout", format = "json", data = "<request>")]
+fn signout(
+    client: KnownClient,
+    db_mtx: &State<Arc<Projects>>,
+    storage: &State<Arc<Storage>>,
+    changes: &State<Arc<Changes>>,
+    request: Json<(String, PartySignup)>,
+) -> Reply<()> {
+    let (project_name, signup) = request.into_inner();
+    check_name("the project", &project_name)?;
+    check_name("the session", &signup.session)?;
 
-    let op = &project.operation;
-    let threshold = match **op {
-        Operation::SignTag {
-            participants: _,
-            threshold,
-            ..
-        } => threshold,
-        Operation::SignKey {
-            participants: _,
-            threshold,
-            ..
-        } => threshold,
-        _ => {
-            panic!("Trying to register for signatures when everything is not yet done!");
+    {
+        let mut hm = db_mtx.write().unwrap();
+        let session = pending_mut(&mut hm, &project_name, &signup.session)?;
+        session.holds(signup.number, client.0.as_ref())?;
+        if session.parties.len() >= session.slots() as usize {
+            return Err(Refusal::Started.into());
         }
-    } as usize;
 
-    let participants = &project.participants;
+        let event = Event::Signout {
+            project: project_name,
+            session: signup.session,
+            party: signup.number,
+        };
+        storage.commit(&mut hm, event)?;
+    }
+    changes.notify();
 
-    let res = if participants.load(Ordering::SeqCst) < threshold + 1 {
-        let index = participants.fetch_add(1, Ordering::SeqCst) + 1;
+    Ok(Json(()))
+}
 
-        Ok(PartySignup {
-            number: index as u16,
+/// Hand the party number of the signup back to the participant it went to, who lost track of
+/// the operation (say, in a crash) and picks it up where they left off
+#[post("/rejoin", format = "json", data = "<request>")]
+fn rejoin(
+    client: KnownClient,
+    db_mtx: &State<Arc<Projects>>,
+    request: Json<(String, PartySignup)>,
+) -> Reply<PartySignup> {
+    let (project_name, signup) = request.into_inner();
+    check_name("the project", &project_name)?;
+    check_name("the session", &signup.session)?;
+
+    let mut hm = db_mtx.write().unwrap();
+    let session = pending_mut(&mut hm, &project_name, &signup.session)?;
+    session.holds(signup.number, client.0.as_ref())?;
+    session.active = SystemTime::now();
+
+    Ok(Json(signup))
+}
+
+/// Publish OpenPGP certificates of the project, or signatures on them such as revocations, as
+/// binary packets in base64. Returns the fingerprints of the certificates they changed, which
+/// anyone can then look up over HKP. Only clients the server knows publish, and only the keys
+/// whose generation the transparency log of the project records.
+#[post("/publish-key", format = "json", data = "<request>")]
+fn publish_key(
+    client: KnownClient,
+    db: &State<Arc<Projects>>,
+    storage: &State<Arc<Storage>>,
+    request: Json<(String, String)>,
+) -> Reply<Vec<String>> {
+    if client.0.is_none() {
+        return Err(Refusal::Unauthorized.into());
+    }
+    let (project_name, certificate) = request.into_inner();
+    check_name("the project", &project_name)?;
+    let packets = base64::decode(&certificate)
+        .map_err(|_| Refusal::Invalid("the certificate is not in base64".to_owned()))?;
+
+    let mut write_db = db.write().unwrap();
+    let project = write_db.get(&project_name).ok_or(Refusal::UnknownProject)?;
+    let updated = project.keys.updates(&packets).map_err(Refusal::Invalid)?;
+    for certificate in updated.iter() {
+        let digest = certificate.key_digest();
+        let generated = project
+            .log
+            .iter()
+            .any(|entry| entry.kind == EntryKind::KeyGen && entry.digest == digest);
+        if !generated {
+            let reason = format!(
+                "the project did not generate the key {}",
+                certificate.fingerprint()
+            );
+            return Err(Refusal::Invalid(reason).into());
+        }
+    }
+    let fingerprints = updated.iter().map(|key| key.fingerprint()).collect();
+    let revocations = project.keys.new_revocations(&updated);
+
+    let event = Event::Publish {
+        project: project_name.clone(),
+        certificate,
+        by: client.0.clone(),
+    };
+    storage.commit(&mut write_db, event)?;
+
+    // Revoking the key of a project is as much of a signing event as using it
+    for (fingerprint, revocation) in revocations {
+        let entry = LogEntry {
+            kind: EntryKind::Revocation,
+            digest: sha256_hex(&revocation),
+            signers: vec![fingerprint],
+            timestamp: unix_time(),
+        };
+        let event = Event::Append {
+            project: project_name.clone(),
+            entry,
+            by: client.0.clone(),
+        };
+        storage.commit(&mut write_db, event)?;
+    }
+
+    Ok(Json(fingerprints))
+}
+
+/// Certificates published to any of the projects, over the HTTP Keyserver Protocol, so that
+/// `gpg --keyserver hkp://...` can fetch them. Both `op=get` and `op=index` are supported, and
+/// since certificates are public, clients do not have to be known to the server to look them up.
+#[get("/pks/lookup?<op>&<search>")]
+fn lookup(
+    db: &State<Arc<Projects>>,
+    op: &str,
+    search: String,
+) -> (Status, (ContentType, String)) {
+    let mut found = Keyring::default();
+    for project in db.read().unwrap().values() {
+        let matching = project.keys.certificates().filter(|key| key.matches(&search));
+        for certificate in matching {
+            found.merge(certificate.clone());
+        }
+    }
+
+    let keys = ContentType::new("application", "pgp-keys");
+    match op {
+        "get" | "index" | "vindex" if found.is_empty() => {
+            (Status::NotFound, (ContentType::Plain, "No keys found".to_owned()))
+        }
+        "get" => (Status::Ok, (keys, armor_public_key(&found.to_bytes()))),
+        "index" | "vindex" => (Status::Ok, (ContentType::Plain, found.index())),
+        _ => (
+            Status::NotImplemented,
+            (ContentType::Plain, format!("Unsupported operation {}", op)),
+        ),
+    }
+}
+
+/// Log the outcome of the operation of a session in the transparency log of the project, which
+/// its leader does once it is finalized. Only identified participants of the operation can log
+/// it, and the server fills in who they were. Returns the index of the entry, which is the one it
+/// already has when the digest was logged before.
+#[post("/log/append", format = "json", data = "<request>")]
+fn log_append(
+    client: KnownClient,
+    db: &State<Arc<Projects>>,
+    storage: &State<Arc<Storage>>,
+    request: Json<(String, LogAppend)>,
+) -> Reply<u64> {
+    let (project_name, append) = request.into_inner();
+    check_name("the project", &project_name)?;
+    check_name("the session", &append.session)?;
+    check_digest(&append.digest)?;
+    let digest = append.digest.to_lowercase();
+
+    let mut write_db = db.write().unwrap();
+    let session = find_session(&write_db, &project_name, &append.session)?;
+    session.loggable(append.kind, client.0.as_ref())?;
+    let signers = session
+        .parties
+        .iter()
+        .map(|(number, by)| by.clone().unwrap_or_else(|| number.to_string()))
+        .collect();
+
+    let log = &write_db[&project_name].log;
+    let logged = log
+        .iter()
+        .position(|entry| entry.kind == append.kind && entry.digest == digest);
+    if let Some(index) = logged {
+        return Ok(Json(index as u64));
+    }
+
+    let index = log.len() as u64;
+    let entry = LogEntry {
+        kind: append.kind,
+        digest,
+        signers,
+        timestamp: unix_time(),
+    };
+    let event = Event::Append {
+        project: project_name,
+        entry,
+        by: client.0,
+    };
+    storage.commit(&mut write_db, event)?;
+
+    Ok(Json(index))
+}
+
+/// Current head of the transparency log of the project, signed by the key of the log. Like
+/// the rest of the log, it is public, so that anyone can keep an eye on what gets signed.
+#[post("/log/head", format = "json", data = "<request>")]
+fn log_head(
+    db: &State<Arc<Projects>>,
+    log_key: &State<LogKey>,
+    request: Json<(String, usize)>,
+) -> Reply<SignedTreeHead> {
+    let (project_name, _) = request.into_inner();
+    check_name("the project", &project_name)?;
+
+    let read_db = db.read().unwrap();
+    let empty = Tree::default();
+    let tree = read_db.get(&project_name).map_or(&empty, |project| &project.tree);
+    let root = tree.root(tree.size()).expect("The whole tree has a root");
+
+    Ok(Json(SignedTreeHead::new(
+        &project_name,
+        tree.size(),
+        &root,
+        unix_time(),
+        &log_key.0,
+    )))
+}
+
+/// Entries of the transparency log with the given digest, along with their indices
+#[post("/log/find", format = "json", data = "<request>")]
+fn log_find(
+    db: &State<Arc<Projects>>,
+    request: Json<(String, String)>,
+) -> Reply<Vec<(u64, LogEntry)>> {
+    let (project_name, digest) = request.into_inner();
+    check_name("the project", &project_name)?;
+    check_digest(&digest)?;
+    let digest = digest.to_lowercase();
+
+    let found = match db.read().unwrap().get(&project_name) {
+        Some(project) => project
+            .log
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| entry.digest == digest)
+            .map(|(index, entry)| (index as u64, entry.clone()))
+            .collect(),
+        None => vec![],
+    };
+
+    Ok(Json(found))
+}
+
+/// Entries of the transparency log from the given index on, at most `MAX_BATCH_KEYS` of them
+#[post("/log/entries", format = "json", data = "<request>")]
+fn log_entries(
+    db: &State<Arc<Projects>>,
+    request: Json<(String, u64)>,
+) -> Reply<Vec<LogEntry>> {
+    let (project_name, start) = request.into_inner();
+    check_name("the project", &project_name)?;
+
+    let entries = match db.read().unwrap().get(&project_name) {
+        Some(project) => project
+            .log
+            .iter()
+            .skip(start as usize)
+            .take(MAX_BATCH_KEYS)
+            .cloned()
+            .collect(),
+        None => vec![],
+    };
+
+    Ok(Json(entries))
+}
+
+/// Inclusion or consistency proof for the transparency log, as hashes in hex
+#[post("/log/proof", format = "json", data = "<request>")]
+fn log_proof(
+    db: &State<Arc<Projects>>,
+    request: Json<(String, ProofRequest)>,
+) -> Reply<Vec<String>> {
+    let (project_name, proof) = request.into_inner();
+    check_name("the project", &project_name)?;
+
+    let read_db = db.read().unwrap();
+    let tree = &read_db
+        .get(&project_name)
+        .ok_or(Refusal::UnknownProject)?
+        .tree;
+    let hashes = match proof {
+        ProofRequest::Inclusion { index, size } => tree.inclusion(index, size),
+        ProofRequest::Consistency { first, second } => tree.consistency(first, second),
+    }
+    .ok_or_else(|| Refusal::Invalid("the log does not have these entries".to_owned()))?;
+
+    Ok(Json(hashes.iter().map(|hash| to_hex(hash)).collect()))
+}
+
+/// Every project, with the number of its pending and archived sessions
+#[post("/admin/projects")]
+fn admin_projects(
+    _admin: Admin,
+    db: &State<Arc<Projects>>,
+) -> Json<Vec<ProjectSummary>> {
+    let read_db = db.read().unwrap();
+    let mut projects: Vec<ProjectSummary> = read_db
+        .iter()
+        .map(|(name, project)| {
+            let pending = project.pending().count();
+            ProjectSummary {
+                name: name.clone(),
+                pending,
+                archived: project.order.len() - pending,
+            }
         })
-    } else {
-        Err(())
+        .collect();
+    projects.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Json(projects)
+}
+
+/// Every session of the project, oldest first, along with its parties and messages
+#[post("/admin/sessions", format = "json", data = "<request>")]
+fn admin_sessions(
+    _admin: Admin,
+    db: &State<Arc<Projects>>,
+    request: Json<String>,
+) -> Reply<Vec<SessionReport>> {
+    let project_name = request.into_inner();
+    check_name("the project", &project_name)?;
+
+    let read_db = db.read().unwrap();
+    let project = read_db
+        .get(&project_name)
+        .ok_or(Refusal::UnknownProject)?;
+
+    let sessions = project
+        .order
+        .iter()
+        .map(|id| project.sessions[id].report(id))
+        .collect();
+    Ok(Json(sessions))
+}
+
+/// Abort the operation of the session and archive it at once, for operations whose leader is
+/// no longer around to end them. The participants still following it see it as aborted.
+#[post("/admin/abort", format = "json", data = "<request>")]
+fn admin_abort(
+    _admin: Admin,
+    db: &State<Arc<Projects>>,
+    storage: &State<Arc<Storage>>,
+    changes: &State<Arc<Changes>>,
+    request: Json<(String, String)>,
+) -> Reply<()> {
+    let (project_name, session) = request.into_inner();
+    check_name("the project", &project_name)?;
+    check_name("the session", &session)?;
+
+    {
+        let mut write_db = db.write().unwrap();
+        pending_mut(&mut write_db, &project_name, &session)?;
+
+        let event = Event::Abort {
+            project: project_name,
+            session,
+            at: SystemTime::now(),
+        };
+        storage.commit(&mut write_db, event)?;
+    }
+    changes.notify();
+
+    Ok(Json(()))
+}
+
+/// Drop the sessions of the project archived at least the given number of seconds ago, without
+/// waiting for them to expire, returning how many were dropped
+#[post("/admin/purge", format = "json", data = "<request>")]
+fn admin_purge(
+    _admin: Admin,
+    db: &State<Arc<Projects>>,
+    storage: &State<Arc<Storage>>,
+    request: Json<(String, u64)>,
+) -> Reply<usize> {
+    let (project_name, older_than) = request.into_inner();
+    check_name("the project", &project_name)?;
+
+    let mut write_db = db.write().unwrap();
+    let before = write_db
+        .get(&project_name)
+        .ok_or(Refusal::UnknownProject)?
+        .order
+        .len();
+
+    let event = Event::Purge {
+        project: project_name.clone(),
+        before: SystemTime::now()
+            .checked_sub(Duration::from_secs(older_than))
+            .unwrap_or(SystemTime::UNIX_EPOCH),
     };
+    storage.commit(&mut write_db, event)?;
 
-    Json(res)
+    Ok(Json(before - write_db[&project_name].order.len()))
+}
+
+/// Events making up the state of the project, which replayed on a fresh server reproduce it
+#[post("/admin/export", format = "json", data = "<request>")]
+fn admin_export(
+    _admin: Admin,
+    db: &State<Arc<Projects>>,
+    request: Json<String>,
+) -> Reply<Vec<Event>> {
+    let project_name = request.into_inner();
+    check_name("the project", &project_name)?;
+
+    let read_db = db.read().unwrap();
+    let project = read_db
+        .get(&project_name)
+        .ok_or(Refusal::UnknownProject)?;
+    Ok(Json(project.snapshot(&project_name)))
+}
+
+/// Requests that never reach a route, such as those from unknown clients, those that do not
+/// parse and those over the size limits, are refused with a reason all the same
+#[catch(400)]
+fn bad_request() -> Json<Refusal> {
+    Json(Refusal::Invalid("the request could not be read".to_owned()))
+}
+
+#[catch(403)]
+fn forbidden() -> Json<Refusal> {
+    Json(Refusal::Unauthorized)
+}
+
+#[catch(404)]
+fn not_found(request: &Request<'_>) -> Json<Refusal> {
+    Json(Refusal::Invalid(format!("no such route {}", request.uri().path())))
+}
+
+#[catch(413)]
+fn too_large() -> Json<Refusal> {
+    Json(Refusal::TooLarge)
+}
+
+#[catch(422)]
+fn unprocessable() -> Json<Refusal> {
+    Json(Refusal::Invalid("the request does not have the expected fields".to_owned()))
 }
 
 struct Project {
-    operation: Arc<Operation>,
-    participants: AtomicUsize,
-    cache: RwLock<HashMap<Key, String>>,
+    /// Sessions of the project, in the order they were started
+    order: Vec<String>,
+    sessions: HashMap<String, Session>,
+    /// Certificates published to the project, which outlive its sessions
+    keys: Keyring,
+    /// Transparency log of the signing events of the project, which is never pruned
+    log: Vec<LogEntry>,
+    tree: Tree,
 }
 
+/// One operation and its messages. When the operation ends, its session is archived rather than
+/// dropped, and only expires once it has been archived for longer than the `Retention`.
+struct Session {
+    /// The operation as it was started, which is reported as `Blame` once it is aborted
+    operation: Operation,
+    stage: Stage,
+    /// When the operation last moved along, which is when it was started, someone signed up
+    /// for it or a message arrived. After a restart, it is when the server came back.
+    active: SystemTime,
+    /// Identity of the client that started the operation
+    initiator: Option<String>,
+    end_votes: HashSet<String>,
+    abort_votes: HashSet<String>,
+    /// Identity keys of the members of the project, in the order of their key shares
+    roster: Vec<String>,
+    /// Party numbers handed out so far, along with the identities of the clients they went to
+    parties: BTreeMap<u16, Option<String>>,
+    cache: HashMap<Key, String>,
+    ended: Option<SystemTime>,
+    /// Started by the leader in place of this operation once it ended, which is how the
+    /// participants of a key generation find their way to the signing of the key
+    successor: Option<String>,
+}
+
+impl Project {
+    fn new() -> Project {
+        Project {
+            order: Vec::new(),
+            sessions: HashMap::new(),
+            keys: Keyring::default(),
+            log: Vec::new(),
+            tree: Tree::default(),
+        }
+    }
+
+    /// Queue `operation` under the session `id`, in place of the ended operation of `after`
+    fn start(
+        &mut self,
+        id: String,
+        operation: Operation,
+        after: Option<String>,
+        initiator: Option<String>,
+        roster: Vec<String>,
+    ) {
+        self.sessions.insert(
+            id.clone(),
+            Session {
+                operation,
+                stage: Stage::Proposed,
+                active: SystemTime::now(),
+                initiator,
+                end_votes: HashSet::new(),
+                abort_votes: HashSet::new(),
+                roster,
+                parties: BTreeMap::new(),
+                cache: HashMap::new(),
+                ended: None,
+                successor: None,
+            },
+        );
+        self.order.push(id.clone());
+
+        let previous = after.and_then(|after| self.sessions.get_mut(&after));
+        if let Some(previous) = previous.filter(|previous| previous.ended.is_some()) {
+            previous.successor = Some(id);
+        }
+    }
+
+    /// Sessions of the operations that have been started but not ended yet, oldest first
+    fn pending(&self) -> impl Iterator<Item = &String> {
+        self.order.iter().filter(move |id| self.running(id).is_some())
+    }
+
+    fn running(&self, id: &str) -> Option<&Session> {
+        self.sessions.get(id).filter(|session| session.ended.is_none())
+    }
+
+    fn running_mut(&mut self, id: &str) -> Option<&mut Session> {
+        self.sessions
+            .get_mut(id)
+            .filter(|session| session.ended.is_none())
+    }
+
+    /// The pending operation of the session `id` (or of the oldest session, without one),
+    /// following the operations started in place of the ones that ended
+    fn resolve(&self, id: Option<&String>) -> Option<Queued> {
+        let mut id = id.or_else(|| self.pending().next())?;
+
+        loop {
+            let session = self.sessions.get(id)?;
+            match (session.ended, &session.successor) {
+                (None, _) => return Some(session.queued(id)),
+                (Some(_), Some(successor)) => id = successor,
+                // Operations that expired or were aborted by an operator are ended along with
+                // it, and their participants have to find out as well
+                (Some(_), None) if session.is_aborted() => return Some(session.queued(id)),
+                (Some(_), None) => return None,
+            }
+        }
+    }
+
+    fn expire(&mut self, retention: Duration) {
+        self.prune(|ended| matches!(ended.elapsed(), Ok(age) if age >= retention));
+    }
+
+    /// Drop the archived sessions for which `dropped` holds, given the time they ended
+    fn prune<F: Fn(SystemTime) -> bool>(&mut self, dropped: F) {
+        self.sessions.retain(|_, session| match session.ended {
+            Some(ended) => !dropped(ended),
+            None => true,
+        });
+
+        let sessions = &self.sessions;
+        self.order.retain(|id| sessions.contains_key(id));
+    }
+
+    /// Events leading to the current state of the project `name`
+    fn snapshot(&self, name: &str) -> Vec<Event> {
+        let mut events: Vec<Event> = self
+            .keys
+            .certificates()
+            .map(|certificate| Event::Publish {
+                project: name.to_owned(),
+                certificate: base64::encode(certificate.to_bytes()),
+                by: None,
+            })
+            .collect();
+        events.extend(self.log.iter().map(|entry| Event::Append {
+            project: name.to_owned(),
+            entry: entry.clone(),
+            by: None,
+        }));
+
+        for id in self.order.iter() {
+            let session = &self.sessions[id];
+            let after = self
+                .order
+                .iter()
+                .find(|other| self.sessions[*other].successor.as_ref() == Some(id))
+                .cloned();
+
+            events.push(Event::Start {
+                project: name.to_owned(),
+                session: id.clone(),
+                operation: session.operation.clone(),
+                after,
+                by: session.initiator.clone(),
+                roster: session.roster.clone(),
+            });
+            for (&number, by) in session.parties.iter() {
+                events.push(Event::Signup {
+                    project: name.to_owned(),
+                    session: id.clone(),
+                    party: Some(number),
+                    by: by.clone(),
+                });
+            }
+            for (key, value) in session.cache.iter() {
+                events.push(Event::Set {
+                    project: name.to_owned(),
+                    session: id.clone(),
+                    key: key.clone(),
+                    value: value.clone(),
+                });
+            }
+
+            // Operations the server ended on its own, or by order of an operator
+            let project = name.to_owned();
+            let session_id = id.clone();
+            let ended = match (&session.stage, session.ended) {
+                (Stage::Aborted(AbortReason::Expired), Some(at)) => Some(Event::Expire {
+                    project,
+                    session: session_id,
+                    at,
+                }),
+                (Stage::Aborted(AbortReason::Operator), Some(at)) => Some(Event::Abort {
+                    project,
+                    session: session_id,
+                    at,
+                }),
+                _ => None,
+            };
+            if let Some(event) = ended {
+                events.push(event);
+                continue;
+            }
+
+            // Votes that did not make a majority still count once the server is back, while
+            // the changes that were made are replayed as if nobody was identified
+            let votes = if session.is_aborted() {
+                vec![None]
+            } else {
+                session.abort_votes.iter().cloned().map(Some).collect()
+            };
+            for by in votes {
+                events.push(Event::Blame {
+                    project: name.to_owned(),
+                    session: id.clone(),
+                    by,
+                });
+            }
+
+            let votes = match session.ended {
+                Some(at) => vec![(None, at)],
+                None => session
+                    .end_votes
+                    .iter()
+                    .map(|by| (Some(by.clone()), SystemTime::now()))
+                    .collect(),
+            };
+            for (by, at) in votes {
+                events.push(Event::End {
+                    project: name.to_owned(),
+                    session: id.clone(),
+                    at,
+                    by,
+                });
+            }
+        }
+
+        events
+    }
+}
+
+impl Session {
+    fn current(&self) -> Operation {
+        if self.is_aborted() {
+            Operation::Blame {}
+        } else {
+            self.operation.clone()
+        }
+    }
+
+    fn queued(&self, id: &str) -> Queued {
+        Queued {
+            session: id.to_owned(),
+            operation: self.current(),
+            stage: self.stage.clone(),
+        }
+    }
+
+    /// Kind of entry the outcome of the operation is logged as in the transparency log
+    fn logged_as(&self) -> Option<EntryKind> {
+        match self.operation {
+            Operation::KeyGen { .. } => Some(EntryKind::KeyGen),
+            Operation::SignKey { .. } => Some(EntryKind::KeySignature),
+            Operation::SignTag { .. } => Some(EntryKind::TagSignature),
+            Operation::SignStatement { .. } => Some(EntryKind::Attestation),
+            Operation::Idle | Operation::Blame {} => None,
+        }
+    }
+
+    fn is_aborted(&self) -> bool {
+        matches!(self.stage, Stage::Aborted(_))
+    }
+
+    /// Abort the operation for `reason`, unless it already was for another one
+    fn abort(&mut self, reason: AbortReason) {
+        if !self.is_aborted() {
+            self.stage = Stage::Aborted(reason);
+        }
+    }
+
+    /// Number of parties taking part in the operation
+    fn slots(&self) -> u16 {
+        match self.operation {
+            Operation::KeyGen { participants, .. } => participants,
+            Operation::SignTag { threshold, .. }
+            | Operation::SignKey { threshold, .. }
+            | Operation::SignStatement { threshold, .. } => threshold + 1,
+            Operation::Idle | Operation::Blame {} => 0,
+        }
+    }
+
+    /// Move the operation along, after a signup or a message of the given round. Operations
+    /// only run once every party has signed up, since the first parties send their first
+    /// messages before the last one shows up.
+    fn progress(&mut self, round: Option<&str>) {
+        self.active = SystemTime::now();
+        if !matches!(
+            self.stage,
+            Stage::Proposed | Stage::CollectingSignups | Stage::Running(_)
+        ) {
+            return;
+        }
+
+        self.stage = match round {
+            Some(round) if self.parties.len() >= self.slots() as usize => {
+                Stage::Running(round.to_owned())
+            }
+            _ if self.parties.is_empty() => Stage::Proposed,
+            _ => Stage::CollectingSignups,
+        };
+    }
+
+    /// Whether the operation stalled for longer than `expiry` allows at its stage
+    fn stalled(&self, expiry: &Expiry) -> bool {
+        let limit = match self.stage {
+            Stage::Proposed | Stage::CollectingSignups => expiry.signups,
+            // Aborted operations wait for their leader to end them
+            Stage::Running(_) | Stage::Aborted(_) => expiry.rounds,
+            Stage::Finalized => None,
+        };
+
+        match (limit, self.active.elapsed()) {
+            (Some(limit), Ok(idle)) => idle >= limit,
+            _ => false,
+        }
+    }
+
+    fn report(&self, id: &str) -> SessionReport {
+        let mut messages = BTreeMap::new();
+        for key in self.cache.keys() {
+            *messages.entry(admin::round_of(key).to_owned()).or_insert(0) += 1;
+        }
+
+        SessionReport {
+            session: id.to_owned(),
+            operation: self.operation.clone(),
+            stage: self.stage.clone(),
+            initiator: self.initiator.clone(),
+            parties: self
+                .parties
+                .iter()
+                .map(|(&number, by)| (number, by.clone()))
+                .collect(),
+            messages,
+            ended: self.ended,
+            successor: self.successor.clone(),
+        }
+    }
+
+    /// Party number for the signup of `by`, who keeps the one they already have. Clients the
+    /// server knows have to be members of the roster, if the operation came with one, and
+    /// generate the key share of their place in it. Anyone else gets the number they claim, or
+    /// the lowest free one.
+    fn assign(&self, party: Option<u16>, by: Option<&String>) -> Result<u16, Refusal> {
+        if let Some(by) = by {
+            let existing = self.parties.iter().find(|(_, other)| other.as_ref() == Some(by));
+            if let Some((&number, _)) = existing {
+                return Ok(number);
+            }
+        }
+
+        let place = match by {
+            Some(by) if !self.roster.is_empty() => {
+                let place = self.roster.iter().position(|member| member == by);
+                Some(place.ok_or(Refusal::Unlisted)? as u16 + 1)
+            }
+            _ => None,
+        };
+
+        let slots = self.slots();
+        let number = match (&self.operation, place, party) {
+            (Operation::KeyGen { .. }, Some(place), _) => place,
+            (_, _, Some(party)) if !(1..=slots).contains(&party) => {
+                let reason = format!("party numbers run from 1 to {}", slots);
+                return Err(Refusal::Invalid(reason));
+            }
+            (_, _, Some(party)) => party,
+            _ => (1..=slots)
+                .find(|number| !self.parties.contains_key(number))
+                .ok_or(Refusal::Full)?,
+        };
+
+        Some(number)
+            .filter(|number| (1..=slots).contains(number) && !self.parties.contains_key(number))
+            .ok_or(Refusal::Full)
+    }
+
+    /// Whether `value` is a new message under `key`. Stored messages are never replaced, so
+    /// that a participant resuming after a crash cannot change what the others may have read:
+    /// sending the same one again changes nothing, and sending another one is refused.
+    fn takes(&self, key: &str, value: &str) -> Result<bool, Refusal> {
+        match self.cache.get(key) {
+            None => Ok(true),
+            Some(stored) if stored == value => Ok(false),
+            Some(_) => Err(Refusal::Conflict),
+        }
+    }
+
+    /// Check that `party` went to `by`, who alone can pick it up again or give it up. On
+    /// servers without `Clients`, nobody is told apart.
+    fn holds(&self, party: u16, by: Option<&String>) -> Result<(), Refusal> {
+        match self.parties.get(&party) {
+            Some(holder) if holder.as_ref() == by => Ok(()),
+            Some(_) => Err(Refusal::Invalid(format!(
+                "party {} went to another participant",
+                party
+            ))),
+            None => Err(Refusal::Invalid(format!("nobody signed up as party {}", party))),
+        }
+    }
+
+    /// Check that the outcome of the operation can be logged as `kind` by `by`, which takes a
+    /// finalized operation and one of its parties. Anonymous clients are never told apart, so
+    /// they cannot log anything.
+    fn loggable(&self, kind: EntryKind, by: Option<&String>) -> Result<(), Refusal> {
+        let by = by.ok_or(Refusal::Unauthorized)?;
+        if !self.parties.values().any(|party| party.as_ref() == Some(by)) {
+            return Err(Refusal::Unauthorized);
+        }
+        if self.logged_as() != Some(kind) {
+            return Err(Refusal::WrongOperation);
+        }
+
+        match self.stage {
+            Stage::Finalized => Ok(()),
+            Stage::Aborted(_) => Err(Refusal::Invalid("the operation was aborted".to_owned())),
+            _ => Err(Refusal::Invalid("the operation has not been finalized".to_owned())),
+        }
+    }
+
+    /// Check that `by` has a say in ending or aborting the operation, which takes having
+    /// started it, signed up for it, or being in its roster. Without `Clients`, nobody is told
+    /// apart, and anyone does.
+    fn votes(&self, by: Option<&String>) -> Result<(), Refusal> {
+        match by {
+            Some(by) if !self.electorate().contains(by) && Some(by) != self.initiator.as_ref() => {
+                Err(Refusal::Unauthorized)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Identified clients that signed up for the operation or are in its roster
+    fn electorate(&self) -> HashSet<&String> {
+        self.parties
+            .values()
+            .flatten()
+            .chain(self.roster.iter())
+            .collect()
+    }
+
+    /// Whether ending (or otherwise aborting) the operation at the request of `by` takes effect,
+    /// either because they started it, because they signed up and blame it, or because their
+    /// vote completes a majority of the parties and roster members. Without `Clients`, nobody is
+    /// identified, and every request takes effect.
+    fn carries(&mut self, by: Option<String>, end: bool) -> bool {
+        let by = match by {
+            Some(by) if Some(&by) != self.initiator.as_ref() => by,
+            _ => return true,
+        };
+        if self.votes(Some(&by)).is_err() {
+            return false;
+        }
+        if !end && self.parties.values().any(|party| party.as_ref() == Some(&by)) {
+            return true;
+        }
+
+        let electorate = self.electorate().len();
+        let votes = if end {
+            &mut self.end_votes
+        } else {
+            &mut self.abort_votes
+        };
+        votes.insert(by);
+        votes.len() > electorate / 2
+    }
+}
+
+/// Apply `event` to the state, both as it happens and when restoring the state from the store
+fn apply(projects: &mut HashMap<String, Project>, event: Event) {
+    match event {
+        Event::Start {
+            project,
+            session,
+            operation,
+            after,
+            by,
+            roster,
+        } => projects
+            .entry(project)
+            .or_insert_with(Project::new)
+            .start(session, operation, after, by, roster),
+        Event::Signup {
+            project,
+            session,
+            party,
+            by,
+        } => {
+            if let Some(session) = running_mut(projects, &project, &session) {
+                if let Ok(number) = session.assign(party, by.as_ref()) {
+                    // Roster members who voted to abort go on to blame it as parties
+                    let blamed = matches!(&by, Some(by) if session.abort_votes.remove(by));
+                    session.parties.insert(number, by);
+                    session.progress(None);
+                    if blamed {
+                        session.abort(AbortReason::Blamed);
+                    }
+                }
+            }
+        }
+        Event::Set {
+            project,
+            session,
+            key,
+            value,
+        } => {
+            if let Some(session) = running_mut(projects, &project, &session) {
+                session.progress(Some(admin::round_of(&key)));
+                session.cache.entry(key).or_insert(value);
+            }
+        }
+        Event::Signout {
+            project,
+            session,
+            party,
+        } => {
+            if let Some(session) = running_mut(projects, &project, &session) {
+                session.parties.remove(&party);
+                // Whoever takes the number next sends their own messages in place of these
+                let sender = party.to_string();
+                session
+                    .cache
+                    .retain(|key, _| key.split('-').next() != Some(sender.as_str()));
+                session.progress(None);
+            }
+        }
+        Event::Blame {
+            project,
+            session,
+            by,
+        } => {
+            if let Some(session) = running_mut(projects, &project, &session) {
+                if session.carries(by, false) {
+                    session.abort(AbortReason::Blamed);
+                }
+            }
+        }
+        Event::End {
+            project,
+            session,
+            at,
+            by,
+        } => {
+            if let Some(session) = running_mut(projects, &project, &session) {
+                if session.carries(by, true) {
+                    if !session.is_aborted() {
+                        session.stage = Stage::Finalized;
+                    }
+                    session.ended = Some(at);
+                }
+            }
+        }
+        Event::Abort {
+            project,
+            session,
+            at,
+        } => {
+            if let Some(session) = running_mut(projects, &project, &session) {
+                session.abort(AbortReason::Operator);
+                session.ended = Some(at);
+            }
+        }
+        Event::Expire {
+            project,
+            session,
+            at,
+        } => {
+            if let Some(session) = running_mut(projects, &project, &session) {
+                session.abort(AbortReason::Expired);
+                session.ended = Some(at);
+            }
+        }
+        Event::Purge { project, before } => {
+            if let Some(project) = projects.get_mut(&project) {
+                project.prune(|ended| ended < before);
+            }
+        }
+        Event::Publish {
+            project,
+            certificate,
+            by: _,
+        } => {
+            // Projects whose sessions were all purged keep their keys
+            let keys = &mut projects.entry(project).or_insert_with(Project::new).keys;
+            let updated = base64::decode(&certificate)
+                .map_err(|err| err.to_string())
+                .and_then(|packets| keys.updates(&packets));
+            if let Ok(updated) = updated {
+                keys.insert(updated);
+            }
+        }
+        Event::Append {
+            project,
+            entry,
+            by: _,
+        } => {
+            let project = projects.entry(project).or_insert_with(Project::new);
+            project.tree.push(&entry);
+            project.log.push(entry);
+        }
+    }
+}
+
+fn running_mut<'a>(
+    projects: &'a mut HashMap<String, Project>,
+    project: &str,
+    session: &str,
+) -> Option<&'a mut Session> {
+    projects
+        .get_mut(project)
+        .and_then(|project| project.running_mut(session))
+}
+
+/// Events leading to the current state, which replace everything recorded before them when
+/// compacting the store
+fn snapshot(projects: &HashMap<String, Project>) -> Vec<Event> {
+    projects
+        .iter()
+        .flat_map(|(name, project)| project.snapshot(name))
+        .collect()
+}
+
+/// Seconds since the epoch, as the transparency log records them
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+/// Serve the operations, unless asked to run `dit-server admin` against a running server
 fn main() {
-    let projects: HashMap<String, Project> = HashMap::with_capacity(1);
-    let db_mtx = RwLock::new(projects);
+    let mut args = std::env::args().skip(1);
+    if args.next().as_deref() == Some("admin") {
+        std::process::exit(admin::run(args.collect()));
+    }
+
+    if let Err(err) = rocket::execute(rocket().launch()) {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+}
+
+/// TLS, and mutual TLS with the certificates of the `clients`, are set up in `Rocket.toml`
+/// (see the README)
+fn rocket() -> rocket::Rocket<rocket::Build> {
+    let limits = Limits::default().limit("json", DEFAULT_JSON_LIMIT_MIB.mebibytes());
+    let rocket = rocket::custom(rocket::Config::figment().join(("limits", limits)));
+    let clients = Clients::from_config(rocket.figment());
+    let admin_token = AdminToken::from_config(rocket.figment());
+    let retention = Retention::from_config(rocket.figment());
+    let storage = Storage::from_config(rocket.figment());
+    let log_key = LogKey::from_config(rocket.figment());
+
+    let expiry = Expiry::from_config(rocket.figment());
+
+    let db_mtx = Arc::new(RwLock::new(storage.restore(retention.0)));
+    let storage = Arc::new(storage);
+    let changes = Arc::new(Changes {
+        changed: Notify::new(),
+    });
+
+    // Stalled operations are expired in the background, since their participants may all be
+    // gone
+    let (db, store, changed) = (db_mtx.clone(), storage.clone(), changes.clone());
+    let expire_stalled = AdHoc::on_liftoff("Expire stalled operations", move |_| {
+        Box::pin(async move {
+            rocket::tokio::spawn(async move {
+                let mut interval = interval(Duration::from_secs(EXPIRY_INTERVAL_SECS));
+                loop {
+                    interval.tick().await;
+                    expiry.expire(&db, &store, &changed);
+                }
+            });
+        })
+    });
 
-    rocket::ignite()
+    rocket
+        .attach(expire_stalled)
         .mount(
             "/",
             routes![
+                hello,
                 get,
+                get_batch,
                 set,
                 signup_keygen,
                 signup_sign,
+                signout,
+                rejoin,
+                publish_key,
+                lookup,
+                log_append,
+                log_head,
+                log_find,
+                log_entries,
+                log_proof,
                 start_operation,
                 end_operation,
+                operations,
+                history,
                 get_operation,
-                clear
+                await_operation,
+                blame,
+                clear,
+                admin_projects,
+                admin_sessions,
+                admin_abort,
+                admin_purge,
+                admin_export
             ],
         )
+        .register(
+            "/",
+            catchers![bad_request, forbidden, not_found, too_large, unprocessable],
+        )
         .manage(db_mtx)
-        .launch();
+        .manage(changes)
+        .manage(clients)
+        .manage(admin_token)
+        .manage(retention)
+        .manage(storage)
+        .manage(log_key)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PROJECT: &str = "project";
+    const SESSION: &str = "session";
+
+    fn sign_key(participants: u16, threshold: u16) -> Operation {
+        Operation::SignKey {
+            participants,
+            threshold,
+            leader: "leader".to_owned(),
+            email: "leader@example.com".to_owned(),
+            epoch: 0,
+        }
+    }
+
+    fn key_gen(participants: u16) -> Operation {
+        Operation::KeyGen {
+            participants,
+            leader: "leader".to_owned(),
+            email: "leader@example.com".to_owned(),
+            epoch: 0,
+        }
+    }
+
+    fn started(
+        operation: Operation,
+        initiator: Option<&str>,
+        roster: &[&str],
+    ) -> HashMap<String, Project> {
+        let mut projects = HashMap::new();
+        apply(
+            &mut projects,
+            Event::Start {
+                project: PROJECT.to_owned(),
+                session: SESSION.to_owned(),
+                operation,
+                after: None,
+                by: initiator.map(str::to_owned),
+                roster: roster.iter().map(|member| member.to_string()).collect(),
+            },
+        );
+        projects
+    }
+
+    fn session(projects: &HashMap<String, Project>) -> &Session {
+        &projects[PROJECT].sessions[SESSION]
+    }
+
+    fn signup(projects: &mut HashMap<String, Project>, party: Option<u16>, by: Option<&str>) {
+        let event = Event::Signup {
+            project: PROJECT.to_owned(),
+            session: SESSION.to_owned(),
+            party,
+            by: by.map(str::to_owned),
+        };
+        apply(projects, event);
+    }
+
+    fn send(projects: &mut HashMap<String, Project>, key: &str) {
+        let event = Event::Set {
+            project: PROJECT.to_owned(),
+            session: SESSION.to_owned(),
+            key: key.to_owned(),
+            value: "message".to_owned(),
+        };
+        apply(projects, event);
+    }
+
+    fn signout(projects: &mut HashMap<String, Project>, party: u16) {
+        let event = Event::Signout {
+            project: PROJECT.to_owned(),
+            session: SESSION.to_owned(),
+            party,
+        };
+        apply(projects, event);
+    }
+
+    fn end(projects: &mut HashMap<String, Project>, by: Option<&str>) {
+        let event = Event::End {
+            project: PROJECT.to_owned(),
+            session: SESSION.to_owned(),
+            at: SystemTime::now(),
+            by: by.map(str::to_owned),
+        };
+        apply(projects, event);
+    }
+
+    fn blame(projects: &mut HashMap<String, Project>, by: Option<&str>) {
+        let event = Event::Blame {
+            project: PROJECT.to_owned(),
+            session: SESSION.to_owned(),
+            by: by.map(str::to_owned),
+        };
+        apply(projects, event);
+    }
+
+    /// Store that fails to keep anything, like one on a full disk
+    struct Broken;
+
+    impl Store for Broken {
+        fn load(&self) -> io::Result<Vec<Event>> {
+            Ok(Vec::new())
+        }
+
+        fn record(&self, _event: &Event) -> io::Result<()> {
+            Err(io::Error::new(io::ErrorKind::WriteZero, "no space left"))
+        }
+
+        fn compact(&self, _events: &[Event]) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn leader_ends_at_once() {
+        let mut projects = started(sign_key(5, 2), Some("leader"), &[]);
+
+        end(&mut projects, Some("leader"));
+        assert_eq!(session(&projects).stage, Stage::Finalized);
+        assert!(session(&projects).ended.is_some());
+    }
+
+    #[test]
+    fn others_end_by_majority() {
+        let roster = ["first", "second", "third", "fourth", "fifth"];
+        let mut projects = started(sign_key(5, 2), Some("leader"), &roster);
+
+        end(&mut projects, Some("first"));
+        end(&mut projects, Some("second"));
+        // Voting twice does not count twice, and neither do votes to abort
+        end(&mut projects, Some("second"));
+        blame(&mut projects, Some("third"));
+        assert!(session(&projects).ended.is_none());
+        assert!(!session(&projects).is_aborted());
+
+        end(&mut projects, Some("third"));
+        assert_eq!(session(&projects).stage, Stage::Finalized);
+        assert!(session(&projects).ended.is_some());
+    }
+
+    #[test]
+    fn others_abort_by_majority() {
+        let mut projects = started(
+            sign_key(3, 1),
+            Some("leader"),
+            &["first", "second", "third"],
+        );
+
+        blame(&mut projects, Some("first"));
+        assert!(!session(&projects).is_aborted());
+        blame(&mut projects, Some("second"));
+        assert_eq!(
+            session(&projects).stage,
+            Stage::Aborted(AbortReason::Blamed)
+        );
+        // Aborted operations still wait for their leader to end them
+        assert!(session(&projects).ended.is_none());
+    }
+
+    #[test]
+    fn parties_abort_at_once() {
+        let mut projects = started(sign_key(5, 2), Some("leader"), &[]);
+        signup(&mut projects, None, Some("first"));
+
+        blame(&mut projects, Some("first"));
+        assert_eq!(
+            session(&projects).stage,
+            Stage::Aborted(AbortReason::Blamed)
+        );
+
+        // Roster members who voted to abort before signing up blame it as they do
+        let mut projects = started(sign_key(5, 2), Some("leader"), &["first", "second"]);
+        blame(&mut projects, Some("second"));
+        assert!(!session(&projects).is_aborted());
+        signup(&mut projects, None, Some("second"));
+        assert!(session(&projects).is_aborted());
+    }
+
+    #[test]
+    fn strangers_do_not_vote() {
+        let mut projects = started(sign_key(3, 1), Some("leader"), &["first"]);
+        signup(&mut projects, None, Some("first"));
+
+        let stranger = "stranger".to_owned();
+        assert_eq!(
+            session(&projects).votes(Some(&stranger)),
+            Err(Refusal::Unauthorized)
+        );
+        assert_eq!(session(&projects).votes(Some(&"first".to_owned())), Ok(()));
+        assert_eq!(session(&projects).votes(None), Ok(()));
+
+        // Not even once they make up a majority of the votes
+        for _ in 0..3 {
+            blame(&mut projects, Some("stranger"));
+            end(&mut projects, Some("stranger"));
+        }
+        assert_eq!(session(&projects).stage, Stage::CollectingSignups);
+        assert!(session(&projects).ended.is_none());
+    }
+
+    #[test]
+    fn anonymous_requests_carry() {
+        let mut projects = started(sign_key(5, 2), None, &[]);
+
+        blame(&mut projects, None);
+        assert!(session(&projects).is_aborted());
+        end(&mut projects, None);
+        assert!(session(&projects).ended.is_some());
+    }
+
+    #[test]
+    fn roster_places_the_key_shares() {
+        let mut projects = started(key_gen(3), Some("leader"), &["first", "second", "third"]);
+        let owned = |name: &str| Some(name.to_owned());
+
+        // Members get the number of their place, whatever they claim
+        signup(&mut projects, Some(1), Some("third"));
+        signup(&mut projects, None, Some("first"));
+        assert_eq!(session(&projects).parties[&3], owned("third"));
+        assert_eq!(session(&projects).parties[&1], owned("first"));
+
+        let client = "second".to_owned();
+        assert_eq!(session(&projects).assign(None, Some(&client)), Ok(2));
+        // Signing up again leaves them their number
+        let client = "third".to_owned();
+        assert_eq!(session(&projects).assign(Some(2), Some(&client)), Ok(3));
+
+        let stranger = "stranger".to_owned();
+        assert_eq!(
+            session(&projects).assign(None, Some(&stranger)),
+            Err(Refusal::Unlisted)
+        );
+    }
+
+    #[test]
+    fn parties_take_the_number_they_claim() {
+        let mut projects = started(sign_key(5, 2), Some("leader"), &[]);
+
+        signup(&mut projects, Some(2), Some("first"));
+        signup(&mut projects, None, Some("second"));
+        assert_eq!(
+            session(&projects)
+                .parties
+                .keys()
+                .copied()
+                .collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+
+        let by = "third".to_owned();
+        assert_eq!(session(&projects).assign(Some(3), Some(&by)), Ok(3));
+        assert_eq!(
+            session(&projects).assign(Some(2), Some(&by)),
+            Err(Refusal::Full)
+        );
+        assert!(matches!(
+            session(&projects).assign(Some(4), Some(&by)),
+            Err(Refusal::Invalid(_))
+        ));
+
+        signup(&mut projects, None, Some("third"));
+        let late = "late".to_owned();
+        assert_eq!(
+            session(&projects).assign(None, Some(&late)),
+            Err(Refusal::Full)
+        );
+    }
+
+    #[test]
+    fn runs_once_every_party_signed_up() {
+        let mut projects = started(sign_key(5, 1), Some("leader"), &[]);
+        assert_eq!(session(&projects).stage, Stage::Proposed);
+
+        signup(&mut projects, None, Some("first"));
+        assert_eq!(session(&projects).stage, Stage::CollectingSignups);
+        // The first party may send before the others show up
+        send(&mut projects, "1-sign-round0");
+        assert_eq!(session(&projects).stage, Stage::CollectingSignups);
+
+        signup(&mut projects, None, Some("second"));
+        send(&mut projects, "2-sign-round0");
+        assert_eq!(
+            session(&projects).stage,
+            Stage::Running("sign-round0".to_owned())
+        );
+
+        end(&mut projects, Some("leader"));
+        // Late messages do not bring ended operations back
+        send(&mut projects, "1-sign-round1");
+        assert_eq!(session(&projects).stage, Stage::Finalized);
+    }
+
+    #[test]
+    fn stalls_by_the_limit_of_its_stage() {
+        let mut projects = started(sign_key(5, 1), Some("leader"), &[]);
+        let now = Some(Duration::from_secs(0));
+        let never = Some(Duration::from_secs(3600));
+        let at_signups = Expiry {
+            signups: now,
+            rounds: never,
+        };
+        let at_rounds = Expiry {
+            signups: never,
+            rounds: now,
+        };
+        let off = Expiry {
+            signups: None,
+            rounds: None,
+        };
+
+        assert!(session(&projects).stalled(&at_signups));
+        assert!(!session(&projects).stalled(&at_rounds));
+        assert!(!session(&projects).stalled(&off));
+
+        signup(&mut projects, None, Some("first"));
+        signup(&mut projects, None, Some("second"));
+        send(&mut projects, "1-sign-round0");
+        assert!(!session(&projects).stalled(&at_signups));
+        assert!(session(&projects).stalled(&at_rounds));
+
+        // Aborted operations wait as long for their leader as running ones for their parties
+        let mut aborted = started(sign_key(5, 1), Some("leader"), &[]);
+        blame(&mut aborted, Some("leader"));
+        assert!(session(&aborted).stalled(&at_rounds));
+
+        end(&mut projects, Some("leader"));
+        assert_eq!(session(&projects).stage, Stage::Finalized);
+        assert!(!session(&projects).stalled(&at_signups));
+        assert!(!session(&projects).stalled(&at_rounds));
+    }
+
+    #[test]
+    fn expires_stalled_operations() {
+        let db: Projects = RwLock::new(started(sign_key(5, 1), Some("leader"), &[]));
+        let storage = Storage {
+            store: Box::new(Memory),
+            audit: Audit(None),
+        };
+        let changes = Changes {
+            changed: Notify::new(),
+        };
+
+        let patient = Expiry {
+            signups: Some(Duration::from_secs(3600)),
+            rounds: None,
+        };
+        patient.expire(&db, &storage, &changes);
+        assert_eq!(session(&db.read().unwrap()).stage, Stage::Proposed);
+
+        let impatient = Expiry {
+            signups: Some(Duration::from_secs(0)),
+            rounds: None,
+        };
+        impatient.expire(&db, &storage, &changes);
+        let projects = db.read().unwrap();
+        assert_eq!(
+            session(&projects).stage,
+            Stage::Aborted(AbortReason::Expired)
+        );
+        assert!(session(&projects).ended.is_some());
+    }
+
+    #[test]
+    fn signout_frees_the_number_and_its_messages() {
+        let mut projects = started(sign_key(5, 2), Some("leader"), &[]);
+        signup(&mut projects, None, Some("first"));
+        signup(&mut projects, None, Some("second"));
+        send(&mut projects, "1-sign-round0");
+        send(&mut projects, "2-sign-round0");
+
+        signout(&mut projects, 1);
+        let cached: Vec<&String> = session(&projects).cache.keys().collect();
+        assert_eq!(cached, vec!["2-sign-round0"]);
+        assert!(!session(&projects).parties.contains_key(&1));
+
+        // The number goes to whoever signs up next, who sends their own messages
+        signup(&mut projects, None, Some("third"));
+        assert_eq!(session(&projects).parties[&1], Some("third".to_owned()));
+    }
+
+    #[test]
+    fn only_the_holder_rejoins() {
+        let mut projects = started(sign_key(5, 2), Some("leader"), &[]);
+        signup(&mut projects, None, Some("first"));
+        signup(&mut projects, None, Some("second"));
+
+        let first = "first".to_owned();
+        let second = "second".to_owned();
+        assert_eq!(session(&projects).holds(1, Some(&first)), Ok(()));
+        assert!(matches!(
+            session(&projects).holds(1, Some(&second)),
+            Err(Refusal::Invalid(_))
+        ));
+        assert!(matches!(
+            session(&projects).holds(3, Some(&first)),
+            Err(Refusal::Invalid(_))
+        ));
+
+        // Signing up again after a crash also leaves the holder their number
+        signup(&mut projects, None, Some("second"));
+        assert_eq!(session(&projects).parties.len(), 2);
+        assert_eq!(session(&projects).parties[&2], Some(second));
+    }
+
+    #[test]
+    fn anonymous_parties_are_not_told_apart() {
+        let mut projects = started(sign_key(5, 2), None, &[]);
+        signup(&mut projects, None, None);
+
+        assert_eq!(session(&projects).holds(1, None), Ok(()));
+        let client = "client".to_owned();
+        assert!(session(&projects).holds(1, Some(&client)).is_err());
+    }
+
+    #[test]
+    fn parties_log_what_was_finalized() {
+        let mut projects = started(sign_key(5, 2), Some("leader"), &[]);
+        signup(&mut projects, None, Some("leader"));
+        signup(&mut projects, None, Some("first"));
+
+        let leader = "leader".to_owned();
+        let kind = EntryKind::KeySignature;
+        assert!(matches!(
+            session(&projects).loggable(kind, Some(&leader)),
+            Err(Refusal::Invalid(_))
+        ));
+
+        end(&mut projects, Some("leader"));
+        assert_eq!(session(&projects).loggable(kind, Some(&leader)), Ok(()));
+        let first = "first".to_owned();
+        assert_eq!(session(&projects).loggable(kind, Some(&first)), Ok(()));
+        let outsider = "outsider".to_owned();
+        assert_eq!(
+            session(&projects).loggable(kind, Some(&outsider)),
+            Err(Refusal::Unauthorized)
+        );
+        assert_eq!(
+            session(&projects).loggable(kind, None),
+            Err(Refusal::Unauthorized)
+        );
+        assert_eq!(
+            session(&projects).loggable(EntryKind::TagSignature, Some(&leader)),
+            Err(Refusal::WrongOperation)
+        );
+
+        let mut projects = started(sign_key(5, 2), Some("leader"), &[]);
+        signup(&mut projects, None, Some("leader"));
+        blame(&mut projects, Some("leader"));
+        end(&mut projects, Some("leader"));
+        assert!(matches!(
+            session(&projects).loggable(kind, Some(&leader)),
+            Err(Refusal::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn refuses_changes_it_cannot_record() {
+        let storage = Storage {
+            store: Box::new(Broken),
+            audit: Audit(None),
+        };
+        let mut projects = started(sign_key(5, 2), Some("leader"), &[]);
+
+        let event = Event::End {
+            project: PROJECT.to_owned(),
+            session: SESSION.to_owned(),
+            at: SystemTime::now(),
+            by: Some("leader".to_owned()),
+        };
+        assert_eq!(
+            storage.commit(&mut projects, event),
+            Err(Refusal::Unavailable)
+        );
+        assert!(session(&projects).ended.is_none());
+    }
+
+    #[test]
+    fn messages_are_never_replaced() {
+        let mut projects = started(sign_key(5, 2), Some("leader"), &[]);
+        assert_eq!(
+            session(&projects).takes("1-sign-round0", "message"),
+            Ok(true)
+        );
+
+        send(&mut projects, "1-sign-round0");
+        assert_eq!(
+            session(&projects).takes("1-sign-round0", "message"),
+            Ok(false)
+        );
+        assert_eq!(
+            session(&projects).takes("1-sign-round0", "another message"),
+            Err(Refusal::Conflict)
+        );
+        assert_eq!(Refusal::Conflict.status(), 409);
+    }
+
+    #[test]
+    fn tokens_are_matched_by_their_digests() {
+        let client = [("token", "secret"), ("identity", "alice")]
+            .iter()
+            .cloned()
+            .collect::<HashMap<_, _>>();
+        let figment = Figment::new()
+            .merge(("clients", vec![client]))
+            .merge(("admin_token", "operator"));
+
+        let clients = Clients::from_config(&figment);
+        assert_eq!(clients.bearer("secret"), Some(&"alice".to_owned()));
+        assert_eq!(clients.bearer("secreT"), None);
+        assert!(!clients.tokens.contains_key("secret"));
+
+        let admin = AdminToken::from_config(&figment);
+        assert_eq!(admin.0, Some(sha256_hex(b"operator")));
+    }
+
+    #[test]
+    fn digests_are_sha256_hashes() {
+        assert!(check_digest(&sha256_hex(b"tag")).is_ok());
+        assert!(check_digest(&sha256_hex(b"tag").to_uppercase()).is_ok());
+        assert!(check_digest("").is_err());
+        assert!(check_digest(&sha256_hex(b"tag")[1..]).is_err());
+        assert!(check_digest(&"z".repeat(64)).is_err());
+    }
 }
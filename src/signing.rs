@@ -15,49 +15,72 @@ use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2020::orchestrate::*;
 use multi_party_ecdsa::utilities::mta::{MessageA, MessageB};
 
 use paillier::*;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::comm::Channel;
+use crate::encryption;
+use crate::errors::ProtocolError;
+use crate::wire::{self, Session};
+
+/// Run a stage that draws fresh randomness only once per operation. Transports that resume
+/// the protocol across invocations rerun it from the start, and have to send the same
+/// messages as the first time around.
+pub(crate) fn checkpoint<C, T, F>(channel: &C, stage: &str, run: F) -> Result<T, ProtocolError>
+where
+    C: Channel + ?Sized,
+    T: Serialize + DeserializeOwned,
+    F: FnOnce() -> Result<T, ProtocolError>,
+{
+    if let Some(stored) = channel.load_stage(stage) {
+        if let Ok(result) = serde_json::from_str(&stored) {
+            return Ok(result);
+        }
+    }
 
-use crate::comm::HTTPChannel;
+    let result = run()?;
+    channel.store_stage(stage, serde_json::to_string(&result).unwrap())?;
+    Ok(result)
+}
 
-pub fn distributed_sign(
-    channel: &HTTPChannel,
+pub fn distributed_sign<C: Channel + ?Sized>(
+    channel: &C,
     message: &[u8],
     keypair: &PartyKeyPair,
     participants: u16,
     threshold: u16,
-) -> Result<SignatureRecid, ()> {
+) -> Result<SignatureRecid, ProtocolError> {
     let params = Parameters {
         threshold,
         share_count: participants,
     };
 
-    let party_num_int = match channel.signup_sign() {
-        Ok(index) => index,
-        Err(_) => return Err(()),
-    };
-
-    channel
-        .broadcast(
-            party_num_int,
-            "sign-round0",
-            serde_json::to_string(&keypair.party_num_int).unwrap(),
-        )
-        .unwrap();
-
-    let round0_ans_vec =
-        channel.poll_for_broadcasts(party_num_int, params.threshold + 1, "sign-round0");
-
-    let mut j = 0;
-    //0 indexed vec containing ids of the signing parties.
-    let mut signers_vec: Vec<usize> = Vec::new();
-    for i in 1..=params.threshold + 1 {
-        if i == party_num_int {
-            signers_vec.push((keypair.party_num_int - 1) as usize);
-        } else {
-            let signer_j: u16 = serde_json::from_str(&round0_ans_vec[j]).unwrap();
-            signers_vec.push((signer_j - 1) as usize);
-            j += 1;
-        }
+    let party_num_int = channel.signup_sign()?;
+    let session = Session::join(channel, party_num_int, params.threshold + 1, "sign")?;
+
+    let mut signers: Vec<u16> = session.broadcast("sign-round0", &keypair.party_num_int)?;
+    signers.insert(party_num_int as usize - 1, keypair.party_num_int);
+    // Key share numbers index into the key, so anything out of range is refused
+    if let Some(party) = (1..=params.threshold + 1).find(|&i| {
+        let signer = signers[i as usize - 1];
+        signer == 0 || signer > params.share_count
+    }) {
+        let _ = channel.blame();
+        return Err(ProtocolError::Malformed(party, "sign-round0".to_owned()));
     }
+    // Members of the roster generated the key share of their place in it, and sign with no other
+    if let Some(party) = (1..=params.threshold + 1).find(|&i| {
+        channel
+            .member(i)
+            .map_or(false, |place| place != signers[i as usize - 1])
+    }) {
+        let _ = channel.blame();
+        return Err(ProtocolError::Authentication(
+            party,
+            "sign-round0".to_owned(),
+        ));
+    }
+    //0 indexed vec containing ids of the signing parties.
+    let signers_vec: Vec<usize> = signers.iter().map(|&signer| signer as usize - 1).collect();
     let input_stage1 = SignStage1Input {
         vss_scheme: keypair.vss_scheme_vec[signers_vec[(party_num_int - 1) as usize]].clone(),
         index: signers_vec[(party_num_int - 1) as usize],
@@ -66,23 +89,16 @@ pub fn distributed_sign(
         shared_keys: keypair.shared_keys.clone(),
     };
 
-    let res_stage1 = sign_stage1(&input_stage1);
+    let res_stage1 = checkpoint(channel, "sign-stage1", || Ok(sign_stage1(&input_stage1)))?;
 
-    channel
-        .broadcast(
-            party_num_int,
-            "sign-round1",
-            serde_json::to_string(&(
-                res_stage1.bc1.clone(),
-                res_stage1.m_a.0.clone(),
-                res_stage1.sign_keys.g_w_i,
-            ))
-            .unwrap(),
-        )
-        .unwrap();
-
-    let round1_ans_vec =
-        channel.poll_for_broadcasts(party_num_int, params.threshold + 1, "sign-round1");
+    let round1_ans_vec: Vec<(SignBroadcastPhase1, MessageA, GE)> = session.broadcast(
+        "sign-round1",
+        &(
+            res_stage1.bc1.clone(),
+            res_stage1.m_a.0.clone(),
+            res_stage1.sign_keys.g_w_i,
+        ),
+    )?;
 
     let mut j = 0;
     let mut bc1_vec: Vec<SignBroadcastPhase1> = Vec::new();
@@ -95,8 +111,7 @@ pub fn distributed_sign(
             g_w_i_vec.push(res_stage1.sign_keys.g_w_i.clone());
             m_a_vec.push(res_stage1.m_a.0.clone());
         } else {
-            let (bc1_j, m_a_party_j, g_w_i): (SignBroadcastPhase1, MessageA, GE) =
-                serde_json::from_str(&round1_ans_vec[j]).unwrap();
+            let (bc1_j, m_a_party_j, g_w_i) = round1_ans_vec[j].clone();
             bc1_vec.push(bc1_j);
             g_w_i_vec.push(g_w_i);
             m_a_vec.push(m_a_party_j);
@@ -115,9 +130,15 @@ pub fn distributed_sign(
         l_s: signers_vec.clone(),
     };
 
+    // Messages between signers are encrypted under keys agreed on with their long-term keys
+    let u_i = keypair.party_keys.u_i;
+    let public_key = |i: u16| keypair.vss_scheme_vec[signers_vec[(i - 1) as usize]].commitments[0];
+
     let mut beta_vec: Vec<FE> = vec![];
     let mut ni_vec: Vec<FE> = vec![];
-    let res_stage2 = sign_stage2(&input_stage2).expect("sign stage2 failed.");
+    let res_stage2 = checkpoint(channel, "sign-stage2", || {
+        sign_stage2(&input_stage2).map_err(|_| channel.abort())
+    })?;
     // Send out MessageB, beta, ni to other signers so that they can calculate there alpha values.
     let mut j = 0;
     for i in 1..params.threshold + 2 {
@@ -130,28 +151,38 @@ pub fn distributed_sign(
             let c_b_messageb_gammai = res_stage2.gamma_i_vec[j].0.clone();
             let c_b_messageb_wi = res_stage2.w_i_vec[j].0.clone();
 
-            channel
-                .sendp2p(
-                    party_num_int,
-                    i,
-                    "sign-round2",
-                    serde_json::to_string(&(c_b_messageb_gammai, c_b_messageb_wi)).unwrap(),
-                )
-                .unwrap();
+            // They do carry proofs about our secrets though, which only the recipient needs
+            let key = encryption::shared_key(u_i, public_key(i));
+            session.send_p2p(
+                i,
+                "sign-round2",
+                encryption::seal(
+                    &key,
+                    &encryption::context("sign-round2", party_num_int, i),
+                    &wire::to_payload(&(c_b_messageb_gammai, c_b_messageb_wi)),
+                ),
+            )?;
 
             j += 1;
         }
     }
 
-    let round2_ans_vec = channel.poll_for_p2p(party_num_int, params.threshold + 1, "sign-round2");
+    let round2_ans_vec = session.poll_for_p2p("sign-round2")?;
 
     let mut m_b_gamma_rec_vec: Vec<MessageB> = Vec::new();
     let mut m_b_w_rec_vec: Vec<MessageB> = Vec::new();
 
-    // TODO Can safely replace this
-    for i in 0..params.threshold {
+    let senders = (1..params.threshold + 2).filter(|&i| i != party_num_int);
+    for (i, message) in senders.zip(round2_ans_vec.iter()) {
+        let key = encryption::shared_key(u_i, public_key(i));
+        let context = encryption::context("sign-round2", i, party_num_int);
+        let message = encryption::open(&key, &context, message).ok_or_else(|| {
+            let _ = channel.blame();
+            ProtocolError::Decryption(i, "sign-round2".to_owned())
+        })?;
+
         let (l_mb_gamma, l_mb_w): (MessageB, MessageB) =
-            serde_json::from_str(&round2_ans_vec[i as usize]).unwrap();
+            session.read(i, "sign-round2", &message)?;
         m_b_gamma_rec_vec.push(l_mb_gamma);
         m_b_w_rec_vec.push(l_mb_w);
     }
@@ -166,7 +197,7 @@ pub fn distributed_sign(
         g_w_i_s: g_w_i_vec.clone(),
     };
 
-    let res_stage3 = sign_stage3(&input_stage3).expect("Sign stage 3 failed.");
+    let res_stage3 = sign_stage3(&input_stage3).map_err(|_| channel.abort())?;
     let mut alpha_vec = vec![];
     let mut miu_vec = vec![];
     // Send out alpha, miu to other signers.
@@ -187,18 +218,12 @@ pub fn distributed_sign(
         sign_keys_s: res_stage1.sign_keys.clone(),
     };
 
-    let res_stage4 = sign_stage4(&input_stage4).expect("Sign Stage4 failed.");
+    let res_stage4 = sign_stage4(&input_stage4).map_err(|_| channel.abort())?;
     //broadcast decommitment from stage1 and delta_i
-    channel
-        .broadcast(
-            party_num_int,
-            "sign-round4",
-            serde_json::to_string(&(res_stage1.decom1.clone(), res_stage4.delta_i)).unwrap(),
-        )
-        .unwrap();
-
-    let round4_ans_vec =
-        channel.poll_for_broadcasts(party_num_int, params.threshold + 1, "sign-round4");
+    let round4_ans_vec: Vec<(SignDecommitPhase1, FE)> = session.broadcast(
+        "sign-round4",
+        &(res_stage1.decom1.clone(), res_stage4.delta_i),
+    )?;
 
     let mut delta_i_vec = vec![];
     let mut decom1_vec = vec![];
@@ -208,8 +233,7 @@ pub fn distributed_sign(
             delta_i_vec.push(res_stage4.delta_i);
             decom1_vec.push(res_stage1.decom1.clone());
         } else {
-            let (decom_l, delta_l): (SignDecommitPhase1, FE) =
-                serde_json::from_str(&round4_ans_vec[j]).unwrap();
+            let (decom_l, delta_l) = round4_ans_vec[j].clone();
             delta_i_vec.push(delta_l);
             decom1_vec.push(decom_l);
             j += 1;
@@ -227,17 +251,9 @@ pub fn distributed_sign(
         sign_keys: res_stage1.sign_keys.clone(),
         s_ttag: signers_vec.len(),
     };
-    let res_stage5 = sign_stage5(&input_stage5).expect("Sign Stage 5 failed.");
-    channel
-        .broadcast(
-            party_num_int,
-            "sign-round5",
-            serde_json::to_string(&(res_stage5.R_dash, res_stage5.R)).unwrap(),
-        )
-        .unwrap();
-
-    let round5_ans_vec =
-        channel.poll_for_broadcasts(party_num_int, params.threshold + 1, "sign-round5");
+    let res_stage5 = sign_stage5(&input_stage5).map_err(|_| channel.abort())?;
+    let round5_ans_vec: Vec<(GE, GE)> =
+        session.broadcast("sign-round5", &(res_stage5.R_dash, res_stage5.R))?;
 
     let mut Rs = vec![];
     let mut R_dash_vec = vec![];
@@ -247,7 +263,7 @@ pub fn distributed_sign(
             Rs.push(res_stage5.R);
             R_dash_vec.push(res_stage5.R_dash);
         } else {
-            let (R_dash, R): (GE, GE) = serde_json::from_str(&round5_ans_vec[j]).unwrap();
+            let (R_dash, R) = round5_ans_vec[j];
             Rs.push(R);
             R_dash_vec.push(R_dash);
             j += 1;
@@ -273,17 +289,11 @@ pub fn distributed_sign(
         message_bn: message.clone(),
     };
 
-    let res_stage6 = sign_stage6(&input_stage6).expect("stage6 sign failed.");
-    channel
-        .broadcast(
-            party_num_int,
-            "sign-round6",
-            serde_json::to_string(&res_stage6.local_sig).unwrap(),
-        )
-        .unwrap();
-
-    let round6_ans_vec =
-        channel.poll_for_broadcasts(party_num_int, params.threshold + 1, "sign-round6");
+    let res_stage6 = checkpoint(channel, "sign-stage6", || {
+        sign_stage6(&input_stage6).map_err(|_| channel.abort())
+    })?;
+    let round6_ans_vec: Vec<LocalSignature> =
+        session.broadcast("sign-round6", &res_stage6.local_sig)?;
 
     let mut local_sig_vec = vec![];
     let mut j = 0;
@@ -291,8 +301,7 @@ pub fn distributed_sign(
         if i == party_num_int {
             local_sig_vec.push(res_stage6.local_sig.clone());
         } else {
-            let local_sig: LocalSignature = serde_json::from_str(&round6_ans_vec[j]).unwrap();
-            local_sig_vec.push(local_sig.clone());
+            local_sig_vec.push(round6_ans_vec[j].clone());
             j += 1;
         }
     }
@@ -302,7 +311,7 @@ pub fn distributed_sign(
         ysum: keypair.y_sum.clone(),
     };
 
-    let res_stage7 = sign_stage7(&input_stage7).expect("sign stage 7 failed");
+    let res_stage7 = sign_stage7(&input_stage7).map_err(|_| channel.abort())?;
 
     Ok(res_stage7.local_sig)
 }
@@ -0,0 +1,274 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use dit::comm::Key;
+use dit::transparency::LogEntry;
+use dit::utils::Operation;
+
+/// Change to the state of `dit-server`. Stores record these as they happen, and the state is
+/// restored after a restart by applying them again in order. Changes are made `by` the identity
+/// of a client, when the server knows it.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Event {
+    Start {
+        project: String,
+        session: String,
+        operation: Operation,
+        after: Option<String>,
+        by: Option<String>,
+        #[serde(default)]
+        roster: Vec<String>,
+    },
+    Signup {
+        project: String,
+        session: String,
+        party: Option<u16>,
+        by: Option<String>,
+    },
+    /// Giving up a party number before every party signed up
+    Signout {
+        project: String,
+        session: String,
+        party: u16,
+    },
+    Set {
+        project: String,
+        session: String,
+        key: Key,
+        value: String,
+    },
+    Blame {
+        project: String,
+        session: String,
+        by: Option<String>,
+    },
+    End {
+        project: String,
+        session: String,
+        at: SystemTime,
+        by: Option<String>,
+    },
+    /// Aborting and ending the operation at once, by an operator through the admin API
+    Abort {
+        project: String,
+        session: String,
+        at: SystemTime,
+    },
+    /// Aborting and ending the operation at once, as it stalled for too long
+    Expire {
+        project: String,
+        session: String,
+        at: SystemTime,
+    },
+    /// Dropping the sessions archived `before`, by an operator through the admin API
+    Purge { project: String, before: SystemTime },
+    /// OpenPGP packets in base64, to be merged with the certificates of the project
+    Publish {
+        project: String,
+        certificate: String,
+        by: Option<String>,
+    },
+    /// Entry of the transparency log of the project
+    Append {
+        project: String,
+        entry: LogEntry,
+        by: Option<String>,
+    },
+}
+
+/// Where the server keeps the events that make up its state
+pub trait Store: Send + Sync {
+    /// Every event recorded so far, in order
+    fn load(&self) -> io::Result<Vec<Event>>;
+    fn record(&self, event: &Event) -> io::Result<()>;
+    /// Replace everything recorded so far with `events`, which lead to the current state
+    fn compact(&self, events: &[Event]) -> io::Result<()>;
+}
+
+/// Keeps nothing, so the state is lost with the server
+pub struct Memory;
+
+impl Store for Memory {
+    fn load(&self) -> io::Result<Vec<Event>> {
+        Ok(Vec::new())
+    }
+
+    fn record(&self, _event: &Event) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn compact(&self, _events: &[Event]) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Appends the events to a file, one JSON object per line
+pub struct Log {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl Log {
+    pub fn open(path: PathBuf) -> io::Result<Log> {
+        let file = Log::append(&path)?;
+
+        Ok(Log {
+            path,
+            file: Mutex::new(file),
+        })
+    }
+
+    fn append(path: &Path) -> io::Result<File> {
+        OpenOptions::new().create(true).append(true).open(path)
+    }
+}
+
+impl Store for Log {
+    fn load(&self) -> io::Result<Vec<Event>> {
+        let contents = fs::read(&self.path)?;
+        let mut lines: Vec<&[u8]> = contents.split(|&b| b == b'\n').collect();
+        // Events are written along with their newline, so anything after the last one is an
+        // event cut short by a crash, which was never recorded and compacting gets rid of.
+        // Every other line has to hold an event.
+        lines.pop();
+
+        lines
+            .iter()
+            .enumerate()
+            .map(|(number, line)| {
+                serde_json::from_slice(line).map_err(|err| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("line {} of {}: {}", number + 1, self.path.display(), err),
+                    )
+                })
+            })
+            .collect()
+    }
+
+    fn record(&self, event: &Event) -> io::Result<()> {
+        let mut line = serde_json::to_vec(event)?;
+        line.push(b'\n');
+
+        let mut file = self.file.lock().unwrap();
+        let length = file.metadata()?.len();
+        let written = file.write_all(&line).and_then(|_| file.sync_data());
+        if written.is_err() {
+            // Leave no partial line behind for the next event to be appended to
+            let _ = file.set_len(length);
+        }
+        written
+    }
+
+    fn compact(&self, events: &[Event]) -> io::Result<()> {
+        let mut file = self.file.lock().unwrap();
+
+        let compacted = self.path.with_extension("compacted");
+        let mut new = File::create(&compacted)?;
+        for event in events {
+            serde_json::to_writer(&mut new, event)?;
+            new.write_all(b"\n")?;
+        }
+        new.sync_all()?;
+        fs::rename(&compacted, &self.path)?;
+
+        *file = Log::append(&self.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::temp_dir;
+
+    fn events() -> Vec<Event> {
+        vec![
+            Event::Start {
+                project: "project".into(),
+                session: "session".into(),
+                operation: Operation::SignKey {
+                    participants: 3,
+                    threshold: 1,
+                    leader: "leader".into(),
+                    email: "leader@example.com".into(),
+                    epoch: 0,
+                },
+                after: None,
+                by: Some("leader".into()),
+                roster: vec!["leader".into(), "member".into()],
+            },
+            Event::Signup {
+                project: "project".into(),
+                session: "session".into(),
+                party: Some(1),
+                by: Some("leader".into()),
+            },
+            Event::Set {
+                project: "project".into(),
+                session: "session".into(),
+                key: "1-sign-round0".into(),
+                value: "message".into(),
+            },
+            Event::End {
+                project: "project".into(),
+                session: "session".into(),
+                at: SystemTime::now(),
+                by: Some("leader".into()),
+            },
+        ]
+    }
+
+    #[test]
+    fn restores_what_it_recorded() {
+        let dir = temp_dir("store-round-trip");
+        let path = dir.join("events");
+        let events = events();
+
+        let log = Log::open(path.clone()).unwrap();
+        for event in events.iter() {
+            log.record(event).unwrap();
+        }
+        assert_eq!(Log::open(path.clone()).unwrap().load().unwrap(), events);
+
+        // Compacting replaces the log, which takes new events as before
+        log.compact(&events[..1]).unwrap();
+        log.record(&events[3]).unwrap();
+        let restored = Log::open(path.clone()).unwrap().load().unwrap();
+        assert_eq!(restored, vec![events[0].clone(), events[3].clone()]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn tolerates_only_a_torn_last_line() {
+        let dir = temp_dir("store-torn");
+        let path = dir.join("events");
+        let events = events();
+        let lines: Vec<String> = events
+            .iter()
+            .map(|event| serde_json::to_string(event).unwrap())
+            .collect();
+
+        // A crash in the middle of writing the last event
+        let torn = format!("{}\n{}\n{}", lines[0], lines[1], &lines[2][..10]);
+        fs::write(&path, torn).unwrap();
+        assert_eq!(
+            Log::open(path.clone()).unwrap().load().unwrap(),
+            events[..2]
+        );
+
+        // Anything else is not something a crash leaves behind
+        let corrupt = format!("{}\n{}\n{}\n", lines[0], &lines[1][..10], lines[2]);
+        fs::write(&path, corrupt).unwrap();
+        let err = Log::open(path.clone()).unwrap().load().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
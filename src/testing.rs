@@ -0,0 +1,13 @@
+//! Fixtures the tests of several modules share
+
+use std::fs;
+use std::path::PathBuf;
+
+/// Fresh, empty directory for the test `name`, under the temporary directory of the system.
+/// Its name includes the process, so that concurrent runs do not get in each other's way.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("dit-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
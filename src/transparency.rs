@@ -0,0 +1,407 @@
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use serde::{Deserialize, Serialize};
+
+use crate::identity::{self, Identity};
+use crate::utils::{from_hex, to_hex};
+
+pub type Hash = [u8; 32];
+
+/// Completed operation a transparency log entry records
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum EntryKind {
+    KeyGen,
+    KeySignature,
+    TagSignature,
+    Attestation,
+    Revocation,
+}
+
+/// Entry of the append-only log the server keeps of the signing events of a project, so that
+/// a release cannot be signed without leaving a public record
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LogEntry {
+    pub kind: EntryKind,
+    /// SHA-256 digest of what was signed, in hex. For key generations it is the digest of the
+    /// generated public key, and for revocations the one of the revocation signature.
+    pub digest: String,
+    /// Identity keys of the participants of the operation when the server knows them, and
+    /// their party numbers otherwise. Revocations list the fingerprint of the revoked key.
+    pub signers: Vec<String>,
+    /// When the server logged the entry, in seconds since the epoch
+    pub timestamp: u64,
+}
+
+/// Request to log the outcome of the operation of `session`, which the server adds the
+/// signers and the time to
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LogAppend {
+    pub session: String,
+    pub kind: EntryKind,
+    pub digest: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum ProofRequest {
+    /// Proof that entry `index` is part of the tree of the first `size` entries
+    Inclusion { index: u64, size: u64 },
+    /// Proof that the tree of the first `first` entries is a prefix of the one of `second`
+    Consistency { first: u64, second: u64 },
+}
+
+/// Root of the log of `project` over its first `size` entries, as of `timestamp`, signed by
+/// the key of the log
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SignedTreeHead {
+    pub project: String,
+    pub size: u64,
+    pub root: String,
+    pub timestamp: u64,
+    pub key: String,
+    pub signature: String,
+}
+
+impl SignedTreeHead {
+    pub fn new(
+        project: &str,
+        size: u64,
+        root: &Hash,
+        timestamp: u64,
+        log_key: &Identity,
+    ) -> SignedTreeHead {
+        let root = to_hex(root);
+        let signature = log_key.sign(&tree_head_bytes(project, size, &root, timestamp));
+
+        SignedTreeHead {
+            project: project.to_owned(),
+            size,
+            root,
+            timestamp,
+            key: log_key.public_key(),
+            signature,
+        }
+    }
+
+    /// Whether the tree head is signed by the key it names, which the caller still has to
+    /// check is the key of the log
+    pub fn verify(&self) -> bool {
+        let signed = tree_head_bytes(&self.project, self.size, &self.root, self.timestamp);
+        identity::verify(&self.key, &signed, &self.signature)
+    }
+
+    pub fn root(&self) -> Option<Hash> {
+        to_hash(&self.root)
+    }
+}
+
+fn tree_head_bytes(project: &str, size: u64, root: &str, timestamp: u64) -> Vec<u8> {
+    format!(
+        "dit-tree-head\n{}\n{}\n{}\n{}\n",
+        project, size, root, timestamp
+    )
+    .into_bytes()
+}
+
+pub fn to_hash(hex: &str) -> Option<Hash> {
+    let bytes = from_hex(hex)?;
+    let mut hash = [0u8; 32];
+    if bytes.len() != hash.len() {
+        return None;
+    }
+
+    hash.copy_from_slice(&bytes);
+    Some(hash)
+}
+
+fn sha256(parts: &[&[u8]]) -> Hash {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.input(part);
+    }
+
+    let mut hash = [0u8; 32];
+    hasher.result(&mut hash);
+    hash
+}
+
+/// Hash of an entry as a leaf of the tree, over its JSON encoding (Section 2.1 of RFC 6962)
+pub fn leaf_hash(entry: &LogEntry) -> Hash {
+    let encoded = serde_json::to_vec(entry).expect("Entries are always serializable");
+    sha256(&[&[0x00], &encoded])
+}
+
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    sha256(&[&[0x01], left, right])
+}
+
+/// Largest power of two smaller than `n`, which is where the tree of `n` leaves splits
+fn split(n: usize) -> usize {
+    let mut k = 1;
+    while k << 1 < n {
+        k <<= 1;
+    }
+    k
+}
+
+fn merkle_root(leaves: &[Hash]) -> Hash {
+    match leaves.len() {
+        0 => sha256(&[]),
+        1 => leaves[0],
+        n => {
+            let k = split(n);
+            node_hash(&merkle_root(&leaves[..k]), &merkle_root(&leaves[k..]))
+        }
+    }
+}
+
+fn audit_path(m: usize, leaves: &[Hash]) -> Vec<Hash> {
+    let n = leaves.len();
+    if n <= 1 {
+        return Vec::new();
+    }
+
+    let k = split(n);
+    let (mut path, sibling) = if m < k {
+        (audit_path(m, &leaves[..k]), merkle_root(&leaves[k..]))
+    } else {
+        (audit_path(m - k, &leaves[k..]), merkle_root(&leaves[..k]))
+    };
+    path.push(sibling);
+    path
+}
+
+fn subproof(m: usize, leaves: &[Hash], whole: bool) -> Vec<Hash> {
+    let n = leaves.len();
+    if m == n {
+        return if whole {
+            Vec::new()
+        } else {
+            vec![merkle_root(leaves)]
+        };
+    }
+
+    let k = split(n);
+    let (mut proof, sibling) = if m <= k {
+        (subproof(m, &leaves[..k], whole), merkle_root(&leaves[k..]))
+    } else {
+        (
+            subproof(m - k, &leaves[k..], false),
+            merkle_root(&leaves[..k]),
+        )
+    };
+    proof.push(sibling);
+    proof
+}
+
+/// Merkle tree over the entries of a log. Logs of signing events stay small, so roots and
+/// proofs are computed from the leaves whenever they are asked for.
+#[derive(Clone, Debug, Default)]
+pub struct Tree {
+    leaves: Vec<Hash>,
+}
+
+impl Tree {
+    pub fn push(&mut self, entry: &LogEntry) {
+        self.leaves.push(leaf_hash(entry));
+    }
+
+    pub fn size(&self) -> u64 {
+        self.leaves.len() as u64
+    }
+
+    /// Root of the tree of the first `size` entries
+    pub fn root(&self, size: u64) -> Option<Hash> {
+        Some(merkle_root(self.leaves.get(..size as usize)?))
+    }
+
+    pub fn inclusion(&self, index: u64, size: u64) -> Option<Vec<Hash>> {
+        let leaves = self.leaves.get(..size as usize)?;
+        if index >= size {
+            return None;
+        }
+
+        Some(audit_path(index as usize, leaves))
+    }
+
+    pub fn consistency(&self, first: u64, second: u64) -> Option<Vec<Hash>> {
+        let leaves = self.leaves.get(..second as usize)?;
+        match first {
+            0 => Some(Vec::new()),
+            first if first > second => None,
+            first => Some(subproof(first as usize, leaves, true)),
+        }
+    }
+}
+
+/// Check that `leaf` is entry `index` of the tree of `size` entries with the given `root`
+/// (Section 2.1.3.2 of RFC 9162)
+pub fn verify_inclusion(leaf: &Hash, index: u64, size: u64, proof: &[Hash], root: &Hash) -> bool {
+    if index >= size {
+        return false;
+    }
+
+    let (mut fn_, mut sn) = (index, size - 1);
+    let mut r = *leaf;
+    for p in proof {
+        if sn == 0 {
+            return false;
+        }
+        if fn_ & 1 == 1 || fn_ == sn {
+            r = node_hash(p, &r);
+            while fn_ & 1 == 0 && fn_ != 0 {
+                fn_ >>= 1;
+                sn >>= 1;
+            }
+        } else {
+            r = node_hash(&r, p);
+        }
+        fn_ >>= 1;
+        sn >>= 1;
+    }
+
+    sn == 0 && r == *root
+}
+
+/// Check that the tree of `first` entries with `first_root` is a prefix of the one of `second`
+/// entries with `second_root` (Section 2.1.4.2 of RFC 9162)
+pub fn verify_consistency(
+    first: u64,
+    second: u64,
+    first_root: &Hash,
+    second_root: &Hash,
+    proof: &[Hash],
+) -> bool {
+    if first > second {
+        return false;
+    }
+    if first == 0 {
+        return proof.is_empty();
+    }
+    if first == second {
+        return proof.is_empty() && first_root == second_root;
+    }
+
+    let mut path = proof.to_vec();
+    if first.is_power_of_two() {
+        path.insert(0, *first_root);
+    }
+    let (mut fn_, mut sn) = (first - 1, second - 1);
+    while fn_ & 1 == 1 {
+        fn_ >>= 1;
+        sn >>= 1;
+    }
+
+    let (mut fr, mut sr) = match path.first() {
+        Some(start) => (*start, *start),
+        None => return false,
+    };
+    for c in path.iter().skip(1) {
+        if sn == 0 {
+            return false;
+        }
+        if fn_ & 1 == 1 || fn_ == sn {
+            fr = node_hash(c, &fr);
+            sr = node_hash(c, &sr);
+            while fn_ & 1 == 0 && fn_ != 0 {
+                fn_ >>= 1;
+                sn >>= 1;
+            }
+        } else {
+            sr = node_hash(&sr, c);
+        }
+        fn_ >>= 1;
+        sn >>= 1;
+    }
+
+    sn == 0 && fr == *first_root && sr == *second_root
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn tree(size: u64) -> Tree {
+        let mut tree = Tree::default();
+        for i in 0..size {
+            tree.push(&LogEntry {
+                kind: EntryKind::TagSignature,
+                digest: format!("{:064x}", i),
+                signers: vec!["1".to_owned(), "2".to_owned()],
+                timestamp: i,
+            });
+        }
+        tree
+    }
+
+    #[test]
+    fn empty_root() {
+        assert_eq!(
+            to_hex(&Tree::default().root(0).unwrap()),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+
+    #[test]
+    fn inclusion_proofs() {
+        let tree = tree(9);
+
+        for size in 1..=9 {
+            let root = tree.root(size).unwrap();
+            for index in 0..size {
+                let proof = tree.inclusion(index, size).unwrap();
+                let leaf = tree.leaves[index as usize];
+                assert!(verify_inclusion(&leaf, index, size, &proof, &root));
+
+                // Neither another leaf nor another place in the tree check out
+                let other = tree.leaves[((index + 1) % 9) as usize];
+                assert!(!verify_inclusion(&other, index, size, &proof, &root));
+                if size > 1 {
+                    let moved = (index + 1) % size;
+                    assert!(!verify_inclusion(&leaf, moved, size, &proof, &root));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn consistency_proofs() {
+        let tree = tree(9);
+
+        for second in 1..=9 {
+            let second_root = tree.root(second).unwrap();
+            for first in 1..=second {
+                let first_root = tree.root(first).unwrap();
+                let proof = tree.consistency(first, second).unwrap();
+                assert!(verify_consistency(
+                    first,
+                    second,
+                    &first_root,
+                    &second_root,
+                    &proof
+                ));
+
+                // A log that rewrote its history does not
+                let rewritten = node_hash(&first_root, &first_root);
+                assert!(!verify_consistency(
+                    first,
+                    second,
+                    &rewritten,
+                    &second_root,
+                    &proof
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn tree_head_signature() {
+        let log_key = Identity::generate();
+        let root = tree(3).root(3).unwrap();
+        let head = SignedTreeHead::new("project", 3, &root, 1_700_000_000, &log_key);
+        assert!(head.verify());
+        assert_eq!(head.root(), Some(root));
+
+        let shrunk = SignedTreeHead { size: 2, ..head };
+        assert!(!shrunk.verify());
+    }
+}
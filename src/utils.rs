@@ -3,10 +3,11 @@ use std::fmt::Display;
 use std::fs;
 use std::io;
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::time;
 
 use crate::errors::Result;
+use crate::provenance::Statement;
 pub const DIT_LOG: &str = "\x1B[31m[dit]\x1B[0m\t";
 /// Executing commands instantly when the operation is done does not allow the 
 /// user to read through the results
@@ -16,15 +17,109 @@ pub const USER_SLEEP: std::time::Duration = std::time::Duration::from_secs(1);
 pub struct Server {
     pub address: String,
     pub port: u16,
+    pub tls: Option<TlsConfig>,
+    /// File holding the token identifying this participant to servers that require one,
+    /// relative to the repository root and kept out of version control
+    pub token_file: Option<PathBuf>,
+    /// Public key the server signs the heads of its transparency log with. Without it, the key
+    /// seen first is trusted from then on.
+    pub log_key: Option<String>,
+}
+
+/// Talk to the server over HTTPS, as set up by a `[server.tls]` table. All of the paths are
+/// resolved against the repository root, and point to PEM files.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TlsConfig {
+    /// CAs trusted to issue the certificate of the server, besides the ones of the system
+    pub ca_bundle: Option<PathBuf>,
+    /// The self-signed certificate the server has to present, trusted instead of any CA
+    pub pinned_certificate: Option<PathBuf>,
+    /// Certificate (and its key) identifying this participant to servers that require one
+    pub client_certificate: Option<PathBuf>,
+    pub client_key: Option<PathBuf>,
+}
+
+/// Transports other than the HTTP server, selected with a `[channel]` table in the config.
+/// Without one, `dit` talks to the server configured under `[server]`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ChannelConfig {
+    /// Messages are pushed as refs to a shared remote (a remote name or URL), which is
+    /// polled every `poll_interval` seconds
+    Git {
+        remote: String,
+        poll_interval: Option<u64>,
+    },
+    /// Messages are written as files to a directory shared by all participants (a network
+    /// share, or a local directory when testing), which is polled every `poll_interval_ms`
+    /// milliseconds. Relative paths are resolved against the repository root.
+    Directory {
+        path: PathBuf,
+        poll_interval_ms: Option<u64>,
+    },
+    /// Messages are kept by `dit relay --stdio`, started by running `command` (typically
+    /// `["ssh", "<host>", "dit", "relay", "--stdio", "--path", "<dir>"]`)
+    Relay { command: Vec<String> },
+    /// Messages are kept by `dit relay --socket`, listening on the Unix socket at `path`
+    Socket { path: PathBuf },
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Config {
     pub project: String,
-    pub server: Server,
-    pub participants: u16,
+    pub server: Option<Server>,
+    pub channel: Option<ChannelConfig>,
+    pub participants: Participants,
     pub threshold: u16,
-    pub user: Option<User>
+    pub user: Option<User>,
+    pub timeouts: Option<TimeoutConfig>,
+    /// Set to `false` for a project whose server keeps no transparency log, which leaves
+    /// `dit verify-tag` to verify tags without looking them up in it
+    pub transparency_log: Option<bool>,
+}
+
+impl Config {
+    /// Whether tags have to be in the transparency log of the server to pass verification,
+    /// which they do unless the project opts out
+    pub fn checks_log(&self) -> bool {
+        self.transparency_log != Some(false)
+    }
+
+    /// Members whose identity keys the protocol messages have to be signed with, in the order
+    /// of the party numbers they generate their key shares under
+    pub fn members(&self) -> Option<&[Member]> {
+        match &self.participants {
+            Participants::Roster(members) => Some(members),
+            Participants::Count(_) => None,
+        }
+    }
+}
+
+/// Either a bare count of participants, or the roster of the project under
+/// `[[participants]]`, which binds every key share to a known person: the member listed
+/// `n`-th always generates share `n`, and only members sign.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum Participants {
+    Count(u16),
+    Roster(Vec<Member>),
+}
+
+impl Participants {
+    pub fn count(&self) -> u16 {
+        match self {
+            Participants::Count(count) => *count,
+            Participants::Roster(members) => members.len() as u16,
+        }
+    }
+}
+
+/// Limits on waiting for the other participants under `[timeouts]`, in seconds. A round
+/// limit of 0 turns off the default of the HTTP and directory channels.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TimeoutConfig {
+    pub round: Option<u64>,
+    pub operation: Option<u64>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -33,6 +128,15 @@ pub struct User {
     pub email: String,
 }
 
+/// A participant listed under `[[participants]]`, whose messages are only accepted when
+/// signed with the identity key printed by `dit identity`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Member {
+    pub name: String,
+    pub email: String,
+    pub identity: String,
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
 pub enum Operation {
     Idle,
@@ -56,6 +160,11 @@ pub enum Operation {
         email: String,
         epoch: u64,
     },
+    SignStatement {
+        participants: u16,
+        threshold: u16,
+        statement: Statement,
+    },
     Blame {},
 }
 
@@ -93,6 +202,28 @@ impl Display for Operation {
                 "{} <{}> started signing the key with a {}-{} threshold at {}",
                 leader, email, participants, threshold, epoch
             ),
+            Operation::SignStatement {
+                participants,
+                threshold,
+                statement,
+            } => {
+                writeln!(
+                    fmt,
+                    "Signing release provenance with a {}-{} threshold:",
+                    participants, threshold
+                )?;
+                for subject in statement.subject.iter() {
+                    for (algorithm, digest) in subject.digest.iter() {
+                        writeln!(fmt, "\t{} {}:{}", subject.name, algorithm, digest)?;
+                    }
+                }
+                write!(
+                    fmt,
+                    "built from {} by {}",
+                    statement.predicate.build_definition.external_parameters.source.uri,
+                    statement.predicate.run_details.builder.id
+                )
+            }
             Operation::Blame {} => write!(fmt, "Protocol has been terminated"),
         }
     }
@@ -165,3 +296,18 @@ pub fn get_user_choice(prompt: &str, choices: &[&str]) -> Result<usize> {
     }
 }
 
+pub fn to_hex(buffer: &[u8]) -> String {
+    buffer.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            let pair = std::str::from_utf8(pair)
+                .ok()
+                .filter(|pair| pair.len() == 2)?;
+            u8::from_str_radix(pair, 16).ok()
+        })
+        .collect()
+}
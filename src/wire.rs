@@ -0,0 +1,307 @@
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::comm::Channel;
+use crate::echo;
+use crate::errors::ProtocolError;
+
+/// Version of the wire format this build writes, covering both the frames exchanged between
+/// participants and the requests to `dit-server`. Builds only work together when they run the
+/// same version, which participants and the server check before anything else instead of
+/// settling on a common one. Version 2 namespaces the messages stored on the server by the
+/// session it issues for each operation, version 3 queues operations, version 4 binds signups
+/// to the roster, and version 5 has the server answer refused requests with a status code and
+/// the reason.
+pub const VERSION: u8 = 5;
+
+/// Every frame starts with these, which stay the same across versions so that any build can
+/// tell which version a frame was written in
+const HEADER_LEN: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    /// Sent before anything else, for the other parties to check the version in the header
+    Hello,
+    Broadcast,
+    P2p,
+}
+
+impl Kind {
+    fn to_byte(self) -> u8 {
+        match self {
+            Kind::Hello => 0,
+            Kind::Broadcast => 1,
+            Kind::P2p => 2,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Option<Kind> {
+        match byte {
+            0 => Some(Kind::Hello),
+            1 => Some(Kind::Broadcast),
+            2 => Some(Kind::P2p),
+            _ => None,
+        }
+    }
+}
+
+/// A protocol message as it travels over the channel. In binary, it is laid out as
+///
+/// `version: u8 | kind: u8 | sender: u16 | session: u16 + bytes | round: u16 + bytes | payload`
+///
+/// with integers in big endian and strings prefixed by their length. Channels carry it
+/// base64-encoded.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    pub version: u8,
+    pub kind: Kind,
+    pub session: String,
+    pub sender: u16,
+    pub round: String,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(
+            HEADER_LEN + 4 + self.session.len() + self.round.len() + self.payload.len(),
+        );
+        bytes.push(self.version);
+        bytes.push(self.kind.to_byte());
+        bytes.extend_from_slice(&self.sender.to_be_bytes());
+        for field in [&self.session, &self.round].iter() {
+            bytes.extend_from_slice(&(field.len() as u16).to_be_bytes());
+            bytes.extend_from_slice(field.as_bytes());
+        }
+        bytes.extend_from_slice(&self.payload);
+
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Frame> {
+        if bytes.len() < HEADER_LEN {
+            return None;
+        }
+        let mut rest = &bytes[HEADER_LEN..];
+        let mut string = || {
+            if rest.len() < 2 {
+                return None;
+            }
+            let len = u16::from_be_bytes([rest[0], rest[1]]) as usize;
+            let field = rest.get(2..2 + len)?;
+            rest = &rest[2 + len..];
+
+            String::from_utf8(field.to_vec()).ok()
+        };
+        let session = string()?;
+        let round = string()?;
+
+        Some(Frame {
+            version: bytes[0],
+            kind: Kind::from_byte(bytes[1])?,
+            session,
+            sender: u16::from_be_bytes([bytes[2], bytes[3]]),
+            round,
+            payload: rest.to_vec(),
+        })
+    }
+
+    pub fn encode(&self) -> String {
+        base64::encode(self.to_bytes())
+    }
+
+    pub fn decode(data: &str) -> Option<Frame> {
+        Frame::from_bytes(&base64::decode(data).ok()?)
+    }
+}
+
+/// Payload of a frame carrying `value`
+pub fn to_payload<T: Serialize>(value: &T) -> Vec<u8> {
+    serde_cbor::to_vec(value).unwrap()
+}
+
+pub fn from_payload<T: DeserializeOwned>(payload: &[u8]) -> Option<T> {
+    serde_cbor::from_slice(payload).ok()
+}
+
+/// The parties among `versions` that write another version of the wire format than this build
+pub fn mismatched(versions: &[(u16, u8)]) -> Vec<u16> {
+    versions
+        .iter()
+        .filter(|(_, version)| *version != VERSION)
+        .map(|(party, _)| *party)
+        .collect()
+}
+
+/// The framed messages of one run of a protocol, between parties that all run this version
+pub struct Session<'a, C: Channel + ?Sized> {
+    channel: &'a C,
+    id: String,
+    party: u16,
+    n: u16,
+}
+
+impl<'a, C: Channel + ?Sized> Session<'a, C> {
+    /// Exchange hellos with the other `n - 1` parties, in the round `"{prefix}-hello"`, and
+    /// make sure all of them write the version of this build
+    pub fn join(
+        channel: &'a C,
+        party: u16,
+        n: u16,
+        prefix: &str,
+    ) -> Result<Session<'a, C>, ProtocolError> {
+        let session = Session {
+            channel,
+            id: channel.session().unwrap_or_default(),
+            party,
+            n,
+        };
+
+        let round = format!("{}-hello", prefix);
+        let hello = session.frame(Kind::Hello, &round, Vec::new());
+        channel.broadcast(party, &round, hello)?;
+        let hellos = channel.poll_for_broadcasts(party, n, &round)?;
+
+        let mut versions = Vec::with_capacity(hellos.len());
+        for (sender, hello) in session.senders().zip(hellos.iter()) {
+            let frame = Frame::decode(hello).ok_or_else(|| session.malformed(sender, &round))?;
+            versions.push((sender, frame.version));
+        }
+
+        let parties = mismatched(&versions);
+        if !parties.is_empty() {
+            let _ = channel.blame();
+            return Err(ProtocolError::Version(parties));
+        }
+        for (sender, hello) in session.senders().zip(hellos.iter()) {
+            session.check(sender, &round, Kind::Hello, hello)?;
+        }
+        Ok(session)
+    }
+
+    /// Broadcast `value` like `echo::broadcast`, and decode what the others broadcast
+    pub fn broadcast<T>(&self, round: &str, value: &T) -> Result<Vec<T>, ProtocolError>
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        let data = self.frame(Kind::Broadcast, round, to_payload(value));
+        let received = echo::broadcast(self.channel, self.party, self.n, round, data)?;
+
+        self.senders()
+            .zip(received.iter())
+            .map(|(sender, data)| {
+                let frame = self.check(sender, round, Kind::Broadcast, data)?;
+                self.read(sender, round, &frame.payload)
+            })
+            .collect()
+    }
+
+    pub fn send_p2p(&self, to: u16, round: &str, payload: Vec<u8>) -> Result<(), ProtocolError> {
+        let data = self.frame(Kind::P2p, round, payload);
+        self.channel.send_p2p(self.party, to, round, data)
+    }
+
+    /// Payloads of the messages the other parties sent us in `round`, ordered by party number
+    pub fn poll_for_p2p(&self, round: &str) -> Result<Vec<Vec<u8>>, ProtocolError> {
+        let received = self.channel.poll_for_p2p(self.party, self.n, round)?;
+
+        self.senders()
+            .zip(received.iter())
+            .map(|(sender, data)| Ok(self.check(sender, round, Kind::P2p, data)?.payload))
+            .collect()
+    }
+
+    /// Decode a payload `sender` sent in `round`
+    pub fn read<T: DeserializeOwned>(
+        &self,
+        sender: u16,
+        round: &str,
+        payload: &[u8],
+    ) -> Result<T, ProtocolError> {
+        from_payload(payload).ok_or_else(|| self.malformed(sender, round))
+    }
+
+    fn senders(&self) -> impl Iterator<Item = u16> {
+        let party = self.party;
+        (1..=self.n).filter(move |&i| i != party)
+    }
+
+    fn frame(&self, kind: Kind, round: &str, payload: Vec<u8>) -> String {
+        Frame {
+            version: VERSION,
+            kind,
+            session: self.id.clone(),
+            sender: self.party,
+            round: round.to_owned(),
+            payload,
+        }
+        .encode()
+    }
+
+    /// Decode what `sender` sent in `round`, which has to be a frame of this version
+    /// that belongs to this very message
+    fn check(
+        &self,
+        sender: u16,
+        round: &str,
+        kind: Kind,
+        data: &str,
+    ) -> Result<Frame, ProtocolError> {
+        let frame = Frame::decode(data).ok_or_else(|| self.malformed(sender, round))?;
+
+        if frame.version != VERSION {
+            let _ = self.channel.blame();
+            return Err(ProtocolError::Version(vec![sender]));
+        }
+        if frame.kind != kind
+            || frame.session != self.id
+            || frame.sender != sender
+            || frame.round != round
+        {
+            return Err(self.malformed(sender, round));
+        }
+
+        Ok(frame)
+    }
+
+    fn malformed(&self, sender: u16, round: &str) -> ProtocolError {
+        let _ = self.channel.blame();
+        ProtocolError::Malformed(sender, round.to_owned())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn frames_round_trip() {
+        let frame = Frame {
+            version: VERSION,
+            kind: Kind::P2p,
+            session: "1234-5".into(),
+            sender: 3,
+            round: "dkg-round3".into(),
+            payload: to_payload(&(7u16, "share".to_owned())),
+        };
+
+        let decoded = Frame::decode(&frame.encode()).unwrap();
+        assert_eq!(decoded, frame);
+        assert_eq!(
+            from_payload::<(u16, String)>(&decoded.payload),
+            Some((7, "share".to_owned()))
+        );
+
+        // Truncated right before the round
+        assert_eq!(Frame::from_bytes(&frame.to_bytes()[..12]), None);
+        assert_eq!(Frame::decode("{\"not\": \"a frame\"}"), None);
+    }
+
+    #[test]
+    fn singles_out_the_parties_of_other_versions() {
+        assert_eq!(mismatched(&[(2, VERSION), (3, VERSION)]), Vec::<u16>::new());
+        assert_eq!(
+            mismatched(&[(1, VERSION - 1), (3, VERSION), (4, VERSION + 1)]),
+            vec![1, 4]
+        );
+    }
+}